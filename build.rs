@@ -1,4 +1,20 @@
+use std::{env, fs, path::Path};
+
+/// Optional gesture templates recorded with the `template` host tool.
+const TEMPLATES_FILE: &str = "gesture_templates.bin";
+
 fn main() {
     println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
     println!("cargo:rustc-link-arg-bins=-Trom_functions.x");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Embed the templates if present, an empty file otherwise. The script
+    // reruns on every build while there are none, so the copy is only
+    // written when it changes, not to rebuild the firmware each time
+    println!("cargo:rerun-if-changed={TEMPLATES_FILE}");
+    let templates = fs::read(TEMPLATES_FILE).unwrap_or_default();
+    let embedded = Path::new(&env::var("OUT_DIR").unwrap()).join(TEMPLATES_FILE);
+    if fs::read(&embedded).ok().as_ref() != Some(&templates) {
+        fs::write(embedded, templates).unwrap();
+    }
 }
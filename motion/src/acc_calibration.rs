@@ -0,0 +1,280 @@
+//! Six-position calibration of the accelerometer.
+//!
+//! The device is laid still with each of its axes pointing up and then down,
+//! so that the mean readings of each pose stand for ±1 g along one axis. The
+//! full model of [`ImuCalibration`], `misalignment * ((a - offset) *
+//! sensitivity)`, is then the affine map taking those readings closest to the
+//! gravity vectors, fitted by least squares.
+
+use imu_fusion::FusionVector;
+
+use crate::{
+    calibration::{ImuCalibration, Offset, Sensitivity, Transform},
+    linalg::solve,
+};
+
+/// Readings averaged in a pose: half a second at the firmware's rate.
+const STILL_SAMPLES: u32 = 100;
+
+/// Largest rotation rate, in degrees/s, of a device lying still.
+const MAX_STILL_RATE: f32 = 5.0;
+
+/// Largest difference between readings of a device lying still, in g.
+const MAX_STILL_SPREAD: f32 = 0.05;
+
+/// Smallest cosine between the mean reading and the axis of the pose asked
+/// for: the device must lie within 25° of it.
+const MIN_POSE_COSINE: f32 = 0.9;
+
+/// Largest RMS error of the fitted model over the six poses, in g.
+const MAX_RESIDUAL: f32 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pose {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    ZUp,
+    ZDown,
+}
+
+/// The poses, in the order they are asked for.
+pub const POSES: [Pose; 6] = [Pose::XUp, Pose::XDown, Pose::YUp, Pose::YDown, Pose::ZUp, Pose::ZDown];
+
+impl Pose {
+    /// The reading of an ideal accelerometer in this pose, in g.
+    pub fn gravity(&self) -> [f32; 3] {
+        match self {
+            Pose::XUp => [1.0, 0.0, 0.0],
+            Pose::XDown => [-1.0, 0.0, 0.0],
+            Pose::YUp => [0.0, 1.0, 0.0],
+            Pose::YDown => [0.0, -1.0, 0.0],
+            Pose::ZUp => [0.0, 0.0, 1.0],
+            Pose::ZDown => [0.0, 0.0, -1.0],
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Pose::XUp => "x-up",
+            Pose::XDown => "x-down",
+            Pose::YUp => "y-up",
+            Pose::YDown => "y-down",
+            Pose::ZUp => "z-up",
+            Pose::ZDown => "z-down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccFitError {
+    /// Not every pose was captured yet.
+    Incomplete,
+    /// The readings do not fit an affine model, e.g. two poses were the same.
+    Degenerate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccFit {
+    /// With a unit diagonal.
+    pub misalignment: Transform,
+    pub sensitivity: Sensitivity,
+    /// In g.
+    pub offset: Offset,
+    /// RMS error of the calibrated readings over the six poses, in g.
+    pub residual: f32,
+}
+
+impl AccFit {
+    /// Whether the poses were held well enough for the fit to be trusted.
+    pub fn is_good(&self) -> bool {
+        self.residual <= MAX_RESIDUAL
+    }
+
+    pub fn apply_to(&self, calibration: &mut ImuCalibration) {
+        calibration.acc_misalignment = self.misalignment;
+        calibration.acc_sensitivity = self.sensitivity;
+        calibration.acc_offset = self.offset;
+    }
+}
+
+/// Fits the accelerometer model to the mean readings of each of [`POSES`].
+pub fn solve_six_position(means: &[[f32; 3]; 6]) -> Result<AccFit, AccFitError> {
+    // Each row of `C a + b = g` separately, with C = misalignment *
+    // diag(sensitivity) and b = -C offset
+    let mut c = [[0.0f64; 3]; 3];
+    let mut b = [0.0f64; 3];
+    for r in 0..3 {
+        let mut ata = [[0.0f64; 4]; 4];
+        let mut atg = [0.0f64; 4];
+        for (mean, pose) in means.iter().zip(POSES) {
+            let row = [mean[0] as f64, mean[1] as f64, mean[2] as f64, 1.0];
+            for i in 0..4 {
+                for j in 0..4 {
+                    ata[i][j] += row[i] * row[j];
+                }
+                atg[i] += row[i] * pose.gravity()[r] as f64;
+            }
+        }
+        let [x, y, z, offset] = solve(ata, atg).ok_or(AccFitError::Degenerate)?;
+        c[r] = [x, y, z];
+        b[r] = offset;
+    }
+    let offset = solve(c, b.map(|v| -v)).ok_or(AccFitError::Degenerate)?;
+    let sensitivity = [c[0][0], c[1][1], c[2][2]];
+    if sensitivity.iter().any(|&s| s <= 0.0) {
+        return Err(AccFitError::Degenerate);
+    }
+
+    let mut squared_error = 0.0;
+    for (mean, pose) in means.iter().zip(POSES) {
+        for (r, row) in c.iter().enumerate() {
+            let calibrated = (0..3).map(|k| row[k] * mean[k] as f64).sum::<f64>() + b[r];
+            let error = calibrated - pose.gravity()[r] as f64;
+            squared_error += error * error;
+        }
+    }
+    Ok(AccFit {
+        misalignment: Transform(c.map(|row| core::array::from_fn(|k| (row[k] / sensitivity[k]) as f32))),
+        sensitivity: Sensitivity(sensitivity.map(|s| s as f32)),
+        offset: Offset(offset.map(|o| o as f32)),
+        residual: libm::sqrt(squared_error / 6.0) as f32,
+    })
+}
+
+/// Walks through [`POSES`], capturing the mean reading of each once the
+/// device lies still in it.
+#[derive(Debug, Clone, Default)]
+pub struct SixPositionCalibrator {
+    /// Index of the pose waited for.
+    pose: usize,
+    means: [[f32; 3]; 6],
+    first: [f32; 3],
+    sum: [f32; 3],
+    count: u32,
+}
+
+impl SixPositionCalibrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The pose waited for, `None` once all were captured.
+    pub fn pose(&self) -> Option<Pose> {
+        POSES.get(self.pose).copied()
+    }
+
+    /// Takes a reading of the accelerometer, in g, and of the gyroscope, in
+    /// degrees/s with its offset removed. Returns the pose just captured.
+    pub fn add(&mut self, acc: FusionVector, gyr: FusionVector) -> Option<Pose> {
+        let pose = self.pose()?;
+        let acc = [acc.x, acc.y, acc.z];
+        let rate = libm::sqrtf(gyr.x * gyr.x + gyr.y * gyr.y + gyr.z * gyr.z);
+        let moved = self.count > 0 && (0..3).any(|i| (acc[i] - self.first[i]).abs() > MAX_STILL_SPREAD);
+        if rate > MAX_STILL_RATE || moved {
+            self.count = 0;
+            return None;
+        }
+        if self.count == 0 {
+            self.first = acc;
+            self.sum = [0.0; 3];
+        }
+        for (sum, value) in self.sum.iter_mut().zip(acc) {
+            *sum += value;
+        }
+        self.count += 1;
+        if self.count < STILL_SAMPLES {
+            return None;
+        }
+
+        self.count = 0;
+        let mean = self.sum.map(|s| s / STILL_SAMPLES as f32);
+        let norm = libm::sqrtf(mean[0] * mean[0] + mean[1] * mean[1] + mean[2] * mean[2]);
+        let gravity = pose.gravity();
+        let cosine = (0..3).map(|i| mean[i] * gravity[i]).sum::<f32>() / norm;
+        if cosine < MIN_POSE_COSINE {
+            // Still in another pose, or on the way to this one
+            return None;
+        }
+        self.means[self.pose] = mean;
+        self.pose += 1;
+        Some(pose)
+    }
+
+    pub fn solve(&self) -> Result<AccFit, AccFitError> {
+        if self.pose().is_some() {
+            return Err(AccFitError::Incomplete);
+        }
+        solve_six_position(&self.means)
+    }
+}
+
+#[cfg(test)]
+fn raw_reading(pose: Pose) -> [f32; 3] {
+    // An accelerometer with 3% and -2% gain errors, skewed axes and offsets
+    // of tens of mg: a = C⁻¹ g + offset
+    let c = [[1.03, 0.01, -0.005], [0.0, 0.98, 0.012], [0.0, 0.0, 1.0]];
+    let g = pose.gravity().map(|v| v as f64);
+    let a = solve(c, g).unwrap();
+    [a[0] as f32 + 0.04, a[1] as f32 - 0.025, a[2] as f32 + 0.06]
+}
+
+#[test]
+fn test_six_position_solver() {
+    let fit = solve_six_position(&POSES.map(raw_reading)).unwrap();
+    assert!(fit.residual < 1e-4, "{fit:?}");
+    assert!(fit.is_good());
+    let expected_offset = [0.04, -0.025, 0.06];
+    assert!(fit.offset.0.iter().zip(expected_offset).all(|(o, e)| (o - e).abs() < 1e-4), "{fit:?}");
+    assert!(fit.sensitivity.0.iter().zip([1.03, 0.98, 1.0]).all(|(s, e)| (s - e).abs() < 1e-4), "{fit:?}");
+    assert!((fit.misalignment.0[0][1] - 0.01 / 0.98).abs() < 1e-4, "{fit:?}");
+
+    // Calibrated, every pose reads 1 g along its axis
+    let mut calibration = ImuCalibration::default();
+    fit.apply_to(&mut calibration);
+    for pose in POSES {
+        let [x, y, z] = raw_reading(pose);
+        let calibrated = calibration.correct_acc(FusionVector::new(x, y, z));
+        let gravity = pose.gravity();
+        assert!((calibrated.x - gravity[0]).abs() < 1e-3 && (calibrated.y - gravity[1]).abs() < 1e-3
+                && (calibrated.z - gravity[2]).abs() < 1e-3);
+    }
+
+    // Laid twice the same way
+    let mut means = POSES.map(raw_reading);
+    means[1] = means[0];
+    assert!(!solve_six_position(&means).is_ok_and(|fit| fit.is_good()));
+    // Never turned to Z
+    let mut means = POSES.map(raw_reading);
+    means[4] = means[0];
+    means[5] = means[1];
+    assert_eq!(solve_six_position(&means), Err(AccFitError::Degenerate));
+}
+
+#[test]
+fn test_guided_capture() {
+    let still = FusionVector::zero();
+    let mut calibrator = SixPositionCalibrator::new();
+    assert_eq!(calibrator.solve(), Err(AccFitError::Incomplete));
+
+    // Still, but not yet turned over to X up
+    let [x, y, z] = raw_reading(Pose::ZUp);
+    for _ in 0..2 * STILL_SAMPLES {
+        assert_eq!(calibrator.add(FusionVector::new(x, y, z), still), None);
+    }
+    assert_eq!(calibrator.pose(), Some(Pose::XUp));
+
+    for pose in POSES {
+        let [x, y, z] = raw_reading(pose);
+        // Being turned over
+        for i in 0..50 {
+            let wobble = if i % 2 == 0 { 0.2 } else { -0.2 };
+            calibrator.add(FusionVector::new(x + wobble, y, z), FusionVector::new(0.0, 30.0, 0.0));
+        }
+        let captured: Option<Pose> = (0..STILL_SAMPLES).find_map(|_| calibrator.add(FusionVector::new(x, y, z), still));
+        assert_eq!(captured, Some(pose));
+    }
+    assert_eq!(calibrator.pose(), None);
+    assert!(calibrator.solve().unwrap().is_good());
+}
@@ -0,0 +1,181 @@
+//! Initial attitude of the device, out of the accelerometer and magnetometer
+//! readings taken while it lies still.
+//!
+//! Still, the accelerometer reads 1 g straight up, and the magnetometer reads
+//! the geomagnetic field, whose horizontal part points north. Together they
+//! fix the earth axes in the frame of the sensor (the TRIAD method), so that
+//! tracking starts from the actual tilt and heading instead of from level and
+//! facing north.
+
+use imu_fusion::{FusionQuaternion, FusionVector};
+
+use crate::imu_tracker::Convention;
+
+/// Largest difference between accelerometer readings of a device lying
+/// still, in g.
+const MAX_STILL_SPREAD: f32 = 0.05;
+
+/// Smallest norm, as a fraction of the readings, of the horizontal part of a
+/// vector for it to tell a heading.
+const MIN_HORIZONTAL: f32 = 0.1;
+
+/// Averages the readings taken while the device lies still.
+#[derive(Debug, Clone, Default)]
+pub struct AttitudeAligner {
+    first: [f32; 3],
+    acc: [f32; 3],
+    mag: [f32; 3],
+    count: u32,
+    moved: bool,
+}
+
+impl AttitudeAligner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes an accelerometer reading, in g, and a magnetometer one, in the
+    /// accelerometer frame (see [`crate::imu_tracker::align_magnetometer`]).
+    pub fn add(&mut self, acc: FusionVector, mag: FusionVector) {
+        let acc = [acc.x, acc.y, acc.z];
+        if self.count == 0 {
+            self.first = acc;
+        }
+        self.moved |= (0..3).any(|i| (acc[i] - self.first[i]).abs() > MAX_STILL_SPREAD);
+        for (sum, value) in self.acc.iter_mut().zip(acc) {
+            *sum += value;
+        }
+        for (sum, value) in self.mag.iter_mut().zip([mag.x, mag.y, mag.z]) {
+            *sum += value;
+        }
+        self.count += 1;
+    }
+
+    /// The mean accelerometer and magnetometer readings, unless there were
+    /// none or the device moved while they were taken.
+    pub fn mean(&self) -> Option<(FusionVector, FusionVector)> {
+        if self.count == 0 || self.moved {
+            return None;
+        }
+        let [ax, ay, az] = self.acc.map(|s| s / self.count as f32);
+        let [mx, my, mz] = self.mag.map(|s| s / self.count as f32);
+        Some((FusionVector::new(ax, ay, az), FusionVector::new(mx, my, mz)))
+    }
+}
+
+/// The orientation of a device lying still, from its accelerometer reading
+/// `acc` and magnetometer reading `mag`, both corrected. Without a usable
+/// magnetometer reading, e.g. a zero one, the heading is that of the X axis.
+/// `None` when the accelerometer reads nothing.
+pub fn attitude(acc: FusionVector, mag: FusionVector, convention: Convention) -> Option<FusionQuaternion> {
+    let up = normalized([acc.x, acc.y, acc.z])?;
+    // West is up × north, for the north of any vector but the vertical
+    let west = [[mag.x, mag.y, mag.z], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]].into_iter()
+        .find_map(|v| {
+            let w = cross(up, v);
+            (norm(w) >= MIN_HORIZONTAL * norm(v)).then(|| normalized(w)).flatten()
+        })?;
+    let north = cross(west, up);
+
+    // Rows are the earth axes in the sensor frame: earth = R sensor
+    let neg = |v: [f32; 3]| v.map(|c| -c);
+    let rows = match convention {
+        Convention::Nwu => [north, west, up],
+        Convention::Enu => [neg(west), north, up],
+        Convention::Ned => [north, neg(west), neg(up)],
+    };
+    Some(from_rotation(rows))
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn norm(v: [f32; 3]) -> f32 {
+    libm::sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2])
+}
+
+fn normalized(v: [f32; 3]) -> Option<[f32; 3]> {
+    let n = norm(v);
+    (n > 0.0).then(|| v.map(|c| c / n))
+}
+
+/// The unit quaternion of a rotation matrix, out of its largest diagonal term
+/// for accuracy.
+fn from_rotation(r: [[f32; 3]; 3]) -> FusionQuaternion {
+    let trace = r[0][0] + r[1][1] + r[2][2];
+    let (w, x, y, z) = if trace > 0.0 {
+        let s = 2.0 * libm::sqrtf(1.0 + trace);
+        (0.25 * s, (r[2][1] - r[1][2]) / s, (r[0][2] - r[2][0]) / s, (r[1][0] - r[0][1]) / s)
+    } else if r[0][0] > r[1][1] && r[0][0] > r[2][2] {
+        let s = 2.0 * libm::sqrtf(1.0 + r[0][0] - r[1][1] - r[2][2]);
+        ((r[2][1] - r[1][2]) / s, 0.25 * s, (r[0][1] + r[1][0]) / s, (r[0][2] + r[2][0]) / s)
+    } else if r[1][1] > r[2][2] {
+        let s = 2.0 * libm::sqrtf(1.0 + r[1][1] - r[0][0] - r[2][2]);
+        ((r[0][2] - r[2][0]) / s, (r[0][1] + r[1][0]) / s, 0.25 * s, (r[1][2] + r[2][1]) / s)
+    } else {
+        let s = 2.0 * libm::sqrtf(1.0 + r[2][2] - r[0][0] - r[1][1]);
+        ((r[1][0] - r[0][1]) / s, (r[0][2] + r[2][0]) / s, (r[1][2] + r[2][1]) / s, 0.25 * s)
+    };
+    FusionQuaternion { w, x, y, z }
+}
+
+#[cfg(test)]
+fn close(v: FusionVector, expected: [f32; 3]) -> bool {
+    let tolerance = 1e-3 * norm(expected).max(1.0);
+    (v.x - expected[0]).abs() < tolerance && (v.y - expected[1]).abs() < tolerance && (v.z - expected[2]).abs() < tolerance
+}
+
+#[test]
+fn test_attitude() {
+    use core::f32::consts::FRAC_PI_6;
+
+    use crate::imu_tracker::rotate;
+
+    // Rolled 40° about X, then turned 30° about the vertical: gravity and a
+    // field pointing north and down, as seen by the sensor
+    let (s, c) = (libm::sinf(0.7), libm::cosf(0.7));
+    let tilted = |v: [f32; 3]| [v[0], c * v[1] + s * v[2], -s * v[1] + c * v[2]];
+    let (sy, cy) = (libm::sinf(FRAC_PI_6), libm::cosf(FRAC_PI_6));
+    let turned = |v: [f32; 3]| [cy * v[0] + sy * v[1], -sy * v[0] + cy * v[1], v[2]];
+    let [ax, ay, az] = tilted(turned([0.0, 0.0, 1.0]));
+    let [mx, my, mz] = tilted(turned([20.0, 0.0, -40.0]));
+    let (acc, mag) = (FusionVector::new(ax, ay, az), FusionVector::new(mx, my, mz));
+
+    let q = attitude(acc, mag, Convention::Nwu).unwrap();
+    assert!(close(rotate(acc, q), [0.0, 0.0, 1.0]));
+    assert!(close(rotate(mag, q), [20.0, 0.0, -40.0]));
+    let q = attitude(acc, mag, Convention::Enu).unwrap();
+    assert!(close(rotate(acc, q), [0.0, 0.0, 1.0]));
+    assert!(close(rotate(mag, q), [0.0, 20.0, -40.0]));
+    let q = attitude(acc, mag, Convention::Ned).unwrap();
+    assert!(close(rotate(acc, q), [0.0, 0.0, -1.0]));
+    assert!(close(rotate(mag, q), [20.0, 0.0, 40.0]));
+
+    // Upside down, which takes the other branches of the conversion
+    let q = attitude(FusionVector::new(0.0, 0.0, -1.0), FusionVector::new(-20.0, 0.0, 40.0), Convention::Nwu).unwrap();
+    assert!(close(rotate(FusionVector::new(0.0, 0.0, -1.0), q), [0.0, 0.0, 1.0]));
+    assert!(close(rotate(FusionVector::new(1.0, 0.0, 0.0), q), [-1.0, 0.0, 0.0]));
+
+    // No magnetometer: the tilt alone, heading where X points
+    let q = attitude(acc, FusionVector::zero(), Convention::Nwu).unwrap();
+    assert!(close(rotate(acc, q), [0.0, 0.0, 1.0]));
+    assert!(rotate(FusionVector::new(1.0, 0.0, 0.0), q).y.abs() < 1e-3);
+    assert!(attitude(FusionVector::zero(), mag, Convention::Nwu).is_none());
+}
+
+#[test]
+fn test_aligner() {
+    let mut aligner = AttitudeAligner::new();
+    assert!(aligner.mean().is_none());
+    for i in 0..100 {
+        let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+        aligner.add(FusionVector::new(noise, 0.5, 0.866), FusionVector::new(20.0 + noise, 5.0, -40.0));
+    }
+    let (acc, mag) = aligner.mean().unwrap();
+    assert!(close(acc, [0.0, 0.5, 0.866]) && close(mag, [20.0, 5.0, -40.0]));
+
+    // Picked up halfway through
+    aligner.add(FusionVector::new(0.0, 0.0, 1.0), FusionVector::new(20.0, 5.0, -40.0));
+    assert!(aligner.mean().is_none());
+}
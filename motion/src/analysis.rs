@@ -0,0 +1,635 @@
+use imu_fusion::FusionVector;
+use core::f32::consts::PI;
+// Called through the trait so that host test builds, which link std, run the
+// same approximations as the firmware
+use micromath::F32Ext;
+use alloc::{boxed::Box, collections::VecDeque};
+
+use crate::{denoise::{Denoiser, DenoiserKind}, time::Timestamp};
+
+/// Coarse class of a movement, regardless of its sense.
+///
+/// This is what the wristband used to report before directions were signed,
+/// and its encodings are kept for consumers that only understand these three.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementClass {
+    Horizontal,
+    Vertical,
+    Diagonal,
+}
+
+impl MovementClass {
+
+    pub fn as_digit(&self) -> u8 {
+        match *self {
+            MovementClass::Vertical => 0,
+            MovementClass::Horizontal => 1,
+            MovementClass::Diagonal => 2,
+        }
+    }
+
+    pub fn as_char(&self) -> char {
+        match *self {
+            MovementClass::Vertical => 'V',
+            MovementClass::Horizontal => 'H',
+            MovementClass::Diagonal => 'D',
+        }
+    }
+}
+
+/// Sense of a movement in the earth frame.
+///
+/// Horizontal senses are relative to the reference heading of [`Analysis`]
+/// (north unless set otherwise): `Forward` is along it and `Left` is 90°
+/// counter-clockwise from it, seen from above. Diagonals are named by their
+/// vertical and lateral senses, as when drawing in the air in front of oneself;
+/// one mostly along the heading counts forward as left and back as right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementDirection {
+    Up,
+    Down,
+    Forward,
+    Back,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl MovementDirection {
+
+    pub fn class(&self) -> MovementClass {
+        match *self {
+            MovementDirection::Up | MovementDirection::Down => MovementClass::Vertical,
+            MovementDirection::Forward | MovementDirection::Back
+            | MovementDirection::Left | MovementDirection::Right => MovementClass::Horizontal,
+            MovementDirection::UpLeft | MovementDirection::UpRight
+            | MovementDirection::DownLeft | MovementDirection::DownRight => MovementClass::Diagonal,
+        }
+    }
+
+    /// Digits are laid out as on a numeric keypad facing the user, with `5`
+    /// pushing into it and `0` pulling out of it.
+    pub fn as_digit(&self) -> u8 {
+        match *self {
+            MovementDirection::Up => 8,
+            MovementDirection::Down => 2,
+            MovementDirection::Forward => 5,
+            MovementDirection::Back => 0,
+            MovementDirection::Left => 4,
+            MovementDirection::Right => 6,
+            MovementDirection::UpLeft => 7,
+            MovementDirection::UpRight => 9,
+            MovementDirection::DownLeft => 1,
+            MovementDirection::DownRight => 3,
+        }
+    }
+
+    pub fn from_digit(digit: u8) -> Option<MovementDirection> {
+        let direction = match digit {
+            8 => MovementDirection::Up,
+            2 => MovementDirection::Down,
+            5 => MovementDirection::Forward,
+            0 => MovementDirection::Back,
+            4 => MovementDirection::Left,
+            6 => MovementDirection::Right,
+            7 => MovementDirection::UpLeft,
+            9 => MovementDirection::UpRight,
+            1 => MovementDirection::DownLeft,
+            3 => MovementDirection::DownRight,
+            _ => return None,
+        };
+        Some(direction)
+    }
+
+    /// Diagonals use the letters at the corners of a QWERTY keyboard.
+    pub fn as_char(&self) -> char {
+        match *self {
+            MovementDirection::Up => 'U',
+            MovementDirection::Down => 'D',
+            MovementDirection::Forward => 'F',
+            MovementDirection::Back => 'B',
+            MovementDirection::Left => 'L',
+            MovementDirection::Right => 'R',
+            MovementDirection::UpLeft => 'Q',
+            MovementDirection::UpRight => 'P',
+            MovementDirection::DownLeft => 'Z',
+            MovementDirection::DownRight => 'M',
+        }
+    }
+
+    pub fn opposite(&self) -> MovementDirection {
+        match *self {
+            MovementDirection::Up => MovementDirection::Down,
+            MovementDirection::Down => MovementDirection::Up,
+            MovementDirection::Forward => MovementDirection::Back,
+            MovementDirection::Back => MovementDirection::Forward,
+            MovementDirection::Left => MovementDirection::Right,
+            MovementDirection::Right => MovementDirection::Left,
+            MovementDirection::UpLeft => MovementDirection::DownRight,
+            MovementDirection::DownRight => MovementDirection::UpLeft,
+            MovementDirection::UpRight => MovementDirection::DownLeft,
+            MovementDirection::DownLeft => MovementDirection::UpRight,
+        }
+    }
+}
+
+/// First stage of [`Analysis`]: takes the slowly varying part (bias, gravity
+/// leaking through the attitude estimate) out of the linear acceleration.
+pub trait SmoothingStage {
+    fn add_measurement(&mut self, linear_acceleration: FusionVector) -> FusionVector;
+}
+
+/// Subtracts the mean over a window, a crude high-pass. See
+/// [`FilterBank`](crate::filter::FilterBank) for a sharper one.
+pub struct Smoothing {
+    measurements: VecDeque<FusionVector>,
+    smoothing_window_size: usize,
+}
+
+impl Smoothing {
+    pub fn new(smoothing_window_size: usize) -> Self {
+        assert!(smoothing_window_size > 0);
+        Smoothing {
+            measurements: VecDeque::with_capacity(smoothing_window_size),
+            smoothing_window_size,
+        }
+    }
+
+    fn compute_smoothing_vector(&self) -> FusionVector {
+        let mut sum_x = 0.0;
+        let mut sum_y = 0.0;
+        let mut sum_z = 0.0;
+        for m in self.measurements.iter() {
+            sum_x += m.x;
+            sum_y += m.y;
+            sum_z += m.z;
+        }
+
+        let num_elems =  self.measurements.len() as f32;
+        FusionVector::new(sum_x / num_elems, sum_y / num_elems, sum_z / num_elems)
+    }
+}
+
+impl SmoothingStage for Smoothing {
+    // Adds a measurement and returns smoothed value
+    fn add_measurement(&mut self, linear_acceleration: FusionVector) -> FusionVector {
+        if self.measurements.len() >= self.smoothing_window_size {
+            self.measurements.pop_front();
+        }
+        self.measurements.push_back(linear_acceleration);
+
+        let sv = self.compute_smoothing_vector();
+
+        linear_acceleration - sv
+    }
+}
+
+/// Settings of [`Analysis`], in physical units.
+///
+/// Movements are detected on the magnitude of the smoothed linear
+/// acceleration, the vector norm `sqrt(h² + v²)` in g of its horizontal
+/// component `h` (the norm of the north and west axes) and vertical
+/// component `v`. The denoisers work on `h²` and `v²` over the detection
+/// window, so the magnitude is that of the denoised components. Its angle is
+/// the elevation `atan(v / h)`, 0° for purely horizontal movements and 90°
+/// for purely vertical ones.
+///
+/// Starting and stopping thresholds differ, as do the bounds to enter and
+/// leave the diagonal band, so that borderline strokes do not chatter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalysisConfig {
+    /// Moving average subtracted from the acceleration, in samples.
+    pub smoothing_window: usize,
+    /// Window of the denoiser, in samples.
+    pub detection_window: usize,
+    pub denoiser: DenoiserKind,
+    /// Magnitude over which a movement starts, in g.
+    pub start_threshold_g: f32,
+    /// Magnitude under which an ongoing movement ends, in g.
+    pub stop_threshold_g: f32,
+    /// Elevations between which a movement is diagonal, in degrees.
+    pub diagonal_low_deg: f32,
+    pub diagonal_high_deg: f32,
+    /// How much narrower the diagonal band is to enter than to leave, in
+    /// degrees.
+    pub angle_hysteresis_deg: f32,
+    /// Samples a new direction must last before it is reported.
+    pub min_dwell: usize,
+}
+
+impl Default for AnalysisConfig {
+    /// The settings of the firmware.
+    fn default() -> Self {
+        Self {
+            smoothing_window: 60,
+            detection_window: 30,
+            denoiser: DenoiserKind::Quantile,
+            start_threshold_g: 0.35,
+            stop_threshold_g: 0.32,
+            diagonal_low_deg: 34.0,
+            diagonal_high_deg: 56.0,
+            angle_hysteresis_deg: 4.0,
+            min_dwell: 4,
+        }
+    }
+}
+
+/// [`AnalysisConfig`] thresholds in terms of the denoised squares, so that
+/// classifying takes neither square roots nor arc tangents: the magnitude
+/// thresholds are squared, and the elevation bounds are turned into slopes
+/// `tan²` of `v²` over `h²`.
+#[derive(Debug, Clone, Copy)]
+struct Thresholds {
+    /// The nominal diagonal band, in degrees, for the confidence of events.
+    diagonal_deg: (f32, f32),
+    start: f32,
+    stop: f32,
+    diagonal_enter: (f32, f32),
+    diagonal_exit: (f32, f32),
+}
+
+impl Thresholds {
+    fn new(config: &AnalysisConfig) -> Self {
+        let half = config.angle_hysteresis_deg / 2.0;
+        let (enter_low, enter_high) = (config.diagonal_low_deg + half, config.diagonal_high_deg - half);
+        let (exit_low, exit_high) = (config.diagonal_low_deg - half, config.diagonal_high_deg + half);
+        assert!(0.0 <= config.stop_threshold_g && config.stop_threshold_g <= config.start_threshold_g);
+        assert!(0.0 <= exit_low && enter_low <= enter_high && exit_high < 90.0);
+
+        // micromath's tangent is too coarse near the vertical
+        let slope = |deg: f32| libm::powf(libm::tanf(deg * PI / 180.0), 2.0);
+        Self {
+            diagonal_deg: (config.diagonal_low_deg, config.diagonal_high_deg),
+            start: config.start_threshold_g * config.start_threshold_g,
+            stop: config.stop_threshold_g * config.stop_threshold_g,
+            diagonal_enter: (slope(enter_low), slope(enter_high)),
+            diagonal_exit: (slope(exit_low), slope(exit_high)),
+        }
+    }
+}
+
+/// A movement as seen by [`Analysis`], reported on every sample it lasts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectionEvent<T> {
+    pub direction: MovementDirection,
+    /// Denoised magnitude of the acceleration, in g (see [`AnalysisConfig`]).
+    pub magnitude: f32,
+    /// Degrees from the elevation of the movement to the nearest boundary of
+    /// the diagonal band, the larger the clearer its class.
+    pub confidence: f32,
+    /// When the current class of movement was first detected, ahead of its
+    /// first report by the minimum dwell.
+    pub onset: T,
+    /// Seconds the state before it lasted, rest or another class.
+    pub previous_duration: f32,
+}
+
+struct MovementDetection<T> {
+    movement_computation: Box<dyn Denoiser>,
+    thresholds: Thresholds,
+    min_dwell: usize,
+    prev_direction: Option<MovementClass>,
+    /// Onset of the reported state, and duration of the one before it.
+    onset: T,
+    previous_duration: f32,
+    /// A state differing from the reported one, since when, and for how many
+    /// samples.
+    pending: Option<(Option<MovementClass>, T, usize)>,
+    /// The latest denoised energies.
+    energies: (f32, f32),
+}
+
+impl<T: Timestamp> MovementDetection<T> {
+    fn add_measurement(&mut self, time: T, x: f32, y: f32) -> Option<MovementClass> {
+        self.energies = self.movement_computation.add_measurement(x, y);
+        let (x, y) = self.energies;
+        self.next_direction(time, x, y)
+    }
+
+    fn next_direction(&mut self, time: T, x_accel: f32, y_accel: f32) -> Option<MovementClass> {
+        let next_state = self.classify(x_accel, y_accel);
+
+        // Changes are reported once they have lasted `min_dwell` samples
+        if next_state == self.prev_direction {
+            self.pending = None;
+        } else {
+            let (onset, dwell) = match self.pending {
+                Some((state, onset, dwell)) if state == next_state => (onset, dwell + 1),
+                _ => (time, 1),
+            };
+            if dwell >= self.min_dwell {
+                self.previous_duration = onset.secs_since(self.onset);
+                self.onset = onset;
+                self.prev_direction = next_state;
+                self.pending = None;
+            } else {
+                self.pending = Some((next_state, onset, dwell));
+            }
+        }
+        self.prev_direction
+    }
+
+    /// Norm of the latest denoised acceleration, in g.
+    fn magnitude(&self) -> f32 {
+        F32Ext::sqrt(self.energies.0 + self.energies.1)
+    }
+
+    /// Degrees from the latest elevation to the nearest diagonal band bound.
+    fn margin(&self) -> f32 {
+        let (x, y) = self.energies;
+        let elevation = F32Ext::atan2(F32Ext::sqrt(y), F32Ext::sqrt(x)) * 180.0 / PI;
+        let (low, high) = self.thresholds.diagonal_deg;
+        F32Ext::abs(elevation - low).min(F32Ext::abs(elevation - high))
+    }
+
+    /// Classifies denoised horizontal and vertical energies, `h²` and `v²`.
+    fn classify(&self, x_accel: f32, y_accel: f32) -> Option<MovementClass> {
+        let t = &self.thresholds;
+        let threshold = if self.prev_direction.is_some() { t.stop } else { t.start };
+        if x_accel + y_accel < threshold {
+            return None;
+        }
+
+        let (low, high) = match self.prev_direction {
+            Some(MovementClass::Diagonal) => t.diagonal_exit,
+            Some(MovementClass::Horizontal) => (t.diagonal_enter.0, t.diagonal_exit.1),
+            Some(MovementClass::Vertical) => (t.diagonal_exit.0, t.diagonal_enter.1),
+            None => t.diagonal_enter,
+        };
+        // Elevation above `atan(sqrt(low))` and below `atan(sqrt(high))`
+        if x_accel * low < y_accel && y_accel < x_accel * high {
+            Some(MovementClass::Diagonal)
+        } else if y_accel <= x_accel * low {
+            Some(MovementClass::Horizontal)
+        } else {
+            Some(MovementClass::Vertical)
+        }
+    }
+}
+
+/// Samples over which the velocity estimate forgets, about a second at the
+/// firmware's sampling rate. Shorter memories make the estimate overshoot the
+/// other way at the end of every stroke.
+const VELOCITY_MEMORY: f32 = 200.0;
+
+/// Fraction of the recent peak velocity that motion the other way must reach
+/// for a sense to be reversed.
+const SENSE_REVERSAL: f32 = 0.5;
+
+/// Sense of motion along one axis, with hysteresis.
+struct Sense {
+    positive: bool,
+    peak: f32,
+}
+
+impl Sense {
+    fn update(&mut self, velocity: f32, leak: f32) {
+        let speed = F32Ext::abs(velocity);
+        self.peak = if speed > self.peak * leak { speed } else { self.peak * leak };
+        if (velocity > 0.0) != self.positive && speed > SENSE_REVERSAL * self.peak {
+            self.positive = velocity > 0.0;
+        }
+    }
+}
+
+/// Tells which way the wrist is moving, which the acceleration alone does not
+/// (it reverses while braking), from a leaky integral of it.
+///
+/// It is fed before smoothing, as the moving average removed there turns into
+/// a reversed-sign tail once a stroke stops. Velocities are expressed in the
+/// reference frame: forward, left and up.
+struct SenseTracker {
+    velocity: FusionVector,
+    leak: f32,
+    reference_heading: (f32, f32),
+    forward: Sense,
+    left: Sense,
+    up: Sense,
+}
+
+impl SenseTracker {
+    fn add_measurement(&mut self, acceleration: FusionVector) {
+        let (cos, sin) = self.reference_heading;
+        let acceleration = FusionVector::new(
+            acceleration.x * cos + acceleration.y * sin,
+            acceleration.y * cos - acceleration.x * sin,
+            acceleration.z,
+        );
+        self.velocity = self.velocity * self.leak + acceleration;
+
+        self.forward.update(self.velocity.x, self.leak);
+        self.left.update(self.velocity.y, self.leak);
+        self.up.update(self.velocity.z, self.leak);
+    }
+
+    /// Whether it moved forward or left rather than back or right, along the
+    /// axis it moved the most.
+    fn horizontal_sense(&self) -> bool {
+        if self.forward.peak >= self.left.peak { self.forward.positive } else { self.left.positive }
+    }
+
+    fn direction(&self, class: MovementClass) -> MovementDirection {
+        match class {
+            MovementClass::Vertical if self.up.positive => MovementDirection::Up,
+            MovementClass::Vertical => MovementDirection::Down,
+            MovementClass::Horizontal if self.forward.peak >= self.left.peak => {
+                if self.forward.positive { MovementDirection::Forward } else { MovementDirection::Back }
+            }
+            MovementClass::Horizontal => {
+                if self.left.positive { MovementDirection::Left } else { MovementDirection::Right }
+            }
+            // Along whichever horizontal axis it moved the most, forward
+            // counting as left and back as right, as if turned a quarter
+            MovementClass::Diagonal => match (self.up.positive, self.horizontal_sense()) {
+                (true, true) => MovementDirection::UpLeft,
+                (true, false) => MovementDirection::UpRight,
+                (false, true) => MovementDirection::DownLeft,
+                (false, false) => MovementDirection::DownRight,
+            },
+        }
+    }
+}
+
+pub struct Analysis<T: Timestamp> {
+    smoothing: Box<dyn SmoothingStage>,
+    movement_detection: MovementDetection<T>,
+    sense: SenseTracker,
+}
+
+impl<T: Timestamp + Default> Default for Analysis<T> {
+    fn default() -> Self {
+        Analysis::new(&AnalysisConfig::default(), T::default())
+    }
+}
+
+impl<T: Timestamp> Analysis<T> {
+    pub fn new(config: &AnalysisConfig, now: T) -> Analysis<T> {
+        assert!(config.detection_window > 0);
+        assert!(config.detection_window < config.smoothing_window);
+
+        Analysis {
+            smoothing: Box::new(Smoothing::new(config.smoothing_window)),
+            movement_detection: MovementDetection {
+                movement_computation: config.denoiser.build(config.detection_window),
+                thresholds: Thresholds::new(config),
+                min_dwell: config.min_dwell,
+                prev_direction: None,
+                onset: now,
+                previous_duration: 0.0,
+                pending: None,
+                energies: (0.0, 0.0),
+            },
+            sense: SenseTracker {
+                velocity: FusionVector::zero(),
+                leak: 1.0 - 1.0 / VELOCITY_MEMORY,
+                reference_heading: (1.0, 0.0),
+                forward: Sense { positive: true, peak: 0.0 },
+                left: Sense { positive: true, peak: 0.0 },
+                up: Sense { positive: true, peak: 0.0 },
+            },
+        }
+    }
+
+    /// Replaces the smoothing stage, e.g. with a
+    /// [`FilterBank`](crate::filter::FilterBank).
+    pub fn set_smoothing(&mut self, smoothing: Box<dyn SmoothingStage>) {
+        self.smoothing = smoothing;
+    }
+
+    /// Replaces the denoiser, which starts over with an empty window.
+    pub fn set_denoiser(&mut self, denoiser: Box<dyn Denoiser>) {
+        self.movement_detection.movement_computation = denoiser;
+    }
+
+    /// Sets the heading that counts as `Forward`, in radians counter-clockwise
+    /// from north (i.e. towards west) in the tracker's earth frame.
+    pub fn set_reference_heading(&mut self, heading: f32) {
+        self.sense.reference_heading = (F32Ext::cos(heading), F32Ext::sin(heading));
+    }
+
+    pub fn add_measurement(
+        &mut self,
+        time: T,
+        linear_acceleration: FusionVector,
+    ) -> Option<DetectionEvent<T>> {
+        let smoothed = self.smoothing.add_measurement(linear_acceleration);
+        let horiz = F32Ext::powi(smoothed.x, 2) + F32Ext::powi(smoothed.y, 2); // fuse horizontal components into one
+        let verti = F32Ext::powi(smoothed.z, 2);
+        // The thresholds are squared instead of taking square roots here
+        self.sense.add_measurement(linear_acceleration);
+        let class = self.movement_detection.add_measurement(time, horiz, verti)?;
+
+        let detection = &self.movement_detection;
+        Some(DetectionEvent {
+            direction: self.sense.direction(class),
+            magnitude: detection.magnitude(),
+            confidence: detection.margin(),
+            onset: detection.onset,
+            previous_duration: detection.previous_duration,
+        })
+    }
+}
+
+#[cfg(test)]
+fn detection(config: AnalysisConfig) -> MovementDetection<f32> {
+    // A one-sample window passes the energies through untouched
+    MovementDetection {
+        movement_computation: DenoiserKind::Average.build(1),
+        thresholds: Thresholds::new(&config),
+        min_dwell: config.min_dwell,
+        prev_direction: None,
+        onset: 0.0,
+        previous_duration: 0.0,
+        pending: None,
+        energies: (0.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+fn detect(config: AnalysisConfig, accelerations: &[(f32, f32)]) -> alloc::vec::Vec<Option<MovementClass>> {
+    let mut detection = detection(config);
+    accelerations.iter().enumerate()
+        .map(|(i, (h, v))| detection.add_measurement(i as f32, h * h, v * v))
+        .collect()
+}
+
+/// Horizontal and vertical components of an acceleration of `magnitude` g at
+/// `elevation` degrees.
+#[cfg(test)]
+fn at(elevation: f32, magnitude: f32) -> (f32, f32) {
+    let angle = elevation * PI / 180.0;
+    (magnitude * libm::cosf(angle), magnitude * libm::sinf(angle))
+}
+
+#[cfg(test)]
+fn plain_config(threshold_g: f32) -> AnalysisConfig {
+    AnalysisConfig {
+        start_threshold_g: threshold_g,
+        stop_threshold_g: threshold_g,
+        diagonal_low_deg: 35.0,
+        diagonal_high_deg: 55.0,
+        angle_hysteresis_deg: 0.0,
+        min_dwell: 1,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_threshold_applies_to_the_norm() {
+    // Both components are under the threshold at 45°, but not the magnitude
+    let detected = detect(plain_config(0.15), &[at(45.0, 0.14), at(45.0, 0.16), at(10.0, 0.16), at(80.0, 0.16)]);
+    assert_eq!(detected, [None, Some(MovementClass::Diagonal),
+                          Some(MovementClass::Horizontal), Some(MovementClass::Vertical)]);
+}
+
+#[test]
+fn test_hysteresis_stops_chatter() {
+    // Hovering around the horizontal/diagonal boundary at 35°, and around the
+    // magnitude threshold
+    let borderline = [at(33.0, 1.0), at(37.0, 1.0), at(34.0, 1.0), at(36.0, 1.0), at(38.0, 0.095), at(38.0, 0.13)];
+
+    let plain = detect(plain_config(0.1), &borderline);
+    assert_eq!(plain, [Some(MovementClass::Horizontal), Some(MovementClass::Diagonal),
+                       Some(MovementClass::Horizontal), Some(MovementClass::Diagonal),
+                       None, Some(MovementClass::Diagonal)]);
+
+    let config = AnalysisConfig { stop_threshold_g: 0.05, angle_hysteresis_deg: 10.0, ..plain_config(0.1) };
+    let steady = detect(config, &borderline);
+    assert!(steady.iter().all(|c| *c == Some(MovementClass::Horizontal)), "{steady:?}");
+}
+
+#[test]
+fn test_min_dwell_delays_changes() {
+    let (horizontal, vertical) = ((1.0, 0.0), (0.0, 1.0));
+    let accelerations = [horizontal, horizontal, vertical, horizontal, vertical, vertical, vertical, (0.0, 0.0)];
+
+    let detected = detect(AnalysisConfig { min_dwell: 3, ..plain_config(0.3) }, &accelerations);
+    let h = Some(MovementClass::Horizontal);
+    let v = Some(MovementClass::Vertical);
+    assert_eq!(detected, [None, None, None, None, None, None, v, v]);
+
+    let detected = detect(AnalysisConfig { min_dwell: 2, ..plain_config(0.3) }, &accelerations);
+    assert_eq!(detected, [None, h, h, h, h, v, v, v]);
+}
+
+#[test]
+fn test_event_timing_and_confidence() {
+    let mut detection = detection(AnalysisConfig { min_dwell: 3, ..plain_config(0.3) });
+    let accelerations = [(0.0, 0.0); 5].into_iter()
+        .chain([at(40.0, 1.0); 5])
+        .chain([at(10.0, 0.5); 5]);
+    for (i, (h, v)) in accelerations.enumerate() {
+        detection.add_measurement(i as f32, h * h, v * v);
+    }
+
+    // Rest until 5 s, a diagonal from then until 10 s and a horizontal since
+    assert_eq!(detection.prev_direction, Some(MovementClass::Horizontal));
+    assert_eq!(detection.onset, 10.0);
+    assert_eq!(detection.previous_duration, 5.0);
+    assert!(F32Ext::abs(detection.magnitude() - 0.5) < 0.01);
+    // 25° away from the lower bound of the band at 35°
+    assert!(F32Ext::abs(detection.margin() - 25.0) < 0.5, "{}", detection.margin());
+}
@@ -0,0 +1,356 @@
+//! Calibration of the IMU, as kept in flash across boots.
+//!
+//! Stored as [`CALIBRATION_MAGIC`], a version byte, the fields as little
+//! endian `f32`s and a CRC-32 of all of that, so that a blank, torn or
+//! outdated record is told apart from a valid one.
+
+use imu_fusion::{FusionMatrix, FusionVector};
+
+pub const CALIBRATION_MAGIC: &[u8; 4] = b"IMUC";
+/// Version 1 had no magnetometer calibration.
+pub const CALIBRATION_VERSION: u8 = 2;
+
+const FIELDS: usize = 9 + 3 * 3 + 3 + 9;
+
+/// Size of a stored calibration, in bytes.
+pub const CALIBRATION_SIZE: usize = size(FIELDS);
+
+const fn size(fields: usize) -> usize {
+    4 + 1 + 4 * fields + 4
+}
+
+/// Largest spread of gyroscope readings, in degrees/s, of a device held still.
+const MAX_STILL_SPREAD: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// Erased flash: nothing was ever stored.
+    Blank,
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadChecksum,
+}
+
+/// An offset subtracted from raw readings, in their unit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Offset(pub [f32; 3]);
+
+/// A gain each axis of the readings is multiplied by, once offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sensitivity(pub [f32; 3]);
+
+/// A matrix, row major, the corrected readings are multiplied by: the
+/// misalignment of the axes, or the soft iron of the magnetometer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub [[f32; 3]; 3]);
+
+impl Default for Sensitivity {
+    fn default() -> Self {
+        Self([1.0; 3])
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+}
+
+impl Offset {
+    pub fn fusion(&self) -> FusionVector {
+        FusionVector::new(self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl Sensitivity {
+    pub fn fusion(&self) -> FusionVector {
+        FusionVector::new(self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl Transform {
+    pub fn fusion(&self) -> FusionMatrix {
+        let [x, y, z] = self.0;
+        FusionMatrix::new(x[0], x[1], x[2], y[0], y[1], y[2], z[0], z[1], z[2])
+    }
+}
+
+/// Corrections applied to the raw readings before fusion: the accelerometer
+/// reading `a` becomes `misalignment * ((a - offset) * sensitivity)`, the
+/// gyroscope one `g - gyr_offset` and the magnetometer one
+/// `mag_soft_iron * (m - mag_hard_iron)`.
+///
+/// Its fields being of distinct types, an offset can not be given where a
+/// sensitivity is expected, nor the other way round:
+///
+/// ```compile_fail
+/// use motion::calibration::{ImuCalibration, Offset};
+///
+/// let calibration = ImuCalibration { acc_sensitivity: Offset([0.0; 3]), ..Default::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImuCalibration {
+    pub acc_misalignment: Transform,
+    pub acc_sensitivity: Sensitivity,
+    /// In g.
+    pub acc_offset: Offset,
+    /// In degrees/s.
+    pub gyr_offset: Offset,
+    /// In µT.
+    pub mag_hard_iron: Offset,
+    pub mag_soft_iron: Transform,
+}
+
+impl ImuCalibration {
+    /// An accelerometer reading corrected, in g, the same way Fusion does.
+    pub fn correct_acc(&self, raw: FusionVector) -> FusionVector {
+        self.acc_misalignment.fusion() * ((raw - self.acc_offset.fusion()) * self.acc_sensitivity.fusion())
+    }
+
+    /// A gyroscope reading corrected, in degrees/s.
+    pub fn correct_gyr(&self, raw: FusionVector) -> FusionVector {
+        raw - self.gyr_offset.fusion()
+    }
+
+    /// A magnetometer reading corrected, in µT.
+    pub fn correct_mag(&self, raw: FusionVector) -> FusionVector {
+        self.mag_soft_iron.fusion() * (raw - self.mag_hard_iron.fusion())
+    }
+
+    fn fields(&self) -> [f32; FIELDS] {
+        let mut fields = [0.0; FIELDS];
+        let values = self.acc_misalignment.0.iter().flatten()
+            .chain(&self.acc_sensitivity.0)
+            .chain(&self.acc_offset.0)
+            .chain(&self.gyr_offset.0)
+            .chain(&self.mag_hard_iron.0)
+            .chain(self.mag_soft_iron.0.iter().flatten());
+        for (field, value) in fields.iter_mut().zip(values) {
+            *field = *value;
+        }
+        fields
+    }
+
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_SIZE] {
+        let mut bytes = [0; CALIBRATION_SIZE];
+        bytes[..4].copy_from_slice(CALIBRATION_MAGIC);
+        bytes[4] = CALIBRATION_VERSION;
+        for (chunk, field) in bytes[5..].chunks_exact_mut(4).zip(self.fields()) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        let crc = crc32(&bytes[..CALIBRATION_SIZE - 4]);
+        bytes[CALIBRATION_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CalibrationError> {
+        if bytes.len() >= 4 && bytes[..4] == [0xff; 4] {
+            return Err(CalibrationError::Blank);
+        }
+        if bytes.len() < 5 {
+            return Err(CalibrationError::Truncated);
+        }
+        if &bytes[..4] != CALIBRATION_MAGIC {
+            return Err(CalibrationError::BadMagic);
+        }
+        let size = match bytes[4] {
+            1 => size(9 + 3 * 3),
+            CALIBRATION_VERSION => CALIBRATION_SIZE,
+            version => return Err(CalibrationError::UnsupportedVersion(version)),
+        };
+        let bytes = bytes.get(..size).ok_or(CalibrationError::Truncated)?;
+        let (data, crc) = bytes.split_at(size - 4);
+        if crc32(data).to_le_bytes() != crc {
+            return Err(CalibrationError::BadChecksum);
+        }
+
+        let mut fields = data[5..].chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap()));
+        let mut next = || Some([fields.next()?, fields.next()?, fields.next()?]);
+        let defaults = Self::default();
+        Ok(Self {
+            acc_misalignment: Transform([next().unwrap(), next().unwrap(), next().unwrap()]),
+            acc_sensitivity: Sensitivity(next().unwrap()),
+            acc_offset: Offset(next().unwrap()),
+            gyr_offset: Offset(next().unwrap()),
+            mag_hard_iron: next().map_or(defaults.mag_hard_iron, Offset),
+            mag_soft_iron: match (next(), next(), next()) {
+                (Some(x), Some(y), Some(z)) => Transform([x, y, z]),
+                _ => defaults.mag_soft_iron,
+            },
+        })
+    }
+}
+
+/// A change to the calibration, as given in a remote command:
+/// `<field>=<values>` with the values comma separated, matrices row by row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationUpdate {
+    AccMisalignment(Transform),
+    AccSensitivity(Sensitivity),
+    AccOffset(Offset),
+    GyrOffset(Offset),
+    MagHardIron(Offset),
+    MagSoftIron(Transform),
+}
+
+impl CalibrationUpdate {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (field, values) = text.split_once('=')?;
+        let mut values = values.split(',').map(|v| v.trim().parse::<f32>());
+        let mut parsed = [0.0; 9];
+        let count = match field.trim() {
+            "acc_misalignment" | "mag_soft_iron" => 9,
+            "acc_sensitivity" | "acc_offset" | "gyr_offset" | "mag_hard_iron" => 3,
+            _ => return None,
+        };
+        for value in &mut parsed[..count] {
+            *value = values.next()?.ok().filter(|v| v.is_finite())?;
+        }
+        if values.next().is_some() {
+            return None;
+        }
+
+        let vector = [parsed[0], parsed[1], parsed[2]];
+        let matrix = Transform([vector, [parsed[3], parsed[4], parsed[5]], [parsed[6], parsed[7], parsed[8]]]);
+        Some(match field.trim() {
+            "acc_misalignment" => Self::AccMisalignment(matrix),
+            "acc_sensitivity" => Self::AccSensitivity(Sensitivity(vector)),
+            "acc_offset" => Self::AccOffset(Offset(vector)),
+            "gyr_offset" => Self::GyrOffset(Offset(vector)),
+            "mag_hard_iron" => Self::MagHardIron(Offset(vector)),
+            _ => Self::MagSoftIron(matrix),
+        })
+    }
+
+    pub fn apply(&self, calibration: &mut ImuCalibration) {
+        match *self {
+            Self::AccMisalignment(m) => calibration.acc_misalignment = m,
+            Self::AccSensitivity(v) => calibration.acc_sensitivity = v,
+            Self::AccOffset(v) => calibration.acc_offset = v,
+            Self::GyrOffset(v) => calibration.gyr_offset = v,
+            Self::MagHardIron(v) => calibration.mag_hard_iron = v,
+            Self::MagSoftIron(m) => calibration.mag_soft_iron = m,
+        }
+    }
+}
+
+/// Gyroscope offset out of readings taken while the device is held still.
+#[derive(Debug, Clone, Default)]
+pub struct GyroOffsetEstimator {
+    sum: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+    count: u32,
+}
+
+impl GyroOffsetEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, gyr: FusionVector) {
+        for (i, v) in [gyr.x, gyr.y, gyr.z].into_iter().enumerate() {
+            if self.count == 0 {
+                self.min[i] = v;
+                self.max[i] = v;
+            }
+            self.sum[i] += v;
+            self.min[i] = self.min[i].min(v);
+            self.max[i] = self.max[i].max(v);
+        }
+        self.count += 1;
+    }
+
+    /// The mean reading, in degrees/s, unless there were none or the device
+    /// moved while they were taken.
+    pub fn offset(&self) -> Option<Offset> {
+        let still = (0..3).all(|i| self.max[i] - self.min[i] <= MAX_STILL_SPREAD);
+        (self.count > 0 && still).then(|| Offset(self.sum.map(|s| s / self.count as f32)))
+    }
+}
+
+/// CRC-32 (IEEE 802.3), bit by bit: it only runs when the calibration is
+/// loaded or saved.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_serialization() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+    let calibration = ImuCalibration {
+        acc_misalignment: Transform([[1.0, 0.01, 0.0], [-0.01, 1.0, 0.002], [0.0, 0.0, 0.99]]),
+        acc_sensitivity: Sensitivity([1.02, 0.98, 1.0]),
+        acc_offset: Offset([0.015, -0.02, 0.03]),
+        gyr_offset: Offset([0.8, -1.25, 0.1]),
+        mag_hard_iron: Offset([20.5, -14.0, 31.0]),
+        mag_soft_iron: Transform([[0.95, 0.02, 0.0], [0.02, 1.08, -0.01], [0.0, -0.01, 0.97]]),
+    };
+    let bytes = calibration.to_bytes();
+    assert_eq!(ImuCalibration::from_bytes(&bytes), Ok(calibration));
+    // Whatever follows in the flash sector is ignored
+    let mut sector = [0xff; 256];
+    sector[..CALIBRATION_SIZE].copy_from_slice(&bytes);
+    assert_eq!(ImuCalibration::from_bytes(&sector), Ok(calibration));
+
+    let mut torn = bytes;
+    torn[20] ^= 0x01;
+    assert_eq!(ImuCalibration::from_bytes(&torn), Err(CalibrationError::BadChecksum));
+    let mut newer = bytes;
+    newer[4] = CALIBRATION_VERSION + 1;
+    assert_eq!(ImuCalibration::from_bytes(&newer), Err(CalibrationError::UnsupportedVersion(CALIBRATION_VERSION + 1)));
+    assert_eq!(ImuCalibration::from_bytes(&[0xff; 128]), Err(CalibrationError::Blank));
+    assert_eq!(ImuCalibration::from_bytes(&bytes[..40]), Err(CalibrationError::Truncated));
+    assert_eq!(ImuCalibration::from_bytes(b"XXXX\x01"), Err(CalibrationError::BadMagic));
+
+    // Stored before the magnetometer was calibrated
+    const V1_SIZE: usize = size(9 + 3 * 3);
+    let mut v1 = [0; V1_SIZE];
+    v1[..V1_SIZE - 4].copy_from_slice(&bytes[..V1_SIZE - 4]);
+    v1[4] = 1;
+    let crc = crc32(&v1[..V1_SIZE - 4]);
+    v1[V1_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+    let defaults = ImuCalibration::default();
+    assert_eq!(ImuCalibration::from_bytes(&v1),
+               Ok(ImuCalibration { mag_hard_iron: defaults.mag_hard_iron, mag_soft_iron: defaults.mag_soft_iron, ..calibration }));
+}
+
+#[test]
+fn test_updates() {
+    let mut calibration = ImuCalibration::default();
+    CalibrationUpdate::parse("gyr_offset=0.5,-1,0.25").unwrap().apply(&mut calibration);
+    assert_eq!(calibration.gyr_offset, Offset([0.5, -1.0, 0.25]));
+    CalibrationUpdate::parse("acc_misalignment=1,0,0, 0,1,0.01, 0,0,1").unwrap().apply(&mut calibration);
+    assert_eq!(calibration.acc_misalignment.0[1], [0.0, 1.0, 0.01]);
+
+    assert_eq!(CalibrationUpdate::parse("acc_offset=0,0"), None);
+    assert_eq!(CalibrationUpdate::parse("acc_offset=0,0,0,0"), None);
+    assert_eq!(CalibrationUpdate::parse("acc_offset=0,nan,0"), None);
+    assert_eq!(CalibrationUpdate::parse("mag_hard_iron=20,-14,31"), Some(CalibrationUpdate::MagHardIron(Offset([20.0, -14.0, 31.0]))));
+    assert_eq!(CalibrationUpdate::parse("mag_offset=0,0,0"), None);
+}
+
+#[test]
+fn test_gyro_offset() {
+    let mut estimator = GyroOffsetEstimator::new();
+    assert_eq!(estimator.offset(), None);
+    for i in 0..250 {
+        let noise = if i % 2 == 0 { 0.1 } else { -0.1 };
+        estimator.add(FusionVector::new(0.8 + noise, -1.25, 0.1 - noise));
+    }
+    let Offset(offset) = estimator.offset().unwrap();
+    assert!((offset[0] - 0.8).abs() < 1e-3 && (offset[1] + 1.25).abs() < 1e-3 && (offset[2] - 0.1).abs() < 1e-3);
+
+    // Moved halfway through
+    estimator.add(FusionVector::new(40.0, 0.0, 0.0));
+    assert_eq!(estimator.offset(), None);
+}
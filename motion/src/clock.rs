@@ -0,0 +1,155 @@
+use alloc::collections::VecDeque;
+
+/// Offset measurements kept to discipline the clock, which at the usual SNTP
+/// poll interval span a few minutes.
+const SAMPLES: usize = 8;
+
+/// Samples slower than the fastest one by more than this, in µs, are left out
+/// as they were likely queued somewhere on the way.
+const DELAY_MARGIN: u64 = 2_000;
+
+/// Time the samples must span for the drift to be estimated, in µs.
+const MIN_DRIFT_SPAN: u64 = 1_000_000;
+
+/// Largest frequency error of the local clock that is believed, as a
+/// fraction. Crystals stay within a few tens of ppm.
+const MAX_DRIFT: f64 = 500e-6;
+
+/// One measurement of the wall clock against the local one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSample {
+    /// Local time it was taken at, in µs.
+    pub local: u64,
+    /// Wall time minus local time, in µs.
+    pub offset: i64,
+    /// Round trip delay of the exchange, in µs, the uncertainty of the offset
+    /// being half of it.
+    pub delay: u64,
+}
+
+impl ClockSample {
+    /// Out of an NTP-style exchange: a request sent at local time `t1`,
+    /// received at remote time `t2` and answered at `t3`, the answer arriving
+    /// at local time `t4`, all in µs. The path is assumed to be symmetric.
+    pub fn from_exchange(t1: u64, t2: u64, t3: u64, t4: u64) -> Option<ClockSample> {
+        if t4 < t1 || t3 < t2 {
+            return None;
+        }
+        let delay = (t4 - t1).saturating_sub(t3 - t2);
+        let offset = ((t2 as i128 - t1 as i128) + (t3 as i128 - t4 as i128)) / 2;
+        Some(ClockSample { local: t1 + (t4 - t1) / 2, offset: offset as i64, delay })
+    }
+
+    /// The same exchange seen from the side answering it, whose local clock
+    /// `t2` and `t3` are on, measuring the clock of the side that asked.
+    pub fn from_echo(t1: u64, t2: u64, t3: u64, t4: u64) -> Option<ClockSample> {
+        let sample = ClockSample::from_exchange(t1, t2, t3, t4)?;
+        Some(ClockSample { local: t2 + (t3 - t2) / 2, offset: -sample.offset, delay: sample.delay })
+    }
+}
+
+/// Wall clock kept as an offset and a drift from the local one, disciplined
+/// by [`ClockSample`]s as NTP does: the offset is taken from the fastest
+/// exchanges only, and the drift is the slope of their offsets over time.
+#[derive(Debug)]
+pub struct WallClock {
+    samples: VecDeque<ClockSample>,
+    /// Wall time is `local + offset + drift * (local - reference)`.
+    reference: u64,
+    offset: i64,
+    drift: f64,
+    synchronized: bool,
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        Self { samples: VecDeque::new(), reference: 0, offset: 0, drift: 0.0, synchronized: false }
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized
+    }
+
+    /// Frequency error of the local clock, in ppm, positive when it runs slow.
+    pub fn drift_ppm(&self) -> f32 {
+        (self.drift * 1e6) as f32
+    }
+
+    /// Wall time minus local time at `local`, in µs.
+    pub fn offset(&self, local: u64) -> i64 {
+        self.offset + (self.drift * (local as f64 - self.reference as f64)) as i64
+    }
+
+    /// Wall time at local time `local`, both in µs, once synchronized.
+    pub fn wall_time(&self, local: u64) -> Option<u64> {
+        self.synchronized.then(|| (local as i64 + self.offset(local)) as u64)
+    }
+
+    pub fn add_sample(&mut self, sample: ClockSample) {
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        let fastest = self.samples.iter().map(|s| s.delay).min().unwrap();
+        let kept = || self.samples.iter().filter(|s| s.delay <= fastest + DELAY_MARGIN);
+        let first = kept().map(|s| s.local).min().unwrap();
+        let last = kept().map(|s| s.local).max().unwrap();
+
+        if last - first >= MIN_DRIFT_SPAN {
+            // Least squares fit of the offsets, around their mean
+            let n = kept().count() as f64;
+            let mean_local = kept().map(|s| (s.local - first) as f64).sum::<f64>() / n;
+            let mean_offset = kept().map(|s| s.offset as f64).sum::<f64>() / n;
+            let (mut sxy, mut sxx) = (0.0, 0.0);
+            for s in kept() {
+                let dx = (s.local - first) as f64 - mean_local;
+                sxy += dx * (s.offset as f64 - mean_offset);
+                sxx += dx * dx;
+            }
+            self.drift = (sxy / sxx).clamp(-MAX_DRIFT, MAX_DRIFT);
+            self.reference = first + mean_local as u64;
+            self.offset = mean_offset as i64;
+        } else {
+            let best = kept().min_by_key(|s| s.delay).unwrap();
+            self.reference = best.local;
+            self.offset = best.offset;
+        }
+        self.synchronized = true;
+    }
+}
+
+#[test]
+fn test_exchange_offset() {
+    // The remote clock is 5 s ahead, with 3 ms of path each way and 1 ms spent
+    // answering
+    let sample = ClockSample::from_exchange(10_000, 5_013_000, 5_014_000, 17_000).unwrap();
+    assert_eq!(sample, ClockSample { local: 13_500, offset: 5_000_000, delay: 6_000 });
+    assert_eq!(ClockSample::from_exchange(10_000, 0, 0, 9_000), None);
+    let echo = ClockSample::from_echo(10_000, 5_013_000, 5_014_000, 17_000).unwrap();
+    assert_eq!(echo, ClockSample { local: 5_013_500, offset: -5_000_000, delay: 6_000 });
+}
+
+#[test]
+fn test_discipline() {
+    // A local clock running 40 ppm slow, with exchanges now and then delayed
+    let true_offset = |local: u64| 1_700_000_000_000_000 + (local as f64 * 40e-6) as i64;
+    let mut clock = WallClock::new();
+    assert_eq!(clock.wall_time(0), None);
+    for i in 0..20u64 {
+        let local = i * 2_000_000;
+        let (delay, error) = if i % 3 == 0 { (50_000, 20_000) } else { (1_000, (i as i64 % 2) * 200 - 100) };
+        clock.add_sample(ClockSample { local, offset: true_offset(local) + error, delay });
+    }
+
+    let local = 45_000_000;
+    let error = clock.wall_time(local).unwrap() as i64 - (local as i64 + true_offset(local));
+    assert!(error.abs() < 200, "{error} µs off");
+    assert!((clock.drift_ppm() - 40.0).abs() < 10.0, "{} ppm", clock.drift_ppm());
+}
@@ -0,0 +1,259 @@
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+
+use crate::quantile::SlidingQuantile;
+
+/// Turns the stream of horizontal and vertical movement energies into the
+/// values compared against the detection thresholds.
+pub trait Denoiser {
+    fn add_measurement(&mut self, x: f32, y: f32) -> (f32, f32);
+}
+
+/// The denoisers [`Analysis`](crate::Analysis) can be switched between at
+/// runtime, by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenoiserKind {
+    Average,
+    Quantile,
+    Median,
+    Ema,
+    TrimmedMean,
+}
+
+impl DenoiserKind {
+    pub const ALL: [DenoiserKind; 5] = [
+        DenoiserKind::Average,
+        DenoiserKind::Quantile,
+        DenoiserKind::Median,
+        DenoiserKind::Ema,
+        DenoiserKind::TrimmedMean,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DenoiserKind::Average => "average",
+            DenoiserKind::Quantile => "quantile",
+            DenoiserKind::Median => "median",
+            DenoiserKind::Ema => "ema",
+            DenoiserKind::TrimmedMean => "trimmed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DenoiserKind> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    pub fn build(&self, detection_window_size: usize) -> Box<dyn Denoiser> {
+        match *self {
+            DenoiserKind::Average => Box::new(AverageDenoiser::new(detection_window_size)),
+            DenoiserKind::Quantile => Box::new(QuantileDenoiser::new(detection_window_size)),
+            DenoiserKind::Median => Box::new(MedianDenoiser::new(detection_window_size)),
+            DenoiserKind::Ema => Box::new(EmaDenoiser::new(detection_window_size)),
+            DenoiserKind::TrimmedMean => Box::new(TrimmedMeanDenoiser::new(detection_window_size)),
+        }
+    }
+}
+
+pub struct AverageDenoiser {
+    horizontal_measurements: VecDeque<f32>,
+    vertical_measurements: VecDeque<f32>,
+    detection_window_size: usize,
+}
+
+impl AverageDenoiser {
+    pub fn new(detection_window_size: usize) -> Self {
+        Self {
+            detection_window_size,
+            horizontal_measurements: VecDeque::with_capacity(detection_window_size),
+            vertical_measurements: VecDeque::with_capacity(detection_window_size),
+        }
+    }
+
+    fn compute_average_detection_accel(&self) -> (f32, f32) {
+        let mean_horizontal = self.horizontal_measurements.iter().sum::<f32>()
+            / self.horizontal_measurements.len() as f32;
+        let mean_vertical = self.vertical_measurements.iter().sum::<f32>()
+            / self.horizontal_measurements.len() as f32;
+
+        (mean_horizontal, mean_vertical)
+    }
+}
+
+impl Denoiser for AverageDenoiser {
+    fn add_measurement(&mut self, x: f32, y: f32) -> (f32, f32) {
+        if self.horizontal_measurements.len() >= self.detection_window_size {
+            self.horizontal_measurements.pop_front();
+            self.vertical_measurements.pop_front();
+        }
+
+        self.horizontal_measurements.push_back(x);
+        self.vertical_measurements.push_back(y);
+
+        self.compute_average_detection_accel()
+    }
+}
+
+const QUANTILE: f32 = 0.75;
+
+pub struct QuantileDenoiser {
+    horizontal: SlidingQuantile,
+    vertical: SlidingQuantile,
+}
+
+impl QuantileDenoiser {
+    pub fn new(detection_window_size: usize) -> Self {
+        Self {
+            horizontal: SlidingQuantile::new(QUANTILE, detection_window_size),
+            vertical: SlidingQuantile::new(QUANTILE, detection_window_size),
+        }
+    }
+}
+
+impl Denoiser for QuantileDenoiser {
+    fn add_measurement(&mut self, x: f32, y: f32) -> (f32, f32) {
+        (self.horizontal.add_measurement(x), self.vertical.add_measurement(y))
+    }
+}
+
+pub struct MedianDenoiser {
+    horizontal: SlidingQuantile,
+    vertical: SlidingQuantile,
+}
+
+impl MedianDenoiser {
+    pub fn new(detection_window_size: usize) -> Self {
+        Self {
+            horizontal: SlidingQuantile::new(0.5, detection_window_size),
+            vertical: SlidingQuantile::new(0.5, detection_window_size),
+        }
+    }
+}
+
+impl Denoiser for MedianDenoiser {
+    fn add_measurement(&mut self, x: f32, y: f32) -> (f32, f32) {
+        (self.horizontal.add_measurement(x), self.vertical.add_measurement(y))
+    }
+}
+
+/// Exponential moving average with the same center of mass as a boxcar of
+/// the detection window.
+pub struct EmaDenoiser {
+    alpha: f32,
+    state: Option<(f32, f32)>,
+}
+
+impl EmaDenoiser {
+    pub fn new(detection_window_size: usize) -> Self {
+        Self { alpha: 2.0 / (detection_window_size as f32 + 1.0), state: None }
+    }
+}
+
+impl Denoiser for EmaDenoiser {
+    fn add_measurement(&mut self, x: f32, y: f32) -> (f32, f32) {
+        let state = match self.state {
+            Some((sx, sy)) => (sx + self.alpha * (x - sx), sy + self.alpha * (y - sy)),
+            None => (x, y),
+        };
+        self.state = Some(state);
+        state
+    }
+}
+
+/// Fraction of the window dropped at each end by [`TrimmedMeanDenoiser`].
+const TRIM: f32 = 0.25;
+
+/// The window of one axis kept in arrival order and in sorted order, the
+/// latter updated by binary search instead of being sorted anew.
+struct SortedWindow {
+    arrivals: VecDeque<f32>,
+    sorted: Vec<f32>,
+    size: usize,
+}
+
+impl SortedWindow {
+    fn new(size: usize) -> Self {
+        Self { arrivals: VecDeque::with_capacity(size), sorted: Vec::with_capacity(size), size }
+    }
+
+    fn add(&mut self, value: f32) {
+        if self.arrivals.len() >= self.size {
+            let old = self.arrivals.pop_front().unwrap();
+            let i = self.sorted.partition_point(|v| v.total_cmp(&old).is_lt());
+            self.sorted.remove(i);
+        }
+        self.arrivals.push_back(value);
+        let i = self.sorted.partition_point(|v| v.total_cmp(&value).is_lt());
+        self.sorted.insert(i, value);
+    }
+
+    fn trimmed_mean(&self) -> f32 {
+        let trim = (self.sorted.len() as f32 * TRIM) as usize;
+        let kept = &self.sorted[trim..self.sorted.len() - trim];
+        kept.iter().sum::<f32>() / kept.len() as f32
+    }
+}
+
+/// Mean of the window without its lowest and highest quarters, which keeps
+/// isolated spikes out like the quantiles do while still averaging.
+pub struct TrimmedMeanDenoiser {
+    horizontal: SortedWindow,
+    vertical: SortedWindow,
+}
+
+impl TrimmedMeanDenoiser {
+    pub fn new(detection_window_size: usize) -> Self {
+        Self {
+            horizontal: SortedWindow::new(detection_window_size),
+            vertical: SortedWindow::new(detection_window_size),
+        }
+    }
+}
+
+impl Denoiser for TrimmedMeanDenoiser {
+    fn add_measurement(&mut self, x: f32, y: f32) -> (f32, f32) {
+        self.horizontal.add(x);
+        self.vertical.add(y);
+        (self.horizontal.trimmed_mean(), self.vertical.trimmed_mean())
+    }
+}
+
+#[test]
+fn test_simple_quantile_movement_computation() {
+    let mut movement_detection = QuantileDenoiser::new(30);
+
+    for _ in 0..100 {
+        let movement = movement_detection.add_measurement(0.0, 0.0);
+        assert_eq!(movement, (0.0, 0.0));
+    }
+}
+
+#[test]
+fn test_denoisers_reject_a_spike() {
+    // A steady level with one outlier in the middle of the window
+    for kind in [DenoiserKind::Quantile, DenoiserKind::Median, DenoiserKind::TrimmedMean] {
+        let mut denoiser = kind.build(9);
+        let mut out = (0.0, 0.0);
+        for i in 0..9 {
+            let spike = if i == 4 { 100.0 } else { 1.0 };
+            out = denoiser.add_measurement(spike, 2.0);
+        }
+        assert_eq!(out, (1.0, 2.0), "{kind:?}");
+    }
+
+    let mut average = DenoiserKind::Average.build(9);
+    let mut ema = DenoiserKind::Ema.build(9);
+    for i in 0..9 {
+        let spike = if i == 4 { 100.0 } else { 1.0 };
+        average.add_measurement(spike, 2.0);
+        ema.add_measurement(spike, 2.0);
+    }
+    assert_eq!(average.add_measurement(1.0, 2.0).0, 12.0);
+    assert!(ema.add_measurement(1.0, 2.0).0 > 1.0);
+}
+
+#[test]
+fn test_kinds_by_name() {
+    for kind in DenoiserKind::ALL {
+        assert_eq!(DenoiserKind::from_name(kind.as_str()), Some(kind));
+    }
+    assert_eq!(DenoiserKind::from_name("boxcar"), None);
+}
@@ -0,0 +1,354 @@
+//! Estimators of the orientation of the device out of its readings, which
+//! [`ImuTracker`](crate::ImuTracker) can be built with.
+//!
+//! They all take readings already corrected by the
+//! [`ImuCalibration`](crate::ImuCalibration), and give the rotation from the
+//! sensor frame to the earth axes of a [`Convention`].
+
+use alloc::boxed::Box;
+
+use imu_fusion::{Fusion, FusionQuaternion, FusionVector};
+
+use crate::{
+    imu_tracker::{rotate, AhrsConfig, Convention},
+    kalman::KalmanFilter,
+    mahony::MahonyFilter,
+};
+
+/// Fuses the readings of the IMU into the orientation of the device.
+pub trait OrientationEstimator {
+    /// Takes readings taken `dt` seconds after the previous ones: the
+    /// gyroscope in degrees/s, the accelerometer in g and the magnetometer in
+    /// µT, zero when there is none.
+    fn update(&mut self, gyr: FusionVector, acc: FusionVector, mag: FusionVector, dt: f32);
+
+    fn quaternion(&self) -> FusionQuaternion;
+
+    /// Starts over from a known orientation, such as the one given by
+    /// [`crate::alignment::attitude`], skipping any initial alignment.
+    fn set_quaternion(&mut self, quaternion: FusionQuaternion);
+}
+
+/// The estimators an [`AhrsConfig`] can pick, by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EstimatorKind {
+    /// The AHRS of the `imu-fusion` crate.
+    Fusion,
+    Mahony,
+    Kalman,
+}
+
+impl EstimatorKind {
+    pub const ALL: [EstimatorKind; 3] = [EstimatorKind::Fusion, EstimatorKind::Mahony, EstimatorKind::Kalman];
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EstimatorKind::Fusion => "fusion",
+            EstimatorKind::Mahony => "mahony",
+            EstimatorKind::Kalman => "kalman",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EstimatorKind> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    /// For readings taken `sampling_freq` times a second by a gyroscope
+    /// reading up to `gyr_range` degrees/s.
+    pub fn build(&self, config: &AhrsConfig, sampling_freq: u32, gyr_range: f32) -> Box<dyn OrientationEstimator> {
+        match *self {
+            EstimatorKind::Fusion => Box::new(FusionEstimator::new(config, sampling_freq, gyr_range)),
+            EstimatorKind::Mahony => Box::new(MahonyFilter::new(config)),
+            EstimatorKind::Kalman => Box::new(KalmanFilter::new(config)),
+        }
+    }
+}
+
+/// The AHRS of the `imu-fusion` crate, along with its correction of the
+/// gyroscope offsets at runtime.
+pub struct FusionEstimator {
+    fusion: Fusion,
+}
+
+impl FusionEstimator {
+    pub fn new(config: &AhrsConfig, sampling_freq: u32, gyr_range: f32) -> Self {
+        let mut fusion = Fusion::new(sampling_freq, config.settings(sampling_freq, gyr_range));
+        // Fusion ramps its gain down the same way, but from fixed values
+        let ahrs = &mut fusion.ahrs;
+        ahrs.ramped_gain = config.initial_gain.max(config.gain);
+        ahrs.ramped_gain_step = if config.alignment_period > 0.0 {
+            (ahrs.ramped_gain - config.gain) / config.alignment_period
+        } else {
+            f32::MAX
+        };
+        Self { fusion }
+    }
+
+    /// The gain applied to the last update, as scheduled by the
+    /// [`AhrsConfig`].
+    pub fn gain(&self) -> f32 {
+        self.fusion.ahrs.ramped_gain
+    }
+}
+
+impl OrientationEstimator for FusionEstimator {
+    fn update(&mut self, gyr: FusionVector, acc: FusionVector, mag: FusionVector, dt: f32) {
+        self.fusion.update_by_duration_seconds(gyr, acc, mag, dt);
+    }
+
+    fn quaternion(&self) -> FusionQuaternion {
+        self.fusion.quaternion()
+    }
+
+    fn set_quaternion(&mut self, quaternion: FusionQuaternion) {
+        let ahrs = &mut self.fusion.ahrs;
+        ahrs.quaternion = quaternion;
+        ahrs.initialising = false;
+        ahrs.ramped_gain = ahrs.settings.gain;
+    }
+}
+
+/// The gain decaying linearly from `initial_gain` to `gain` over the
+/// alignment period of an [`AhrsConfig`], as Fusion does.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GainSchedule {
+    gain: f32,
+    target: f32,
+    step: f32,
+}
+
+impl GainSchedule {
+    pub(crate) fn new(config: &AhrsConfig) -> Self {
+        // No alignment either for the gyroscope alone
+        let gain = if config.gain > 0.0 { config.initial_gain.max(config.gain) } else { 0.0 };
+        let step = if config.alignment_period > 0.0 { (gain - config.gain) / config.alignment_period } else { f32::MAX };
+        Self { gain, target: config.gain, step }
+    }
+
+    /// The gain to apply `dt` seconds after the previous update.
+    pub(crate) fn next(&mut self, dt: f32) -> f32 {
+        self.gain = (self.gain - self.step * dt).max(self.target);
+        self.gain
+    }
+
+    pub(crate) fn is_aligning(&self) -> bool {
+        self.gain > self.target
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.gain = self.target;
+    }
+}
+
+/// A reading of the accelerometer or magnetometer that can be ignored for a
+/// while when it disagrees with the orientation, as Fusion does.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rejection {
+    /// Cosine of the largest angle between the reading and its expected
+    /// direction.
+    min_cosine: f32,
+    recovery_period: f32,
+    /// Seconds the reading has been ignored for.
+    ignored: f32,
+}
+
+impl Rejection {
+    pub(crate) fn new(threshold_deg: f32, recovery_period: f32) -> Self {
+        let min_cosine = if threshold_deg > 0.0 && recovery_period > 0.0 {
+            libm::cosf(threshold_deg.to_radians())
+        } else {
+            -1.0
+        };
+        Self { min_cosine, recovery_period, ignored: 0.0 }
+    }
+
+    /// Whether a reading `cosine` off its expected direction is used, `dt`
+    /// seconds after the previous one. Once ignored for the whole recovery
+    /// period, readings are used again whatever they are.
+    pub(crate) fn accepts(&mut self, cosine: f32, dt: f32) -> bool {
+        if cosine >= self.min_cosine || self.ignored >= self.recovery_period {
+            self.ignored = 0.0;
+            true
+        } else {
+            self.ignored += dt;
+            false
+        }
+    }
+}
+
+impl Convention {
+    /// The rotation from the NWU axes to these.
+    fn nwu_rotation(self) -> FusionQuaternion {
+        use core::f32::consts::FRAC_1_SQRT_2;
+
+        match self {
+            Convention::Nwu => FusionQuaternion::identity(),
+            // A quarter turn about the vertical
+            Convention::Enu => FusionQuaternion { w: FRAC_1_SQRT_2, x: 0.0, y: 0.0, z: FRAC_1_SQRT_2 },
+            // Half a turn about north
+            Convention::Ned => FusionQuaternion { w: 0.0, x: 1.0, y: 0.0, z: 0.0 },
+        }
+    }
+
+    /// An orientation in NWU axes, as estimated, given in these.
+    pub(crate) fn to_axes(self, nwu: FusionQuaternion) -> FusionQuaternion {
+        self.nwu_rotation() * nwu
+    }
+
+    /// An orientation given in these axes, in NWU ones.
+    pub(crate) fn to_nwu(self, quaternion: FusionQuaternion) -> FusionQuaternion {
+        conjugate(self.nwu_rotation()) * quaternion
+    }
+}
+
+pub(crate) fn conjugate(q: FusionQuaternion) -> FusionQuaternion {
+    FusionQuaternion { w: q.w, x: -q.x, y: -q.y, z: -q.z }
+}
+
+/// Normalized exactly, unlike Fusion's fast approximation.
+pub(crate) fn normalized(q: FusionQuaternion) -> FusionQuaternion {
+    let norm = libm::sqrtf(q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z);
+    FusionQuaternion { w: q.w / norm, x: q.x / norm, y: q.y / norm, z: q.z / norm }
+}
+
+pub(crate) fn unit(v: FusionVector) -> Option<FusionVector> {
+    let norm = libm::sqrtf(v.dot_product(&v));
+    (norm > 0.0).then(|| v * (1.0 / norm))
+}
+
+/// An earth frame vector `v` in the sensor frame of orientation `q`.
+pub(crate) fn to_sensor(v: FusionVector, q: FusionQuaternion) -> FusionVector {
+    rotate(v, conjugate(q))
+}
+
+/// The orientation after turning by `rate`, in rad/s in the sensor frame,
+/// for `dt` seconds.
+pub(crate) fn integrate(q: FusionQuaternion, rate: FusionVector, dt: f32) -> FusionQuaternion {
+    normalized(q + q * (rate * (0.5 * dt)))
+}
+
+/// Angle between two orientations, in degrees.
+pub fn angle_between(a: FusionQuaternion, b: FusionQuaternion) -> f32 {
+    // Normalized first, as Fusion's quaternions are off unit by up to 0.1%
+    let (a, b) = (normalized(a), normalized(b));
+    let dot = (a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z).abs().min(1.0);
+    2.0 * libm::acosf(dot).to_degrees()
+}
+
+#[cfg(test)]
+fn tilted_readings() -> (FusionVector, FusionVector) {
+    // Rolled by 40° with X pointing north, under a field pointing north and
+    // down
+    let (s, c) = (libm::sinf(0.7), libm::cosf(0.7));
+    (FusionVector::new(0.0, s, c), FusionVector::new(20.0, -40.0 * s, -40.0 * c))
+}
+
+#[test]
+fn test_names() {
+    for kind in EstimatorKind::ALL {
+        assert_eq!(EstimatorKind::from_name(kind.as_str()), Some(kind));
+    }
+    assert_eq!(EstimatorKind::from_name("madgwick"), None);
+}
+
+#[test]
+fn test_gain_schedule() {
+    let (acc, _) = tilted_readings();
+    let config = AhrsConfig::default();
+    let mut estimator = FusionEstimator::new(&config, 200, 1000.0);
+    estimator.update(FusionVector::zero(), acc, FusionVector::zero(), 1.0 / 200.0);
+    assert!(estimator.gain() > 9.0, "{}", estimator.gain());
+    for _ in 2..=100 {
+        estimator.update(FusionVector::zero(), acc, FusionVector::zero(), 1.0 / 200.0);
+    }
+    assert!(estimator.gain() > config.gain);
+    for _ in 101..=200 * 4 {
+        estimator.update(FusionVector::zero(), acc, FusionVector::zero(), 1.0 / 200.0);
+    }
+    assert_eq!(estimator.gain(), config.gain);
+
+    let mut schedule = GainSchedule::new(&config);
+    assert!(schedule.is_aligning());
+    assert_eq!(schedule.next(0.5), 5.25);
+    schedule.finish();
+    assert!(!schedule.is_aligning());
+}
+
+#[test]
+fn test_fusion_gain() {
+    // Down from the initial gain to the steady one, linearly over the
+    // alignment period, however Fusion schedules it by itself
+    let (acc, _) = tilted_readings();
+    let config = AhrsConfig { initial_gain: 8.0, alignment_period: 2.0, ..AhrsConfig::default() };
+    let mut estimator = FusionEstimator::new(&config, 200, 1000.0);
+    assert_eq!(estimator.gain(), 8.0);
+    for i in 1..=200 * 3 {
+        estimator.update(FusionVector::zero(), acc, FusionVector::zero(), 1.0 / 200.0);
+        let expected = (8.0 - 3.75 * i as f32 / 200.0).max(config.gain);
+        assert!((estimator.gain() - expected).abs() < 1e-3, "{i}: {}", estimator.gain());
+    }
+    assert!(!estimator.fusion.ahrs.initialising);
+
+    // Set to an attitude, it is done aligning
+    let mut estimator = FusionEstimator::new(&config, 200, 1000.0);
+    estimator.set_quaternion(FusionQuaternion::identity());
+    assert!(!estimator.fusion.ahrs.initialising);
+    estimator.update(FusionVector::zero(), acc, FusionVector::zero(), 1.0 / 200.0);
+    assert_eq!(estimator.gain(), config.gain);
+}
+
+#[test]
+fn test_alignment() {
+    use crate::alignment::attitude;
+
+    // Started tilted and still, each settles on gravity and north within a
+    // few seconds; set to the attitude, each stays there
+    let (acc, mag) = tilted_readings();
+    for convention in [Convention::Nwu, Convention::Enu, Convention::Ned] {
+        let config = AhrsConfig { convention, ..AhrsConfig::default() };
+        let truth = attitude(acc, mag, convention).unwrap();
+        for kind in EstimatorKind::ALL {
+            let mut estimator = kind.build(&config, 200, 1000.0);
+            for _ in 0..200 * 6 {
+                estimator.update(FusionVector::zero(), acc, mag, 1.0 / 200.0);
+            }
+            let error = angle_between(estimator.quaternion(), truth);
+            assert!(error < 2.0, "{kind:?} {convention:?} {error}°");
+
+            let mut estimator = kind.build(&config, 200, 1000.0);
+            estimator.set_quaternion(truth);
+            for _ in 0..200 {
+                estimator.update(FusionVector::zero(), acc, mag, 1.0 / 200.0);
+            }
+            let error = angle_between(estimator.quaternion(), truth);
+            assert!(error < 0.5, "{kind:?} {convention:?} {error}°");
+        }
+    }
+}
+
+#[test]
+fn test_rotation() {
+    // A quarter turn about the vertical in a second, then still: each
+    // follows the gyroscope, and ends facing west
+    let config = AhrsConfig::default();
+    let field = FusionVector::new(20.0, 0.0, -40.0);
+    let up = FusionVector::new(0.0, 0.0, 1.0);
+    for kind in EstimatorKind::ALL {
+        let mut estimator = kind.build(&config, 200, 1000.0);
+        estimator.set_quaternion(FusionQuaternion::identity());
+        let mut heading = 0.0f32;
+        for i in 0..400 {
+            let rate = if i < 200 { 90.0 } else { 0.0 };
+            heading += rate / 200.0;
+            let (s, c) = (libm::sinf(heading.to_radians()), libm::cosf(heading.to_radians()));
+            // The field as seen by the turned sensor
+            let mag = FusionVector::new(c * field.x, -s * field.x, field.z);
+            estimator.update(FusionVector::new(0.0, 0.0, rate), up, mag, 1.0 / 200.0);
+        }
+        let half = core::f32::consts::FRAC_1_SQRT_2;
+        let west = FusionQuaternion { w: half, x: 0.0, y: 0.0, z: half };
+        let error = angle_between(estimator.quaternion(), west);
+        assert!(error < 1.0, "{kind:?} {error}°");
+    }
+}
+
@@ -0,0 +1,197 @@
+use alloc::vec::Vec;
+use core::f32::consts::PI;
+
+use imu_fusion::FusionVector;
+
+use crate::analysis::SmoothingStage;
+
+/// Quality factor of a second order Butterworth section.
+pub const BUTTERWORTH_Q: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+    /// Unity gain at the center frequency.
+    BandPass,
+}
+
+/// One second order section, designed by bilinear transform of the analog
+/// prototype, prewarped at `frequency`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadDesign {
+    pub kind: FilterKind,
+    /// Cutoff, or center for band-pass, in Hz.
+    pub frequency: f32,
+    pub q: f32,
+}
+
+/// Normalized coefficients of `H(z) = (b0 + b1 z⁻¹ + b2 z⁻²) / (1 + a1 z⁻¹ + a2 z⁻²)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    pub b: [f32; 3],
+    pub a: [f32; 2],
+}
+
+impl BiquadDesign {
+    pub fn coefficients(&self, sampling_freq: f32) -> Coefficients {
+        assert!(0.0 < self.frequency && self.frequency < sampling_freq / 2.0);
+        // In terms of the prewarped frequency, which (unlike 1 - cos w0) keeps
+        // its precision for cutoffs far below the sampling rate. This module
+        // uses libm throughout, as micromath's approximations are too coarse
+        // for filter design.
+        let k = libm::tanf(PI * self.frequency / sampling_freq);
+        let norm = 1.0 / (1.0 + k / self.q + k * k);
+
+        let b = match self.kind {
+            FilterKind::HighPass => [norm, -2.0 * norm, norm],
+            FilterKind::LowPass => [k * k * norm, 2.0 * k * k * norm, k * k * norm],
+            FilterKind::BandPass => [k / self.q * norm, 0.0, -k / self.q * norm],
+        };
+        Coefficients {
+            b,
+            a: [2.0 * (k * k - 1.0) * norm, (1.0 - k / self.q + k * k) * norm],
+        }
+    }
+}
+
+impl Coefficients {
+    /// Gain at `frequency`, from the transfer function.
+    pub fn magnitude(&self, frequency: f32, sampling_freq: f32) -> f32 {
+        let w = 2.0 * PI * frequency / sampling_freq;
+        // Numerator and denominator evaluated at z = e^{jw}
+        let eval = |c: [f32; 3]| {
+            let re = c[0] + c[1] * libm::cosf(w) + c[2] * libm::cosf(2.0 * w);
+            let im = -c[1] * libm::sinf(w) - c[2] * libm::sinf(2.0 * w);
+            libm::sqrtf(re * re + im * im)
+        };
+        eval(self.b) / eval([1.0, self.a[0], self.a[1]])
+    }
+}
+
+/// A biquad filtering the three axes at once, in transposed direct form II.
+#[derive(Clone, Copy)]
+struct Biquad {
+    coefficients: Coefficients,
+    s1: FusionVector,
+    s2: FusionVector,
+}
+
+impl Biquad {
+    fn process(&mut self, x: FusionVector) -> FusionVector {
+        let Coefficients { b, a } = self.coefficients;
+        let y = x * b[0] + self.s1;
+        self.s1 = x * b[1] - y * a[0] + self.s2;
+        self.s2 = x * b[2] - y * a[1];
+        y
+    }
+}
+
+/// Cascade of biquads, usable in place of the moving average [`Smoothing`]
+/// stage of [`Analysis`](crate::Analysis).
+///
+/// [`Smoothing`]: crate::analysis::Smoothing
+pub struct FilterBank {
+    sections: Vec<Biquad>,
+}
+
+impl FilterBank {
+    pub fn new(sampling_freq: f32, designs: &[BiquadDesign]) -> Self {
+        let sections = designs.iter()
+            .map(|d| Biquad {
+                coefficients: d.coefficients(sampling_freq),
+                s1: FusionVector::zero(),
+                s2: FusionVector::zero(),
+            })
+            .collect();
+        Self { sections }
+    }
+
+    /// Butterworth filter of an even `order`, as `order / 2` sections.
+    pub fn butterworth(kind: FilterKind, order: usize, frequency: f32, sampling_freq: f32) -> Self {
+        assert!(order > 0 && order % 2 == 0);
+        assert!(kind != FilterKind::BandPass);
+        let designs: Vec<_> = (0..order / 2)
+            .map(|k| {
+                // Poles of the analog prototype, in conjugate pairs
+                let angle = PI * (2 * k + 1) as f32 / (2 * order) as f32;
+                BiquadDesign { kind, frequency, q: 1.0 / (2.0 * libm::sinf(angle)) }
+            })
+            .collect();
+        Self::new(sampling_freq, &designs)
+    }
+
+    pub fn magnitude(&self, frequency: f32, sampling_freq: f32) -> f32 {
+        self.sections.iter().map(|s| s.coefficients.magnitude(frequency, sampling_freq)).product()
+    }
+
+    pub fn process(&mut self, x: FusionVector) -> FusionVector {
+        self.sections.iter_mut().fold(x, |x, section| section.process(x))
+    }
+}
+
+impl SmoothingStage for FilterBank {
+    fn add_measurement(&mut self, linear_acceleration: FusionVector) -> FusionVector {
+        self.process(linear_acceleration)
+    }
+}
+
+#[cfg(test)]
+fn measured_gain(filter: &mut FilterBank, frequency: f32, sampling_freq: f32) -> f32 {
+    // Peak output once transients have died out
+    let n = (20.0 * sampling_freq / frequency).max(4.0 * sampling_freq) as usize;
+    let mut peak: f32 = 0.0;
+    for i in 0..n {
+        let x = libm::sinf(2.0 * PI * frequency * i as f32 / sampling_freq);
+        let y = filter.process(FusionVector::new(x, 0.0, -x));
+        if i > n / 2 {
+            peak = peak.max(libm::fabsf(y.x));
+            assert!(libm::fabsf(y.x + y.z) < 1e-4);
+        }
+    }
+    peak
+}
+
+/// Bilinear transform maps analog frequency `tan(π f / fs)` to digital `f`.
+#[cfg(test)]
+fn warped(frequency: f32, sampling_freq: f32) -> f32 {
+    libm::tanf(PI * frequency / sampling_freq)
+}
+
+#[test]
+fn test_butterworth_responses() {
+    const FS: f32 = 200.0;
+    const CUTOFF: f32 = 2.0;
+    const ORDER: usize = 4;
+
+    for frequency in [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 20.0] {
+        let ratio = warped(frequency, FS) / warped(CUTOFF, FS);
+        let reference_lp = 1.0 / libm::sqrtf(1.0 + libm::powf(ratio, 2.0 * ORDER as f32));
+        let reference_hp = 1.0 / libm::sqrtf(1.0 + libm::powf(1.0 / ratio, 2.0 * ORDER as f32));
+
+        for (kind, reference) in [(FilterKind::LowPass, reference_lp), (FilterKind::HighPass, reference_hp)] {
+            let mut filter = FilterBank::butterworth(kind, ORDER, CUTOFF, FS);
+            let designed = filter.magnitude(frequency, FS);
+            let measured = measured_gain(&mut filter, frequency, FS);
+
+            assert!(libm::fabsf(designed - reference) < 0.01, "{kind:?} at {frequency} Hz: {designed} vs {reference}");
+            assert!(libm::fabsf(measured - reference) < 0.02, "{kind:?} at {frequency} Hz: {measured} vs {reference}");
+        }
+    }
+}
+
+#[test]
+fn test_band_pass_response() {
+    const FS: f32 = 200.0;
+    let design = BiquadDesign { kind: FilterKind::BandPass, frequency: 2.0, q: 1.5 };
+
+    for frequency in [0.5, 1.0, 2.0, 3.0, 8.0] {
+        let ratio = warped(frequency, FS) / warped(design.frequency, FS);
+        let reference = 1.0 / libm::sqrtf(1.0 + libm::powf(design.q * (ratio - 1.0 / ratio), 2.0));
+        let mut filter = FilterBank::new(FS, &[design]);
+        let measured = measured_gain(&mut filter, frequency, FS);
+
+        assert!(libm::fabsf(filter.magnitude(frequency, FS) - reference) < 0.01, "{frequency} Hz");
+        assert!(libm::fabsf(measured - reference) < 0.02, "{frequency} Hz: {measured} vs {reference}");
+    }
+}
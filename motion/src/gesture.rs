@@ -0,0 +1,286 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{analysis::MovementDirection, time::Timestamp};
+
+use MovementDirection::*;
+
+/// Whichever way the wearer faces, as nothing tells the firmware which.
+const HORIZONTAL: &[MovementDirection] = &[Forward, Back, Left, Right];
+const VERTICAL: &[MovementDirection] = &[Up, Down];
+const DIAGONAL: &[MovementDirection] = &[UpLeft, UpRight, DownLeft, DownRight];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Side to side, a few times, along any horizontal line.
+    Wave,
+    /// Up and down, a few times.
+    Pump,
+    /// Two crossing diagonal strokes.
+    Cross,
+    /// No movement for a while.
+    HoldStill,
+}
+
+impl Gesture {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Gesture::Wave => "wave",
+            Gesture::Pump => "pump",
+            Gesture::Cross => "x",
+            Gesture::HoldStill => "still",
+        }
+    }
+}
+
+/// Condition on one stroke of a gesture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrokeMatch {
+    OneOf(&'static [MovementDirection]),
+    /// Back the way the previous stroke went.
+    Opposite,
+    /// Along the other diagonal than the previous stroke, either way.
+    Crossing,
+}
+
+impl StrokeMatch {
+    fn matches(&self, direction: MovementDirection, previous: Option<MovementDirection>) -> bool {
+        match (*self, previous) {
+            (StrokeMatch::OneOf(accepted), _) => accepted.contains(&direction),
+            (StrokeMatch::Opposite, Some(previous)) => direction == previous.opposite(),
+            (StrokeMatch::Crossing, Some(previous)) => {
+                DIAGONAL.contains(&previous) && DIAGONAL.contains(&direction)
+                    && direction != previous && direction != previous.opposite()
+            }
+            (_, None) => false,
+        }
+    }
+}
+
+/// A gesture as a sequence of strokes, none of them starting later than
+/// `max_gap` seconds after the previous one ended.
+#[derive(Debug, Clone)]
+pub struct GestureSpec {
+    pub gesture: Gesture,
+    pub strokes: Vec<StrokeMatch>,
+    pub max_gap: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct GestureConfig {
+    /// Shortest run of a direction, in seconds, that counts as a stroke.
+    pub min_stroke: f32,
+    /// Seconds without any movement reported as `HoldStill`.
+    pub hold_still: f32,
+    pub specs: Vec<GestureSpec>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        use StrokeMatch::*;
+
+        Self {
+            min_stroke: 0.08,
+            hold_still: 3.0,
+            specs: vec![
+                GestureSpec {
+                    gesture: Gesture::Wave,
+                    strokes: vec![OneOf(HORIZONTAL), Opposite, Opposite],
+                    max_gap: 0.6,
+                },
+                GestureSpec {
+                    gesture: Gesture::Pump,
+                    strokes: vec![OneOf(VERTICAL), Opposite, Opposite, Opposite],
+                    max_gap: 0.6,
+                },
+                GestureSpec {
+                    gesture: Gesture::Cross,
+                    strokes: vec![OneOf(DIAGONAL), Crossing],
+                    max_gap: 1.0,
+                },
+            ],
+        }
+    }
+}
+
+/// A run of samples reporting the same direction.
+#[derive(Debug, Clone, Copy)]
+struct Stroke<T> {
+    direction: MovementDirection,
+    start: T,
+    end: T,
+}
+
+/// Progress of one [`GestureSpec`].
+struct Matcher<T> {
+    matched: usize,
+    previous: Option<MovementDirection>,
+    last_end: Option<T>,
+}
+
+impl<T: Timestamp> Matcher<T> {
+    fn reset(&mut self) {
+        self.matched = 0;
+        self.previous = None;
+        self.last_end = None;
+    }
+
+    fn advance(&mut self, spec: &GestureSpec, stroke: &Stroke<T>) -> bool {
+        if let Some(last_end) = self.last_end {
+            if stroke.start.secs_since(last_end) > spec.max_gap {
+                self.reset();
+            }
+        }
+        if !spec.strokes[self.matched].matches(stroke.direction, self.previous) {
+            self.reset();
+            // The stroke breaking a sequence may well start a new one
+            if !spec.strokes[0].matches(stroke.direction, None) {
+                return false;
+            }
+        }
+
+        self.matched += 1;
+        self.previous = Some(stroke.direction);
+        self.last_end = Some(stroke.end);
+        if self.matched == spec.strokes.len() {
+            self.reset();
+            return true;
+        }
+        false
+    }
+}
+
+/// Turns the per-sample output of `Analysis` into gestures.
+pub struct GestureRecognizer<T: Timestamp> {
+    config: GestureConfig,
+    matchers: Vec<Matcher<T>>,
+    current: Option<Stroke<T>>,
+    last_movement: T,
+    still_reported: bool,
+}
+
+impl<T: Timestamp> GestureRecognizer<T> {
+    pub fn new(config: GestureConfig, now: T) -> Self {
+        let matchers = config.specs.iter()
+            .map(|_| Matcher { matched: 0, previous: None, last_end: None })
+            .collect();
+        Self {
+            config,
+            matchers,
+            current: None,
+            last_movement: now,
+            still_reported: false,
+        }
+    }
+
+    pub fn update(&mut self, time: T, direction: Option<MovementDirection>) -> Option<Gesture> {
+        let mut recognized = None;
+
+        match (self.current, direction) {
+            (Some(stroke), Some(direction)) if stroke.direction == direction => {
+                self.current = Some(Stroke { end: time, ..stroke });
+            }
+            (current, _) => {
+                if let Some(stroke) = current {
+                    recognized = self.stroke_done(&stroke);
+                }
+                self.current = direction.map(|direction| Stroke { direction, start: time, end: time });
+            }
+        }
+
+        if direction.is_some() {
+            self.last_movement = time;
+            self.still_reported = false;
+        } else if !self.still_reported && time.secs_since(self.last_movement) >= self.config.hold_still {
+            self.still_reported = true;
+            recognized = recognized.or(Some(Gesture::HoldStill));
+        }
+
+        recognized
+    }
+
+    fn stroke_done(&mut self, stroke: &Stroke<T>) -> Option<Gesture> {
+        if stroke.end.secs_since(stroke.start) < self.config.min_stroke {
+            return None;
+        }
+        let mut recognized = None;
+        for (spec, matcher) in self.config.specs.iter().zip(self.matchers.iter_mut()) {
+            if matcher.advance(spec, stroke) && recognized.is_none() {
+                recognized = Some(spec.gesture);
+            }
+        }
+        if recognized.is_some() {
+            // Strokes of a completed gesture do not count towards another one
+            self.matchers.iter_mut().for_each(Matcher::reset);
+        }
+        recognized
+    }
+}
+
+#[cfg(test)]
+fn feed(recognizer: &mut GestureRecognizer<f32>, t: &mut f32, strokes: &[(Option<MovementDirection>, f32)])
+    -> Vec<Gesture> {
+    const DT: f32 = 0.005;
+    let mut gestures = Vec::new();
+    for &(direction, duration) in strokes {
+        let end = *t + duration;
+        while *t < end {
+            gestures.extend(recognizer.update(*t, direction));
+            *t += DT;
+        }
+    }
+    gestures
+}
+
+#[test]
+fn test_wave_pump_and_cross() {
+    let mut recognizer = GestureRecognizer::new(GestureConfig::default(), 0.0);
+    let mut t = 0.0;
+
+    let wave = feed(&mut recognizer, &mut t, &[
+        (Some(Left), 0.2), (Some(Right), 0.2), (None, 0.1), (Some(Left), 0.2), (None, 0.1),
+    ]);
+    assert_eq!(wave, [Gesture::Wave]);
+
+    let pump = feed(&mut recognizer, &mut t, &[
+        (Some(Up), 0.2), (Some(Down), 0.2), (Some(Up), 0.2), (Some(Down), 0.2), (None, 0.1),
+    ]);
+    assert_eq!(pump, [Gesture::Pump]);
+
+    let cross = feed(&mut recognizer, &mut t, &[
+        (Some(DownRight), 0.3), (None, 0.3), (Some(DownLeft), 0.3), (None, 0.1),
+    ]);
+    assert_eq!(cross, [Gesture::Cross]);
+}
+
+#[test]
+fn test_gestures_time_out() {
+    let mut recognizer = GestureRecognizer::new(GestureConfig::default(), 0.0);
+    let mut t = 0.0;
+
+    let gestures = feed(&mut recognizer, &mut t, &[
+        (Some(Left), 0.2), (Some(Right), 0.2), (None, 1.0), (Some(Left), 0.2), (None, 0.1),
+    ]);
+    assert!(gestures.is_empty());
+}
+
+#[test]
+fn test_short_blips_are_not_strokes() {
+    let mut recognizer = GestureRecognizer::new(GestureConfig::default(), 0.0);
+    let mut t = 0.0;
+
+    let gestures = feed(&mut recognizer, &mut t, &[
+        (Some(Left), 0.2), (Some(Right), 0.02), (Some(Left), 0.2), (None, 0.1),
+    ]);
+    assert!(gestures.is_empty());
+}
+
+#[test]
+fn test_hold_still_reported_once() {
+    let mut recognizer = GestureRecognizer::new(GestureConfig::default(), 0.0);
+    let mut t = 0.0;
+
+    let gestures = feed(&mut recognizer, &mut t, &[
+        (None, 7.0), (Some(Up), 0.2), (None, 3.5),
+    ]);
+    assert_eq!(gestures, [Gesture::HoldStill, Gesture::HoldStill]);
+}
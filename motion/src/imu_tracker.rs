@@ -0,0 +1,408 @@
+use alloc::boxed::Box;
+
+use imu_fusion::{FusionAhrsSettings, FusionConvention,
+                 FusionQuaternion, FusionVector,
+                 //FusionEuler,
+};
+
+use crate::{
+    alignment::attitude,
+    calibration::ImuCalibration,
+    estimator::{EstimatorKind, OrientationEstimator},
+    time::Timestamp,
+};
+
+/// Earth axes the orientation is given in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convention {
+    /// North-West-Up
+    Nwu,
+    /// East-North-Up
+    Enu,
+    /// North-East-Down
+    Ned,
+}
+
+impl Convention {
+    pub fn fusion(&self) -> FusionConvention {
+        match self {
+            Convention::Nwu => FusionConvention::NWU,
+            Convention::Enu => FusionConvention::ENU,
+            Convention::Ned => FusionConvention::NED,
+        }
+    }
+}
+
+/// Settings of the AHRS algorithm fusing the readings into an orientation.
+///
+/// The gain weighs the accelerometer and magnetometer against the gyroscope.
+/// It starts at `initial_gain`, so that the orientation quickly settles on
+/// gravity and north after a start, and decays linearly to `gain` over
+/// `alignment_period`; the accelerometer is never rejected meanwhile. Each
+/// [`EstimatorKind`] makes what it can of these.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AhrsConfig {
+    pub estimator: EstimatorKind,
+    pub convention: Convention,
+    /// Steady state gain, 0 for the gyroscope alone, with no alignment
+    /// either.
+    pub gain: f32,
+    pub initial_gain: f32,
+    /// In seconds.
+    pub alignment_period: f32,
+    /// Error of the accelerometer against the orientation over which it is
+    /// ignored, in degrees, 0 to never ignore it.
+    pub acc_rejection: f32,
+    /// The same for the magnetometer.
+    pub mag_rejection: f32,
+    /// Time a sensor may be ignored for before the orientation is forced back
+    /// onto it, in seconds, 0 to never ignore any.
+    pub recovery_period: f32,
+}
+
+impl Default for AhrsConfig {
+    /// The settings of the firmware.
+    fn default() -> Self {
+        Self {
+            estimator: EstimatorKind::Fusion,
+            convention: Convention::Nwu,
+            gain: 0.5,
+            initial_gain: 10.0,
+            alignment_period: 1.0,
+            acc_rejection: 10.0,
+            mag_rejection: 10.0,
+            recovery_period: 5.0,
+        }
+    }
+}
+
+impl AhrsConfig {
+    pub(crate) fn settings(&self, sampling_freq: u32, gyr_range: f32) -> FusionAhrsSettings {
+        let mut settings = FusionAhrsSettings::new();
+        settings.convention = self.convention.fusion();
+        settings.gain = self.gain;
+        settings.gyr_range = gyr_range;
+        settings.acc_rejection = self.acc_rejection;
+        settings.mag_rejection = self.mag_rejection;
+        settings.recovery_trigger_period = (self.recovery_period * sampling_freq as f32) as i32;
+        settings
+    }
+}
+
+/// Orientation of the device in the earth axes of its [`Convention`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orientation {
+    /// `w`, `x`, `y` and `z` of the unit quaternion.
+    pub quaternion: [f32; 4],
+    /// Roll, pitch and yaw, in degrees.
+    pub euler: [f32; 3],
+    /// Where the X axis points, in degrees clockwise from magnetic north.
+    pub heading: f32,
+}
+
+pub struct ImuTracker<T: Timestamp> {
+    time: T,
+    estimator: Box<dyn OrientationEstimator>,
+    calibration: ImuCalibration,
+    //pub euler: FusionEuler,
+    pub latest_delta: f32,
+    pub quaternion: FusionQuaternion,
+    /// Acceleration without gravity, in g, in NWU axes whatever the
+    /// convention, as `Analysis` takes it.
+    pub linear_accel: FusionVector,
+    convention: Convention,
+}
+
+impl<T: Timestamp> ImuTracker<T> {
+    /// A tracker for readings taken `sampling_freq` times a second by a
+    /// gyroscope reading up to `gyr_range` degrees/s.
+    pub fn new(sampling_freq: u32, now: T, gyr_range: f32, config: &AhrsConfig, calibration: &ImuCalibration) -> Self {
+        Self {
+            time: now,
+            estimator: config.estimator.build(config, sampling_freq, gyr_range),
+            calibration: *calibration,
+            //euler: FusionEuler::zero(),
+            latest_delta: 0f32,
+            quaternion: FusionQuaternion::identity(),
+            linear_accel: FusionVector::zero(),
+            convention: config.convention,
+        }
+    }
+
+    /// Starts tracking from the attitude given by the mean readings of a
+    /// device lying still (see [`AttitudeAligner`]), rather than from level
+    /// and facing north, skipping the high gain of the alignment period.
+    /// Returns whether the readings told an attitude.
+    ///
+    /// [`AttitudeAligner`]: crate::alignment::AttitudeAligner
+    pub fn align(&mut self, imu_accel: FusionVector, imu_mag: FusionVector) -> bool {
+        let Some(quaternion) = attitude(self.calibrated_accel(imu_accel), self.calibrated_mag(imu_mag), self.convention) else {
+            return false;
+        };
+        self.estimator.set_quaternion(quaternion);
+        self.quaternion = quaternion;
+        true
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        let q = self.quaternion;
+        let angle = q.euler().angle;
+        // Yaw is counter-clockwise from the first axis, but for NED
+        let heading = match self.convention {
+            Convention::Nwu => -angle.yaw,
+            Convention::Enu => 90.0 - angle.yaw,
+            Convention::Ned => angle.yaw,
+        };
+        Orientation {
+            quaternion: [q.w, q.x, q.y, q.z],
+            euler: [angle.roll, angle.pitch, angle.yaw],
+            heading: if heading < 0.0 { heading + 360.0 } else { heading },
+        }
+    }
+
+    /// Replaces the calibration the readings are corrected with.
+    pub fn set_calibration(&mut self, calibration: &ImuCalibration) {
+        self.calibration = *calibration;
+    }
+
+    /// The accelerometer reading as corrected by the calibration, in g.
+    pub fn calibrated_accel(&self, imu_accel: FusionVector) -> FusionVector {
+        self.calibration.correct_acc(imu_accel)
+    }
+
+    /// The magnetometer reading as corrected by the calibration.
+    pub fn calibrated_mag(&self, imu_mag: FusionVector) -> FusionVector {
+        self.calibration.correct_mag(imu_mag)
+    }
+
+    pub fn update(&mut self, time: T, imu_accel: FusionVector, imu_gyro: FusionVector, imu_mag: FusionVector) {
+        // Gets: acceleration in units of standard gravity
+        //       angular rotation in degrees/sec
+        let delta = time.secs_since(self.time);
+        self.time = time;
+        self.latest_delta = delta;
+
+        let accel = self.calibrated_accel(imu_accel);
+        let gyro = self.calibration.correct_gyr(imu_gyro);
+        self.estimator.update(gyro, accel, self.calibrated_mag(imu_mag), delta);
+        self.compute(accel);
+    }
+
+    pub fn compute(&mut self, imu_accel: FusionVector) {
+        // Gets heading in units of degrees
+        //self.euler = self.fusion.euler();
+
+        /* These doesn't seem to work well on the imu-rs implementation
+        self.earth_accel = self.fusion.ahrs.earth_acc();
+        self.linear_acc = self.fusion.ahrs.linear_acc();
+        */
+        self.quaternion = self.estimator.quaternion();
+        let rotated = rotate(imu_accel, self.convention.to_nwu(self.quaternion));
+
+        self.linear_accel.x = rotated.x;
+        self.linear_accel.y = rotated.y;
+        self.linear_accel.z = rotated.z - 1.0;
+    }
+
+}
+
+/// Brings a magnetometer reading into the accelerometer/gyroscope frame.
+///
+/// On the ICM-20948 the magnetometer axes are reflected along X axis, as per
+/// the datasheet.
+pub fn align_magnetometer(raw: FusionVector) -> FusionVector {
+    FusionVector::new(raw.x, -raw.y, -raw.z)
+}
+
+/* The rotate() function below implements this operation
+   using the minimal amount of multiplications.
+
+fn bruteforce_rotate(vec: FusionVector, q: FusionQuaternion) -> FusionVector {
+    let qn = q.normalize();
+    let rot_q = (qn * vec) * conjugate(&qn);
+    FusionVector {
+        x: rot_q.x, y: rot_q.y, z: rot_q.z
+    }
+}
+fn conjugate(q: &FusionQuaternion) -> FusionQuaternion {
+    FusionQuaternion {
+        w: q.w, x: -q.x, y: -q.y, z: -q.z
+    }
+}
+*/
+
+pub fn rotate(v: FusionVector, q: FusionQuaternion) -> FusionVector {
+
+    let qn = q.normalize();
+
+    let ww = qn.w * qn.w;
+    let xx = qn.x * qn.x;
+    let yy = qn.y * qn.y;
+    let zz = qn.z * qn.z;
+    let wx = qn.w * qn.x;
+    let wy = qn.w * qn.y;
+    let wz = qn.w * qn.z;
+    let xy = qn.x * qn.y;
+    let xz = qn.x * qn.z;
+    let yz = qn.y * qn.z;
+
+    // Formula from http://www.euclideanspace.com/maths/algebra/realNormedAlgebra/quaternions/transforms/index.htm
+    // p2.x = w*w*v.x + 2*y*w*v.z - 2*z*w*v.y + x*x*v.x + 2*y*x*v.y + 2*z*x*v.z - z*z*v.x - y*y*v.x;
+    // p2.y = 2*x*y*v.x + y*y*v.y + 2*z*y*v.z + 2*w*z*v.x - z*z*v.y + w*w*v.y - 2*x*w*v.z - x*x*v.y;
+    // p2.z = 2*x*z*v.x + 2*y*z*v.y + z*z*v.z - 2*w*y*v.x - y*y*v.z + 2*w*x*v.y - x*x*v.z + w*w*v.z;
+
+    let x = ww*v.x + 2.0*wy*v.z - 2.0*wz*v.y +
+            xx*v.x + 2.0*xy*v.y + 2.0*xz*v.z -
+            zz*v.x - yy*v.x;
+    let y = 2.0*xy*v.x + yy*v.y + 2.0*yz*v.z +
+            2.0*wz*v.x - zz*v.y + ww*v.y -
+            2.0*wx*v.z - xx*v.y;
+    let z = 2.0*xz*v.x + 2.0*yz*v.y + zz*v.z -
+            2.0*wy*v.x - yy*v.z + 2.0*wx*v.y -
+            xx*v.z + ww*v.z;
+
+    FusionVector::new(x, y, z)
+}
+
+#[test]
+fn test_rotate_quarter_turn_about_z() {
+    use core::f32::consts::FRAC_1_SQRT_2;
+
+    let q = FusionQuaternion { w: FRAC_1_SQRT_2, x: 0.0, y: 0.0, z: FRAC_1_SQRT_2 };
+    let rotated = rotate(FusionVector::new(1.0, 0.0, 0.0), q);
+
+    assert!(rotated.x.abs() < 1e-3);
+    assert!((rotated.y - 1.0).abs() < 1e-3);
+    assert!(rotated.z.abs() < 1e-3);
+}
+
+#[cfg(test)]
+fn test_calibration() -> ImuCalibration {
+    use crate::calibration::{Offset, Sensitivity};
+
+    ImuCalibration {
+        acc_sensitivity: Sensitivity([1.02, 0.98, 1.0]),
+        acc_offset: Offset([0.05, -0.03, 0.1]),
+        gyr_offset: Offset([2.0, -1.5, 0.5]),
+        ..ImuCalibration::default()
+    }
+}
+
+#[test]
+fn test_calibrated_accel() {
+    let tracker = ImuTracker::new(200, 0.0f64, 1000.0, &AhrsConfig::default(), &ImuCalibration::default());
+    let raw = FusionVector::new(0.1, -0.2, 0.97);
+    let calibrated = tracker.calibrated_accel(raw);
+    assert!((calibrated.x - raw.x).abs() < 1e-6 && (calibrated.y - raw.y).abs() < 1e-6
+            && (calibrated.z - raw.z).abs() < 1e-6);
+
+    // Offset first, then scaled
+    let tracker = ImuTracker::new(200, 0.0f64, 1000.0, &AhrsConfig::default(), &test_calibration());
+    let calibrated = tracker.calibrated_accel(FusionVector::new(0.55, 0.97, 1.1));
+    assert!((calibrated.x - 0.51).abs() < 1e-5, "{}", calibrated.x);
+    assert!((calibrated.y - 0.98).abs() < 1e-5, "{}", calibrated.y);
+    assert!((calibrated.z - 1.0).abs() < 1e-5, "{}", calibrated.z);
+}
+
+#[test]
+fn test_still_device_with_calibration() {
+    // Lying flat and still, as read by the miscalibrated IMU
+    let raw_accel = FusionVector::new(0.05, -0.03, 0.1 + 1.0);
+    let raw_gyro = FusionVector::new(2.0, -1.5, 0.5);
+    let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &AhrsConfig::default(), &test_calibration());
+    for i in 1..=400 {
+        tracker.update(i as f64 / 200.0, raw_accel, raw_gyro, FusionVector::zero());
+    }
+
+    let l = tracker.linear_accel;
+    assert!(l.x.abs() < 0.01 && l.y.abs() < 0.01 && l.z.abs() < 0.01, "{} {} {}", l.x, l.y, l.z);
+    let q = tracker.quaternion;
+    assert!((q.w.abs() - 1.0).abs() < 1e-3, "{} {} {} {}", q.w, q.x, q.y, q.z);
+}
+
+#[test]
+fn test_estimators() {
+    // Started rolled by 40° and kept still, within a degree before the gain
+    // settles, whichever the estimator
+    let accel = FusionVector::new(0.0, libm::sinf(0.7), libm::cosf(0.7));
+    for estimator in EstimatorKind::ALL {
+        let config = AhrsConfig { estimator, ..AhrsConfig::default() };
+        let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &config, &ImuCalibration::default());
+        for i in 1..=100 {
+            tracker.update(i as f64 / 200.0, accel, FusionVector::zero(), FusionVector::zero());
+        }
+        let l = tracker.linear_accel;
+        assert!(l.x.abs() < 0.02 && l.y.abs() < 0.02 && l.z.abs() < 0.02, "{estimator:?} {} {} {}", l.x, l.y, l.z);
+
+        // The gyroscope alone never finds gravity
+        let config = AhrsConfig { gain: 0.0, ..config };
+        let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &config, &ImuCalibration::default());
+        for i in 1..=200 * 4 {
+            tracker.update(i as f64 / 200.0, accel, FusionVector::zero(), FusionVector::zero());
+        }
+        assert!(tracker.linear_accel.y.abs() > 0.5, "{estimator:?}");
+    }
+}
+
+#[test]
+fn test_aligned_start() {
+    // Rolled by 40° and still, with the gyroscope alone from the start
+    let accel = FusionVector::new(0.0, libm::sinf(0.7), libm::cosf(0.7));
+    let config = AhrsConfig { gain: 0.0, ..AhrsConfig::default() };
+    let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &config, &ImuCalibration::default());
+    assert!(!tracker.align(FusionVector::zero(), FusionVector::zero()));
+    assert!(tracker.align(accel, FusionVector::zero()));
+    for i in 1..=400 {
+        tracker.update(i as f64 / 200.0, accel, FusionVector::zero(), FusionVector::zero());
+    }
+    let l = tracker.linear_accel;
+    assert!(l.x.abs() < 1e-3 && l.y.abs() < 1e-3 && l.z.abs() < 1e-3, "{} {} {}", l.x, l.y, l.z);
+}
+
+#[test]
+fn test_linear_accel_conventions() {
+    // Lying flat and still, then lifted at half a g
+    let readings = [
+        (Convention::Nwu, FusionVector::new(0.0, 0.0, 1.0), FusionVector::new(20.0, 0.0, -40.0)),
+        (Convention::Enu, FusionVector::new(0.0, 0.0, 1.0), FusionVector::new(20.0, 0.0, -40.0)),
+        // Z down
+        (Convention::Ned, FusionVector::new(0.0, 0.0, -1.0), FusionVector::new(20.0, 0.0, 40.0)),
+    ];
+    for (convention, acc, mag) in readings {
+        let config = AhrsConfig { convention, ..AhrsConfig::default() };
+        let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &config, &ImuCalibration::default());
+        for i in 1..=400 {
+            tracker.update(i as f64 / 200.0, acc, FusionVector::zero(), mag);
+        }
+        let l = tracker.linear_accel;
+        assert!(l.x.abs() < 0.01 && l.y.abs() < 0.01 && l.z.abs() < 0.01, "{convention:?} {} {} {}", l.x, l.y, l.z);
+
+        // Up in NWU axes, with the gyroscope alone so that the lift does not
+        // tilt the orientation
+        let config = AhrsConfig { gain: 0.0, ..config };
+        let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &config, &ImuCalibration::default());
+        assert!(tracker.align(acc, mag));
+        tracker.update(1.0 / 200.0, acc * 1.5, FusionVector::zero(), mag);
+        let l = tracker.linear_accel;
+        assert!(l.x.abs() < 1e-3 && l.y.abs() < 1e-3 && (l.z - 0.5).abs() < 1e-3, "{convention:?} {} {} {}", l.x, l.y, l.z);
+    }
+}
+
+#[test]
+fn test_orientation() {
+    // Lying flat, turned a quarter counter-clockwise from north: X points west
+    let readings = [
+        (Convention::Nwu, FusionVector::new(0.0, 0.0, 1.0), FusionVector::new(0.0, -20.0, -40.0)),
+        (Convention::Enu, FusionVector::new(0.0, 0.0, 1.0), FusionVector::new(0.0, -20.0, -40.0)),
+        // Z down, so Y points north
+        (Convention::Ned, FusionVector::new(0.0, 0.0, -1.0), FusionVector::new(0.0, 20.0, 40.0)),
+    ];
+    for (convention, acc, mag) in readings {
+        let config = AhrsConfig { convention, ..AhrsConfig::default() };
+        let mut tracker = ImuTracker::new(200, 0.0f64, 1000.0, &config, &ImuCalibration::default());
+        assert!(tracker.align(acc, mag));
+        let orientation = tracker.orientation();
+        assert!((orientation.heading - 270.0).abs() < 0.1, "{convention:?} {orientation:?}");
+        assert!(orientation.euler[0].abs() < 0.1 && orientation.euler[1].abs() < 0.1, "{convention:?} {orientation:?}");
+    }
+}
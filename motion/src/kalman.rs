@@ -0,0 +1,260 @@
+//! Error-state Kalman filter of the orientation and the gyroscope offset.
+//!
+//! The orientation is integrated from the gyroscope, minus the offset, and
+//! the filter only tracks the covariance of the small rotation error, in the
+//! sensor frame, and of the offset error. The accelerometer, taken as
+//! gravity, then corrects the tilt, and the magnetometer the heading alone;
+//! each correction is weighed by how uncertain the orientation has grown, so
+//! that the filter leans on the sensors right after a start and on the
+//! gyroscope once settled.
+
+use imu_fusion::{FusionQuaternion, FusionVector};
+
+use crate::{
+    estimator::{integrate, to_sensor, unit, GainSchedule, OrientationEstimator, Rejection},
+    imu_tracker::{rotate, AhrsConfig, Convention},
+    linalg::solve,
+};
+
+/// Angle random walk of the gyroscope, in rad/√s.
+const GYR_NOISE: f64 = 0.002;
+
+/// Rate random walk of the gyroscope offset, in rad/s/√s.
+const OFFSET_NOISE: f64 = 1e-4;
+
+/// Noise of the accelerometer as a direction, in rad, grown by how far the
+/// reading is from 1 g.
+const ACC_NOISE: f64 = 0.05;
+
+/// Noise of the heading the magnetometer tells, in rad.
+const MAG_NOISE: f64 = 0.1;
+
+/// Initial uncertainty of the orientation, in rad, when not set.
+const INITIAL_ANGLE: f64 = 1.0;
+
+/// Initial uncertainty of the gyroscope offset, in rad/s (3°/s).
+const INITIAL_OFFSET: f64 = 0.05;
+
+/// Smallest norm, as a fraction of the field, of its horizontal part for it
+/// to tell a heading.
+const MIN_HORIZONTAL: f32 = 0.1;
+
+const UP: FusionVector = FusionVector { x: 0.0, y: 0.0, z: 1.0 };
+
+type Matrix = [[f64; 6]; 6];
+
+/// The accelerometer and magnetometer are rejected as the [`AhrsConfig`]
+/// says, except while the orientation is more uncertain than the rejection
+/// angle. Its gain only tells whether they are used at all, with 0 for the
+/// gyroscope alone; during its alignment period they are never rejected.
+pub struct KalmanFilter {
+    convention: Convention,
+    /// From the sensor frame to NWU axes.
+    quaternion: FusionQuaternion,
+    /// In rad/s.
+    offset: [f64; 3],
+    /// Of the rotation error, then of the offset error.
+    covariance: Matrix,
+    schedule: GainSchedule,
+    corrected: bool,
+    acc_rejection: Rejection,
+    mag_rejection: Rejection,
+    /// Rejection angles, in degrees.
+    acc_threshold: f32,
+    mag_threshold: f32,
+}
+
+impl KalmanFilter {
+    pub fn new(config: &AhrsConfig) -> Self {
+        let mut covariance = [[0.0; 6]; 6];
+        for i in 0..3 {
+            covariance[i][i] = INITIAL_ANGLE * INITIAL_ANGLE;
+            covariance[i + 3][i + 3] = INITIAL_OFFSET * INITIAL_OFFSET;
+        }
+        Self {
+            convention: config.convention,
+            quaternion: FusionQuaternion::identity(),
+            offset: [0.0; 3],
+            covariance,
+            schedule: GainSchedule::new(config),
+            corrected: config.gain > 0.0,
+            acc_rejection: Rejection::new(config.acc_rejection, config.recovery_period),
+            mag_rejection: Rejection::new(config.mag_rejection, config.recovery_period),
+            acc_threshold: config.acc_rejection,
+            mag_threshold: config.mag_rejection,
+        }
+    }
+
+    /// The gyroscope offset found so far, in degrees/s.
+    pub fn offset(&self) -> FusionVector {
+        let [x, y, z] = self.offset.map(|v| (v as f32).to_degrees());
+        FusionVector::new(x, y, z)
+    }
+
+    /// Standard deviation of the rotation error, in degrees.
+    pub fn uncertainty(&self) -> f32 {
+        let variance = self.covariance[0][0] + self.covariance[1][1] + self.covariance[2][2];
+        (libm::sqrt(variance) as f32).to_degrees()
+    }
+
+    fn predict(&mut self, rate: [f64; 3], dt: f64) {
+        // F = [[I - [ω×] dt, -I dt], [0, I]]
+        let mut f = identity();
+        for (i, row) in skew(rate).iter().enumerate() {
+            for j in 0..3 {
+                f[i][j] -= row[j] * dt;
+            }
+            f[i][i + 3] = -dt;
+        }
+        let mut covariance = multiply(&multiply(&f, &self.covariance), &transpose(&f));
+        for i in 0..3 {
+            covariance[i][i] += GYR_NOISE * GYR_NOISE * dt;
+            covariance[i + 3][i + 3] += OFFSET_NOISE * OFFSET_NOISE * dt;
+        }
+        self.covariance = covariance;
+    }
+
+    /// Corrects the state by the `N` residuals of a measurement with rows `h`
+    /// and independent noises `variance`.
+    fn correct<const N: usize>(&mut self, h: [[f64; 6]; N], residual: [f64; N], variance: f64) {
+        // K = P Hᵀ S⁻¹, column by column of its transpose S⁻¹ H P
+        let hp: [[f64; 6]; N] = h.map(|row| core::array::from_fn(|j| (0..6).map(|k| row[k] * self.covariance[k][j]).sum()));
+        let mut s: [[f64; N]; N] = core::array::from_fn(|i| core::array::from_fn(|j| (0..6).map(|k| hp[i][k] * h[j][k]).sum()));
+        for (i, row) in s.iter_mut().enumerate() {
+            row[i] += variance;
+        }
+        let mut gain = [[0.0; N]; 6];
+        for j in 0..6 {
+            let Some(column) = solve(s, core::array::from_fn(|i| hp[i][j])) else {
+                return;
+            };
+            gain[j] = column;
+        }
+
+        let error: [f64; 6] = core::array::from_fn(|i| (0..N).map(|k| gain[i][k] * residual[k]).sum());
+        // q ⊗ (1, δθ / 2)
+        self.quaternion = integrate(self.quaternion, FusionVector::new(error[0] as f32, error[1] as f32, error[2] as f32), 1.0);
+        for i in 0..3 {
+            self.offset[i] += error[i + 3];
+        }
+
+        // P = (I - K H) P, kept symmetric
+        let mut covariance = self.covariance;
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value -= (0..N).map(|k| gain[i][k] * hp[k][j]).sum::<f64>();
+            }
+        }
+        let transposed = transpose(&covariance);
+        self.covariance = core::array::from_fn(|i| core::array::from_fn(|j| 0.5 * (covariance[i][j] + transposed[i][j])));
+    }
+
+    /// Whether the orientation is more uncertain than `threshold`, in
+    /// degrees, so that no reading can be told off by that much.
+    fn is_uncertain(&self, threshold: f32) -> bool {
+        self.uncertainty() > threshold
+    }
+}
+
+impl OrientationEstimator for KalmanFilter {
+    fn update(&mut self, gyr: FusionVector, acc: FusionVector, mag: FusionVector, dt: f32) {
+        let aligning = self.schedule.is_aligning();
+        self.schedule.next(dt);
+        let rate = [gyr.x, gyr.y, gyr.z].map(|v| v.to_radians() as f64);
+        let rate: [f64; 3] = core::array::from_fn(|i| rate[i] - self.offset[i]);
+        let [x, y, z] = rate.map(|v| v as f32);
+        self.quaternion = integrate(self.quaternion, FusionVector::new(x, y, z), dt);
+        self.predict(rate, dt as f64);
+        if !self.corrected {
+            return;
+        }
+
+        if let Some(measured) = unit(acc) {
+            // The gravity of the orientation off by δθ is v + v × δθ
+            let gravity = to_sensor(UP, self.quaternion);
+            let uncertain = aligning || self.is_uncertain(self.acc_threshold);
+            if uncertain || self.acc_rejection.accepts(measured.dot_product(&gravity), dt) {
+                let v = [gravity.x, gravity.y, gravity.z].map(|c| c as f64);
+                let h = skew(v).map(|row| [row[0], row[1], row[2], 0.0, 0.0, 0.0]);
+                let residual = [measured.x - gravity.x, measured.y - gravity.y, measured.z - gravity.z].map(|r| r as f64);
+                let norm = libm::sqrtf(acc.dot_product(&acc));
+                let noise = ACC_NOISE + (norm - 1.0).abs() as f64;
+                self.correct(h, residual, noise * noise);
+            }
+        }
+
+        // The field in the earth axes should have no westward part: its
+        // heading is the error about the vertical, which is v · δθ
+        let field = rotate(mag, self.quaternion);
+        let horizontal = libm::sqrtf(field.x * field.x + field.y * field.y);
+        if horizontal > MIN_HORIZONTAL * libm::sqrtf(mag.dot_product(&mag)) {
+            let error = -libm::atan2f(field.y, field.x);
+            let uncertain = aligning || self.is_uncertain(self.mag_threshold);
+            if uncertain || self.mag_rejection.accepts(libm::cosf(error), dt) {
+                let v = to_sensor(UP, self.quaternion);
+                self.correct([[v.x as f64, v.y as f64, v.z as f64, 0.0, 0.0, 0.0]], [error as f64], MAG_NOISE * MAG_NOISE);
+            }
+        }
+    }
+
+    fn quaternion(&self) -> FusionQuaternion {
+        self.convention.to_axes(self.quaternion)
+    }
+
+    fn set_quaternion(&mut self, quaternion: FusionQuaternion) {
+        self.quaternion = self.convention.to_nwu(quaternion);
+        self.schedule.finish();
+        for i in 0..6 {
+            for j in 0..6 {
+                if i < 3 || j < 3 {
+                    self.covariance[i][j] = 0.0;
+                }
+            }
+        }
+        for i in 0..3 {
+            self.covariance[i][i] = ACC_NOISE * ACC_NOISE;
+        }
+    }
+}
+
+/// The matrix of `v ×`.
+fn skew(v: [f64; 3]) -> [[f64; 3]; 3] {
+    [[0.0, -v[2], v[1]], [v[2], 0.0, -v[0]], [-v[1], v[0], 0.0]]
+}
+
+fn identity() -> Matrix {
+    core::array::from_fn(|i| core::array::from_fn(|j| if i == j { 1.0 } else { 0.0 }))
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    core::array::from_fn(|i| core::array::from_fn(|j| (0..6).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    core::array::from_fn(|i| core::array::from_fn(|j| m[j][i]))
+}
+
+#[test]
+fn test_gyroscope_offset() {
+    // Rolled by 40° and facing north, with a gyroscope drifting about every
+    // axis
+    let offset = FusionVector::new(1.0, -0.5, 0.8);
+    let (s, c) = (libm::sinf(0.7), libm::cosf(0.7));
+    let (acc, mag) = (FusionVector::new(0.0, s, c), FusionVector::new(20.0, -40.0 * s, -40.0 * c));
+    let mut filter = KalmanFilter::new(&AhrsConfig::default());
+    for _ in 0..200 * 60 {
+        filter.update(offset, acc, mag, 1.0 / 200.0);
+    }
+    let found = filter.offset();
+    assert!((found.x - offset.x).abs() < 0.1 && (found.y - offset.y).abs() < 0.1 && (found.z - offset.z).abs() < 0.1,
+            "{} {} {}", found.x, found.y, found.z);
+    assert!(filter.uncertainty() < 2.0, "{}", filter.uncertainty());
+
+    // The gyroscope alone: nothing to find it with
+    let config = AhrsConfig { gain: 0.0, ..AhrsConfig::default() };
+    let mut filter = KalmanFilter::new(&config);
+    for _ in 0..200 * 10 {
+        filter.update(offset, acc, mag, 1.0 / 200.0);
+    }
+    assert!(filter.offset().is_zero());
+}
@@ -0,0 +1,39 @@
+//! Motion tracking and movement detection for the wristband.
+//!
+//! This crate holds the math that runs on the app core of the firmware, kept
+//! free of any hardware or executor dependency so that it builds (and is
+//! tested) on the host as well as on the ESP32-S3.
+#![no_std]
+
+extern crate alloc;
+
+pub mod acc_calibration;
+pub mod alignment;
+pub mod analysis;
+pub mod calibration;
+pub mod clock;
+pub mod denoise;
+pub mod estimator;
+pub mod filter;
+pub mod gesture;
+pub mod imu_tracker;
+pub mod kalman;
+mod linalg;
+pub mod mag_calibration;
+pub mod mahony;
+pub mod quantile;
+pub mod sntp;
+pub mod tempo;
+pub mod template;
+pub mod time;
+pub mod timesync;
+
+pub use analysis::{Analysis, AnalysisConfig, DetectionEvent, MovementClass, MovementDirection};
+pub use calibration::ImuCalibration;
+pub use denoise::{Denoiser, DenoiserKind};
+pub use estimator::{EstimatorKind, OrientationEstimator};
+pub use gesture::{Gesture, GestureConfig, GestureRecognizer};
+pub use imu_tracker::{AhrsConfig, ImuTracker, Orientation};
+pub use tempo::{Tempo, TempoConfig, TempoEstimator};
+pub use template::{TemplateMatch, TemplateMatcher};
+pub use time::Timestamp;
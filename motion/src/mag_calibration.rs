@@ -0,0 +1,221 @@
+//! Hard- and soft-iron calibration of the magnetometer.
+//!
+//! Rotated every which way, an undistorted magnetometer traces a sphere
+//! around the origin. The battery and the PCB shift it (hard iron) and
+//! squash it into an ellipsoid (soft iron), so an ellipsoid is fitted to the
+//! readings and the transformation mapping it back to a sphere of the same
+//! volume is what corrects them.
+
+use alloc::vec::Vec;
+
+use imu_fusion::FusionVector;
+
+use crate::{
+    calibration::{ImuCalibration, Offset, Transform},
+    linalg::{dot, mat_vec, solve, symmetric_eigen},
+};
+
+/// Readings closer than this to the last one kept, in µT, are skipped, so
+/// that holding the device in one pose does not weigh it in the fit.
+const MIN_SPACING: f32 = 2.0;
+
+/// Readings kept at most, enough for a dense cover of the sphere.
+const MAX_SAMPLES: usize = 400;
+
+/// Readings below which no fit is attempted; it has 9 parameters.
+const MIN_SAMPLES: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MagFitError {
+    TooFewSamples,
+    /// The readings do not lie on an ellipsoid, e.g. the device was only
+    /// turned about one axis.
+    Degenerate,
+}
+
+/// Result of a fit, with its quality.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagFit {
+    /// In µT.
+    pub hard_iron: Offset,
+    pub soft_iron: Transform,
+    /// Radius of the corrected sphere, in µT: the strength of the field.
+    pub field_strength: f32,
+    /// RMS deviation of the corrected readings from the sphere, as a
+    /// fraction of its radius.
+    pub residual: f32,
+    /// Octants the corrected readings point to, out of 8: how much of the
+    /// sphere the rotations covered.
+    pub coverage: u8,
+    pub samples: usize,
+}
+
+impl MagFit {
+    pub fn apply_to(&self, calibration: &mut ImuCalibration) {
+        calibration.mag_hard_iron = self.hard_iron;
+        calibration.mag_soft_iron = self.soft_iron;
+    }
+
+    pub fn correct(&self, raw: FusionVector) -> FusionVector {
+        let Offset(offset) = self.hard_iron;
+        let v = [raw.x - offset[0], raw.y - offset[1], raw.z - offset[2]];
+        let [x, y, z] = self.soft_iron.0.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2]);
+        FusionVector::new(x, y, z)
+    }
+}
+
+/// Collects magnetometer readings while the device is rotated, in the frame
+/// of [`crate::imu_tracker::align_magnetometer`].
+#[derive(Debug, Clone, Default)]
+pub struct MagCalibrator {
+    samples: Vec<[f32; 3]>,
+}
+
+impl MagCalibrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn add(&mut self, mag: FusionVector) {
+        let v = [mag.x, mag.y, mag.z];
+        let far = self.samples.last().map_or(true, |last| {
+            let d = [v[0] - last[0], v[1] - last[1], v[2] - last[2]];
+            d[0] * d[0] + d[1] * d[1] + d[2] * d[2] >= MIN_SPACING * MIN_SPACING
+        });
+        if far && self.samples.len() < MAX_SAMPLES {
+            self.samples.push(v);
+        }
+    }
+
+    pub fn fit(&self) -> Result<MagFit, MagFitError> {
+        let n = self.samples.len();
+        if n < MIN_SAMPLES {
+            return Err(MagFitError::TooFewSamples);
+        }
+        // Centered and scaled to about 1, for the conditioning of the fit
+        let mut mean = [0.0f64; 3];
+        for s in &self.samples {
+            for i in 0..3 {
+                mean[i] += s[i] as f64 / n as f64;
+            }
+        }
+        let scaled = |s: &[f32; 3]| -> [f64; 3] { core::array::from_fn(|i| s[i] as f64 - mean[i]) };
+        let scale = libm::sqrt(self.samples.iter().map(|s| dot(scaled(s), scaled(s))).sum::<f64>() / n as f64);
+        if scale == 0.0 {
+            return Err(MagFitError::Degenerate);
+        }
+
+        // Least squares fit of a x² + b y² + c z² + 2d xy + 2e xz + 2f yz
+        // + 2g x + 2h y + 2i z = 1, by its normal equations
+        let mut ata = [[0.0f64; 9]; 9];
+        let mut atb = [0.0f64; 9];
+        for s in &self.samples {
+            let [x, y, z] = scaled(s).map(|v| v / scale);
+            let row = [x * x, y * y, z * z, 2.0 * x * y, 2.0 * x * z, 2.0 * y * z, 2.0 * x, 2.0 * y, 2.0 * z];
+            for i in 0..9 {
+                for j in 0..9 {
+                    ata[i][j] += row[i] * row[j];
+                }
+                atb[i] += row[i];
+            }
+        }
+        let [a, b, c, d, e, f, g, h, i] = solve(ata, atb).ok_or(MagFitError::Degenerate)?;
+        let quadric = [[a, d, e], [d, b, f], [e, f, c]];
+
+        // Centered on -A⁻¹ [g h i], the ellipsoid is vᵀ (A / k) v = 1
+        let center = solve(quadric, [-g, -h, -i]).ok_or(MagFitError::Degenerate)?;
+        let k = 1.0 + dot(center, mat_vec(&quadric, center));
+        let (eigenvalues, eigenvectors) = symmetric_eigen(quadric.map(|row| row.map(|v| v / k)));
+        if eigenvalues.iter().any(|&l| l.is_nan() || l <= 0.0) {
+            return Err(MagFitError::Degenerate);
+        }
+
+        // The square root of A / k maps the ellipsoid to the unit sphere;
+        // scaled by the mean radius, it keeps the readings in µT
+        let radius = libm::cbrt(1.0 / libm::sqrt(eigenvalues[0] * eigenvalues[1] * eigenvalues[2]));
+        let mut soft_iron = [[0.0f32; 3]; 3];
+        for (r, row) in soft_iron.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                let sum: f64 = (0..3).map(|j| eigenvectors[r][j] * libm::sqrt(eigenvalues[j]) * eigenvectors[c][j]).sum();
+                *value = (radius * sum) as f32;
+            }
+        }
+        let hard_iron = Offset(core::array::from_fn(|i| (mean[i] + center[i] * scale) as f32));
+        let field_strength = (radius * scale) as f32;
+
+        let mut fit = MagFit { hard_iron, soft_iron: Transform(soft_iron), field_strength, residual: 0.0, coverage: 0, samples: n };
+        let mut squared_error = 0.0;
+        let mut octants = 0u8;
+        for s in &self.samples {
+            let v = fit.correct(FusionVector::new(s[0], s[1], s[2]));
+            let norm = libm::sqrtf(v.x * v.x + v.y * v.y + v.z * v.z);
+            squared_error += (norm / field_strength - 1.0) * (norm / field_strength - 1.0);
+            octants |= 1 << ((v.x >= 0.0) as u8 | ((v.y >= 0.0) as u8) << 1 | ((v.z >= 0.0) as u8) << 2);
+        }
+        fit.residual = libm::sqrtf(squared_error / n as f32);
+        fit.coverage = octants.count_ones() as u8;
+        Ok(fit)
+    }
+}
+
+#[cfg(test)]
+fn sphere_points(n: usize) -> impl Iterator<Item = [f32; 3]> {
+    // Fibonacci lattice
+    (0..n).map(move |i| {
+        let z = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+        let r = libm::sqrtf(1.0 - z * z);
+        let phi = i as f32 * 2.399_963;
+        [r * libm::cosf(phi), r * libm::sinf(phi), z]
+    })
+}
+
+#[test]
+fn test_hard_and_soft_iron() {
+    let distortion = [[1.2, 0.05, 0.0], [0.05, 0.9, 0.02], [0.0, 0.02, 1.05]];
+    let hard_iron = [20.0, -15.0, 30.0];
+    let mut calibrator = MagCalibrator::new();
+    let mut truth = Vec::new();
+    for (i, p) in sphere_points(300).enumerate() {
+        let field = p.map(|v| v * 48.0);
+        let noise = if i % 2 == 0 { 0.1 } else { -0.1 };
+        let raw: [f32; 3] = core::array::from_fn(|r| {
+            (0..3).map(|c| distortion[r][c] * field[c]).sum::<f32>() + hard_iron[r] + noise
+        });
+        calibrator.add(FusionVector::new(raw[0], raw[1], raw[2]));
+        truth.push((raw, field));
+    }
+    let fit = calibrator.fit().unwrap();
+
+    assert_eq!(fit.coverage, 8);
+    assert!(fit.residual < 0.01, "{fit:?}");
+    assert!(fit.hard_iron.0.iter().zip(hard_iron).all(|(fitted, truth)| (fitted - truth).abs() < 0.5), "{fit:?}");
+    // Corrected readings point where the field does
+    for (raw, field) in truth {
+        let v = fit.correct(FusionVector::new(raw[0], raw[1], raw[2]));
+        let cos = (v.x * field[0] + v.y * field[1] + v.z * field[2])
+            / (libm::sqrtf(v.x * v.x + v.y * v.y + v.z * v.z) * 48.0);
+        assert!(cos > 0.999, "{cos}");
+    }
+}
+
+#[test]
+fn test_poor_rotations() {
+    let mut calibrator = MagCalibrator::new();
+    // Held still
+    for _ in 0..100 {
+        calibrator.add(FusionVector::new(30.0, 5.0, -20.0));
+    }
+    assert_eq!(calibrator.samples(), 1);
+    assert_eq!(calibrator.fit(), Err(MagFitError::TooFewSamples));
+
+    // Only turned about the vertical axis: a circle, not an ellipsoid
+    for i in 0..100 {
+        let angle = i as f32 * 0.0628;
+        calibrator.add(FusionVector::new(40.0 * libm::cosf(angle), 40.0 * libm::sinf(angle), -20.0));
+    }
+    assert_eq!(calibrator.fit(), Err(MagFitError::Degenerate));
+}
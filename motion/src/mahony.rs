@@ -0,0 +1,122 @@
+//! Mahony's complementary filter.
+//!
+//! The gyroscope is integrated, and the error between where the
+//! accelerometer and magnetometer point and where the orientation says
+//! gravity and west are is fed back to it through a proportional and integral
+//! controller. The integral term takes out what is left of the gyroscope
+//! offsets, which Fusion leaves to its own offset correction.
+
+use imu_fusion::{FusionQuaternion, FusionVector};
+
+use crate::{
+    estimator::{integrate, to_sensor, unit, GainSchedule, OrientationEstimator, Rejection},
+    imu_tracker::{AhrsConfig, Convention},
+};
+
+/// Integral gain, in rad/s per unit of error and second: with the default
+/// proportional gain, an offset settles in about half a minute.
+const INTEGRAL_GAIN: f32 = 0.02;
+
+/// Largest gyroscope offset the integral term corrects, in rad/s (3°/s).
+const MAX_INTEGRAL: f32 = 0.05;
+
+const UP: FusionVector = FusionVector { x: 0.0, y: 0.0, z: 1.0 };
+const WEST: FusionVector = FusionVector { x: 0.0, y: 1.0, z: 0.0 };
+
+/// With the proportional gain of the [`AhrsConfig`], scheduled the same way
+/// as Fusion's, and the same rejection of the accelerometer and
+/// magnetometer.
+pub struct MahonyFilter {
+    convention: Convention,
+    /// From the sensor frame to NWU axes.
+    quaternion: FusionQuaternion,
+    schedule: GainSchedule,
+    acc_rejection: Rejection,
+    mag_rejection: Rejection,
+    /// In rad/s.
+    integral: FusionVector,
+}
+
+impl MahonyFilter {
+    pub fn new(config: &AhrsConfig) -> Self {
+        Self {
+            convention: config.convention,
+            quaternion: FusionQuaternion::identity(),
+            schedule: GainSchedule::new(config),
+            acc_rejection: Rejection::new(config.acc_rejection, config.recovery_period),
+            mag_rejection: Rejection::new(config.mag_rejection, config.recovery_period),
+            integral: FusionVector::zero(),
+        }
+    }
+
+    /// The gyroscope offset found so far, in degrees/s.
+    pub fn offset(&self) -> FusionVector {
+        self.integral * -(1.0f32.to_degrees())
+    }
+}
+
+/// The rotation taking `estimated` onto `measured`, both unit vectors, as
+/// their cross product: the sine of the angle between them, or a full unit
+/// beyond a quarter turn so that it keeps pulling.
+fn feedback(measured: FusionVector, estimated: FusionVector) -> FusionVector {
+    let error = measured.cross_product(&estimated);
+    if measured.dot_product(&estimated) < 0.0 {
+        unit(error).unwrap_or(error)
+    } else {
+        error
+    }
+}
+
+impl OrientationEstimator for MahonyFilter {
+    fn update(&mut self, gyr: FusionVector, acc: FusionVector, mag: FusionVector, dt: f32) {
+        let aligning = self.schedule.is_aligning();
+        let gain = self.schedule.next(dt);
+        let gravity = to_sensor(UP, self.quaternion);
+
+        let mut error = FusionVector::zero();
+        if let Some(acc) = unit(acc) {
+            if aligning || self.acc_rejection.accepts(acc.dot_product(&gravity), dt) {
+                error += feedback(acc, gravity);
+            }
+        }
+        // West, as the magnetometer and the estimated gravity tell it, so that
+        // the magnetometer only ever corrects the heading
+        if let Some(west) = unit(gravity.cross_product(&mag)) {
+            let estimated = to_sensor(WEST, self.quaternion);
+            if aligning || self.mag_rejection.accepts(west.dot_product(&estimated), dt) {
+                error += feedback(west, estimated);
+            }
+        }
+
+        if !aligning && gain > 0.0 {
+            let clamp = |v: f32| v.clamp(-MAX_INTEGRAL, MAX_INTEGRAL);
+            let integral = self.integral + error * (INTEGRAL_GAIN * dt);
+            self.integral = FusionVector::new(clamp(integral.x), clamp(integral.y), clamp(integral.z));
+        }
+        let rate = gyr * 1.0f32.to_radians() + error * gain + self.integral;
+        self.quaternion = integrate(self.quaternion, rate, dt);
+    }
+
+    fn quaternion(&self) -> FusionQuaternion {
+        self.convention.to_axes(self.quaternion)
+    }
+
+    fn set_quaternion(&mut self, quaternion: FusionQuaternion) {
+        self.quaternion = self.convention.to_nwu(quaternion);
+        self.schedule.finish();
+    }
+}
+
+#[test]
+fn test_gyroscope_offset() {
+    // Lying flat and facing north, with a gyroscope drifting about every axis
+    let offset = FusionVector::new(1.0, -0.5, 0.8);
+    let mut filter = MahonyFilter::new(&AhrsConfig::default());
+    filter.set_quaternion(FusionQuaternion::identity());
+    for _ in 0..200 * 120 {
+        filter.update(offset, UP, FusionVector::new(20.0, 0.0, -40.0), 1.0 / 200.0);
+    }
+    let found = filter.offset();
+    assert!((found.x - offset.x).abs() < 0.1 && (found.y - offset.y).abs() < 0.1 && (found.z - offset.z).abs() < 0.1,
+            "{} {} {}", found.x, found.y, found.z);
+}
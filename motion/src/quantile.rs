@@ -0,0 +1,163 @@
+use alloc::collections::{BinaryHeap, VecDeque};
+use core::cmp::{Ordering, Reverse};
+
+/// A value tagged with its arrival number, which tells equal values apart
+/// and when they leave the window.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    value: f32,
+    seq: u32,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.total_cmp(&other.value).then(self.seq.cmp(&other.seq))
+    }
+}
+
+/// Quantile of the last `window_size` values, updated in O(log n) per value.
+///
+/// The values at or below the quantile sit in a max-heap and the rest in a
+/// min-heap, so the quantile is the top of the first one. Values leaving the
+/// window are only counted out when evicted, and dropped once they surface at
+/// the top of their heap; the heaps are swept when such leftovers pile up.
+/// The result is the element at index `floor(len * quantile)` of the sorted
+/// window, as sorting it would give.
+pub struct SlidingQuantile {
+    quantile: f32,
+    window_size: usize,
+    window: VecDeque<Entry>,
+    low: BinaryHeap<Entry>,
+    high: BinaryHeap<Reverse<Entry>>,
+    low_len: usize,
+    high_len: usize,
+    seq: u32,
+}
+
+impl SlidingQuantile {
+    pub fn new(quantile: f32, window_size: usize) -> Self {
+        assert!((0.0..1.0).contains(&quantile));
+        assert!(window_size > 0);
+        Self {
+            quantile,
+            window_size,
+            window: VecDeque::with_capacity(window_size),
+            low: BinaryHeap::with_capacity(window_size),
+            high: BinaryHeap::with_capacity(window_size),
+            low_len: 0,
+            high_len: 0,
+            seq: 0,
+        }
+    }
+
+    /// Adds a value and returns the quantile of the window.
+    pub fn add_measurement(&mut self, value: f32) -> f32 {
+        self.seq = self.seq.wrapping_add(1);
+        let entry = Entry { value, seq: self.seq };
+
+        if self.window.len() == self.window_size {
+            // The tops are live but for the evicted value itself, so it can be
+            // told which heap it is in before pruning
+            let evicted = self.window.pop_front().unwrap();
+            if self.low.peek().is_some_and(|top| evicted <= *top) {
+                self.low_len -= 1;
+            } else {
+                self.high_len -= 1;
+            }
+        }
+        self.window.push_back(entry);
+
+        self.prune();
+        if self.low.peek().is_some_and(|top| entry <= *top) {
+            self.low.push(entry);
+            self.low_len += 1;
+        } else {
+            self.high.push(Reverse(entry));
+            self.high_len += 1;
+        }
+
+        let target = (self.window.len() as f32 * self.quantile) as usize + 1;
+        while self.low_len > target {
+            let moved = self.low.pop().unwrap();
+            self.high.push(Reverse(moved));
+            self.low_len -= 1;
+            self.high_len += 1;
+            self.prune();
+        }
+        while self.low_len < target {
+            let Reverse(moved) = self.high.pop().unwrap();
+            self.low.push(moved);
+            self.high_len -= 1;
+            self.low_len += 1;
+            self.prune();
+        }
+
+        if self.low.len() + self.high.len() > 2 * self.window_size {
+            let (seq, window_size) = (self.seq, self.window_size);
+            self.low.retain(|e| is_live(seq, window_size, e));
+            self.high.retain(|Reverse(e)| is_live(seq, window_size, e));
+        }
+        self.low.peek().unwrap().value
+    }
+
+    /// Drops the values already out of the window from the top of both heaps.
+    fn prune(&mut self) {
+        let (seq, window_size) = (self.seq, self.window_size);
+        while self.low.peek().is_some_and(|e| !is_live(seq, window_size, e)) {
+            self.low.pop();
+        }
+        while self.high.peek().is_some_and(|Reverse(e)| !is_live(seq, window_size, e)) {
+            self.high.pop();
+        }
+    }
+}
+
+/// Whether an entry is among the last `window_size` values, `seq` being the
+/// number of the latest one.
+fn is_live(seq: u32, window_size: usize, entry: &Entry) -> bool {
+    seq.wrapping_sub(entry.seq) < window_size as u32
+}
+
+#[test]
+fn test_matches_sorting_the_window() {
+    use alloc::vec::Vec;
+
+    // Small integers give plenty of ties
+    let mut state = 12345u32;
+    let mut next = || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        ((state >> 16) % 21) as f32 - 10.0
+    };
+
+    for (quantile, window_size) in [(0.75, 30), (0.5, 1), (0.5, 2), (0.9, 7), (0.0, 5), (0.75, 200)] {
+        let mut sliding = SlidingQuantile::new(quantile, window_size);
+        let mut window = VecDeque::new();
+        for _ in 0..2000 {
+            let value = next();
+            if window.len() == window_size {
+                window.pop_front();
+            }
+            window.push_back(value);
+            let mut sorted: Vec<f32> = window.iter().copied().collect();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let expected = sorted[(sorted.len() as f32 * quantile) as usize];
+
+            assert_eq!(sliding.add_measurement(value), expected);
+            assert!(sliding.low.len() + sliding.high.len() <= 2 * window_size + 1);
+        }
+    }
+}
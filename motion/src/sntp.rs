@@ -0,0 +1,125 @@
+//! The client side of SNTP (RFC 4330), transport aside.
+//!
+//! Timestamps are exchanged in µs since the Unix epoch. The NTP era rolls
+//! over in 2036, which is not handled.
+
+pub const PORT: u16 = 123;
+pub const PACKET_SIZE: usize = 48;
+
+/// Seconds from the NTP epoch (1900) to the Unix one.
+const UNIX_EPOCH: u64 = 2_208_988_800;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator of a server whose clock is not synchronized.
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Truncated,
+    NotAServerReply,
+    /// The server has no time to give, or asks not to be polled (a
+    /// kiss-o'-death, stratum 0).
+    Unsynchronized,
+    /// The reply is not to the latest request.
+    Mismatch,
+    BadTimestamp,
+}
+
+/// Converts µs since the Unix epoch to an NTP timestamp.
+pub fn to_ntp_timestamp(unix_us: u64) -> u64 {
+    let secs = unix_us / 1_000_000 + UNIX_EPOCH;
+    let fraction = ((unix_us % 1_000_000) << 32) / 1_000_000;
+    (secs << 32) | fraction
+}
+
+/// Converts an NTP timestamp to µs since the Unix epoch, if not before it.
+pub fn from_ntp_timestamp(timestamp: u64) -> Option<u64> {
+    let secs = (timestamp >> 32).checked_sub(UNIX_EPOCH)?;
+    let fraction = ((timestamp & 0xffff_ffff) * 1_000_000) >> 32;
+    Some(secs * 1_000_000 + fraction)
+}
+
+/// A client request. `transmit` can be any value, which the server echoes
+/// back and [`parse_response`] checks: the local time it is sent at does.
+pub fn request(transmit: u64) -> [u8; PACKET_SIZE] {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = (VERSION << 3) | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&transmit.to_be_bytes());
+    packet
+}
+
+/// The times of a server reply, in µs since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    pub stratum: u8,
+    /// When the request arrived.
+    pub receive: u64,
+    /// When the reply left.
+    pub transmit: u64,
+}
+
+/// Parses the reply to the request sent with `transmit`.
+pub fn parse_response(packet: &[u8], transmit: u64) -> Result<Response, Error> {
+    if packet.len() < PACKET_SIZE {
+        return Err(Error::Truncated);
+    }
+    let field = |offset: usize| u64::from_be_bytes(packet[offset..offset + 8].try_into().unwrap());
+    let (leap, mode, stratum) = (packet[0] >> 6, packet[0] & 0x07, packet[1]);
+    if mode != MODE_SERVER {
+        return Err(Error::NotAServerReply);
+    }
+    if leap == LEAP_UNSYNCHRONIZED || stratum == 0 {
+        return Err(Error::Unsynchronized);
+    }
+    if field(24) != transmit {
+        return Err(Error::Mismatch);
+    }
+    Ok(Response {
+        stratum,
+        receive: from_ntp_timestamp(field(32)).ok_or(Error::BadTimestamp)?,
+        transmit: from_ntp_timestamp(field(40)).ok_or(Error::BadTimestamp)?,
+    })
+}
+
+#[cfg(test)]
+fn reply(request: &[u8; PACKET_SIZE], first_byte: u8, stratum: u8, receive: u64, transmit: u64) -> [u8; PACKET_SIZE] {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = first_byte;
+    packet[1] = stratum;
+    packet[24..32].copy_from_slice(&request[40..48]);
+    packet[32..40].copy_from_slice(&to_ntp_timestamp(receive).to_be_bytes());
+    packet[40..48].copy_from_slice(&to_ntp_timestamp(transmit).to_be_bytes());
+    packet
+}
+
+#[test]
+fn test_timestamp_conversions() {
+    for unix_us in [0, 1, 999_999, 1_700_000_000_123_456] {
+        // Fractions are truncated both ways
+        let back = from_ntp_timestamp(to_ntp_timestamp(unix_us)).unwrap();
+        assert!(back.abs_diff(unix_us) <= 1, "{unix_us} came back as {back}");
+    }
+    assert_eq!(to_ntp_timestamp(0) >> 32, UNIX_EPOCH);
+    assert_eq!(from_ntp_timestamp(0), None);
+}
+
+#[test]
+fn test_responses() {
+    let sent = 123_456_789;
+    let request = request(sent);
+    assert_eq!(request[0], 0x23);
+    let (receive, transmit) = (1_700_000_000_000_000, 1_700_000_000_000_250);
+
+    let server = (VERSION << 3) | MODE_SERVER;
+    let response = parse_response(&reply(&request, server, 2, receive, transmit), sent).unwrap();
+    assert_eq!(response.stratum, 2);
+    assert!(response.receive.abs_diff(receive) <= 1 && response.transmit.abs_diff(transmit) <= 1);
+
+    assert_eq!(parse_response(&reply(&request, server, 2, receive, transmit), sent + 1), Err(Error::Mismatch));
+    assert_eq!(parse_response(&reply(&request, server, 0, receive, transmit), sent), Err(Error::Unsynchronized));
+    assert_eq!(parse_response(&reply(&request, server | 0xc0, 2, receive, transmit), sent), Err(Error::Unsynchronized));
+    assert_eq!(parse_response(&reply(&request, 0x23, 2, receive, transmit), sent), Err(Error::NotAServerReply));
+    assert_eq!(parse_response(&request[..40], sent), Err(Error::Truncated));
+}
@@ -0,0 +1,355 @@
+use alloc::{vec, vec::Vec};
+use core::mem;
+
+use imu_fusion::FusionVector;
+use micromath::F32Ext;
+
+/// Leading bytes of a template file.
+pub const TEMPLATE_MAGIC: &[u8; 4] = b"GTPL";
+pub const TEMPLATE_VERSION: u8 = 1;
+
+/// Quantization step of template frames, in g per count (so frames span ±4 g).
+pub const FRAME_SCALE: f32 = 1.0 / 32.0;
+
+/// Longest template accepted, in frames. Bounds the matcher's memory to a
+/// couple of KiB per template.
+pub const MAX_TEMPLATE_FRAMES: usize = 64;
+
+/// Most templates accepted, so that the longest of them all fit the
+/// firmware's 32 KiB heap with room to spare.
+pub const MAX_TEMPLATES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    /// A template has no frames, more than [`MAX_TEMPLATE_FRAMES`], or a zero
+    /// decimation was given.
+    BadTemplate,
+    /// More than [`MAX_TEMPLATES`].
+    TooManyTemplates,
+    /// Bytes left after the last template.
+    TrailingBytes,
+}
+
+/// An example gesture: a short sequence of linear accelerations in the earth
+/// frame, taken every `decimation` samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub id: u8,
+    /// Largest mean distance per frame, in g, still considered a match.
+    pub threshold: f32,
+    pub frames: Vec<[i8; 3]>,
+}
+
+impl Template {
+    pub fn from_frames(id: u8, threshold: f32, frames: &[FusionVector]) -> Self {
+        let quantize = |v: f32| F32Ext::round(v / FRAME_SCALE).clamp(i8::MIN as f32, i8::MAX as f32) as i8;
+        Self {
+            id,
+            threshold,
+            frames: frames.iter().map(|f| [quantize(f.x), quantize(f.y), quantize(f.z)]).collect(),
+        }
+    }
+
+    pub fn frame(&self, i: usize) -> FusionVector {
+        let [x, y, z] = self.frames[i];
+        FusionVector::new(x as f32 * FRAME_SCALE, y as f32 * FRAME_SCALE, z as f32 * FRAME_SCALE)
+    }
+}
+
+/// Serializes templates: magic, version, decimation and count, then for each
+/// template its id, frame count, threshold in mg (`u16`, little endian) and
+/// three `i8` per frame. There can be no more than [`MAX_TEMPLATES`]
+/// templates, of at most [`MAX_TEMPLATE_FRAMES`] each.
+pub fn encode_templates(decimation: u8, templates: &[Template]) -> Vec<u8> {
+    assert!(templates.len() <= MAX_TEMPLATES);
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(TEMPLATE_MAGIC);
+    bytes.extend_from_slice(&[TEMPLATE_VERSION, decimation, templates.len() as u8]);
+    for t in templates {
+        assert!(t.frames.len() <= MAX_TEMPLATE_FRAMES);
+        let threshold_mg = F32Ext::round(t.threshold * 1000.0).clamp(0.0, u16::MAX as f32) as u16;
+        bytes.extend_from_slice(&[t.id, t.frames.len() as u8]);
+        bytes.extend_from_slice(&threshold_mg.to_le_bytes());
+        bytes.extend(t.frames.iter().flatten().map(|v| *v as u8));
+    }
+    bytes
+}
+
+/// Parses what [`encode_templates`] produces, returning the decimation and
+/// the templates.
+pub fn decode_templates(bytes: &[u8]) -> Result<(u8, Vec<Template>), TemplateError> {
+    if bytes.len() < 7 {
+        return Err(TemplateError::Truncated);
+    }
+    if &bytes[..4] != TEMPLATE_MAGIC {
+        return Err(TemplateError::BadMagic);
+    }
+    if bytes[4] != TEMPLATE_VERSION {
+        return Err(TemplateError::UnsupportedVersion(bytes[4]));
+    }
+    let decimation = bytes[5];
+    if decimation == 0 {
+        return Err(TemplateError::BadTemplate);
+    }
+
+    if bytes[6] as usize > MAX_TEMPLATES {
+        return Err(TemplateError::TooManyTemplates);
+    }
+
+    let mut templates = Vec::with_capacity(bytes[6] as usize);
+    let mut rest = &bytes[7..];
+    for _ in 0..bytes[6] {
+        let [id, len, t0, t1, ..] = *rest else {
+            return Err(TemplateError::Truncated);
+        };
+        let len = len as usize;
+        if len == 0 || len > MAX_TEMPLATE_FRAMES {
+            return Err(TemplateError::BadTemplate);
+        }
+        let frames = rest.get(4..4 + 3 * len).ok_or(TemplateError::Truncated)?;
+        templates.push(Template {
+            id,
+            threshold: u16::from_le_bytes([t0, t1]) as f32 / 1000.0,
+            frames: frames.chunks_exact(3).map(|f| [f[0] as i8, f[1] as i8, f[2] as i8]).collect(),
+        });
+        rest = &rest[4 + 3 * len..];
+    }
+    if !rest.is_empty() {
+        return Err(TemplateError::TrailingBytes);
+    }
+    Ok((decimation, templates))
+}
+
+/// Averages every `decimation` samples into one frame.
+pub struct Decimator {
+    decimation: u8,
+    sum: FusionVector,
+    count: u8,
+}
+
+impl Decimator {
+    pub fn new(decimation: u8) -> Self {
+        assert!(decimation > 0);
+        Self { decimation, sum: FusionVector::zero(), count: 0 }
+    }
+
+    pub fn add_measurement(&mut self, sample: FusionVector) -> Option<FusionVector> {
+        self.sum += sample;
+        self.count += 1;
+        if self.count < self.decimation {
+            return None;
+        }
+        let frame = self.sum * (1.0 / self.count as f32);
+        self.sum = FusionVector::zero();
+        self.count = 0;
+        Some(frame)
+    }
+}
+
+fn frame_distance(a: FusionVector, b: FusionVector) -> f32 {
+    let d = a - b;
+    F32Ext::sqrt(d.x * d.x + d.y * d.y + d.z * d.z)
+}
+
+/// Mean distance per frame between two sequences along their best dynamic
+/// time warping alignment. Meant for the host, as it takes `a.len() * b.len()`
+/// time, in two rows of `b.len()` distances.
+pub fn dtw_distance(a: &[FusionVector], b: &[FusionVector]) -> f32 {
+    let mut prev = vec![f32::INFINITY; b.len() + 1];
+    let mut next = vec![f32::INFINITY; b.len() + 1];
+    prev[0] = 0.0;
+    for x in a {
+        next[0] = f32::INFINITY;
+        for (j, y) in b.iter().enumerate() {
+            next[j + 1] = frame_distance(*x, *y) + next[j].min(prev[j]).min(prev[j + 1]);
+        }
+        mem::swap(&mut prev, &mut next);
+    }
+    prev[b.len()] / a.len().max(b.len()) as f32
+}
+
+/// A template found in the stream, with frames counted since the matcher was
+/// created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemplateMatch {
+    pub id: u8,
+    /// Mean distance per template frame, in g.
+    pub distance: f32,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Streaming subsequence DTW of one template (the SPRING algorithm, Sakurai
+/// et al., 2007): one column of distances and start frames per template frame.
+struct Spring {
+    template: Template,
+    distances: Vec<f32>,
+    starts: Vec<u32>,
+    next_distances: Vec<f32>,
+    next_starts: Vec<u32>,
+    candidate: Option<(f32, u32, u32)>,
+}
+
+impl Spring {
+    fn new(template: Template) -> Self {
+        let m = template.frames.len();
+        let mut distances = vec![f32::INFINITY; m + 1];
+        distances[0] = 0.0;
+        Self {
+            template,
+            next_distances: distances.clone(),
+            distances,
+            starts: vec![0; m + 1],
+            next_starts: vec![0; m + 1],
+            candidate: None,
+        }
+    }
+
+    fn step(&mut self, frame: FusionVector, t: u32) -> Option<TemplateMatch> {
+        let m = self.template.frames.len();
+        self.next_distances[0] = 0.0;
+        self.next_starts[0] = t;
+        for i in 1..=m {
+            let mut best = (self.next_distances[i - 1], self.next_starts[i - 1]);
+            if self.distances[i - 1] < best.0 {
+                best = (self.distances[i - 1], self.starts[i - 1]);
+            }
+            if self.distances[i] < best.0 {
+                best = (self.distances[i], self.starts[i]);
+            }
+            self.next_distances[i] = frame_distance(frame, self.template.frame(i - 1)) + best.0;
+            self.next_starts[i] = best.1;
+        }
+        mem::swap(&mut self.distances, &mut self.next_distances);
+        mem::swap(&mut self.starts, &mut self.next_starts);
+
+        // Report the best candidate once no alignment still running can beat it
+        let mut found = None;
+        if let Some((distance, start, end)) = self.candidate {
+            let settled = (1..=m).all(|i| self.distances[i] >= distance || self.starts[i] > end);
+            if settled {
+                found = Some(TemplateMatch { id: self.template.id, distance: distance / m as f32, start, end });
+                self.candidate = None;
+                for i in 1..=m {
+                    if self.starts[i] <= end {
+                        self.distances[i] = f32::INFINITY;
+                    }
+                }
+            }
+        }
+
+        let limit = self.template.threshold * m as f32;
+        let last = self.distances[m];
+        if last <= limit && self.candidate.map_or(true, |(distance, ..)| last < distance) {
+            self.candidate = Some((last, self.starts[m], t));
+        }
+        found
+    }
+}
+
+/// Finds recorded example gestures in the live linear acceleration.
+pub struct TemplateMatcher {
+    decimator: Decimator,
+    springs: Vec<Spring>,
+    frame: u32,
+}
+
+impl TemplateMatcher {
+    pub fn new(decimation: u8, templates: Vec<Template>) -> Self {
+        Self {
+            decimator: Decimator::new(decimation),
+            springs: templates.into_iter().map(Spring::new).collect(),
+            frame: 0,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TemplateError> {
+        let (decimation, templates) = decode_templates(bytes)?;
+        Ok(Self::new(decimation, templates))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.springs.is_empty()
+    }
+
+    /// Feeds one sample, returning the closest template that just matched.
+    pub fn add_measurement(&mut self, linear_accel: FusionVector) -> Option<TemplateMatch> {
+        let frame = self.decimator.add_measurement(linear_accel)?;
+        let t = self.frame;
+        self.frame += 1;
+
+        let mut best: Option<TemplateMatch> = None;
+        for spring in self.springs.iter_mut() {
+            if let Some(found) = spring.step(frame, t) {
+                if best.map_or(true, |b| found.distance < b.distance) {
+                    best = Some(found);
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+fn bump(len: usize, axis: usize, peak: f32) -> Vec<FusionVector> {
+    (0..len)
+        .map(|i| {
+            let v = peak * F32Ext::sin(core::f32::consts::PI * i as f32 / (len - 1) as f32);
+            let mut c = [0.0; 3];
+            c[axis] = v;
+            FusionVector::new(c[0], c[1], c[2])
+        })
+        .collect()
+}
+
+#[test]
+fn test_templates_round_trip() {
+    let templates = [
+        Template::from_frames(3, 0.2, &bump(10, 2, 1.0)),
+        Template::from_frames(7, 0.125, &bump(20, 0, -2.0)),
+    ];
+    let bytes = encode_templates(4, &templates);
+
+    assert_eq!(bytes.len(), 7 + 4 + 30 + 4 + 60);
+    assert_eq!(decode_templates(&bytes), Ok((4, templates.to_vec())));
+    assert_eq!(decode_templates(&bytes[..bytes.len() - 1]), Err(TemplateError::Truncated));
+    assert_eq!(decode_templates(b"XXXX\x01\x04\x00"), Err(TemplateError::BadMagic));
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(decode_templates(&trailing), Err(TemplateError::TrailingBytes));
+    let mut many = bytes;
+    many[6] = MAX_TEMPLATES as u8 + 1;
+    assert_eq!(decode_templates(&many), Err(TemplateError::TooManyTemplates));
+}
+
+#[test]
+fn test_dtw_absorbs_time_stretch() {
+    let a = bump(20, 2, 1.0);
+    let stretched = bump(30, 2, 1.0);
+    let other_axis = bump(20, 0, 1.0);
+
+    assert!(dtw_distance(&a, &stretched) < 0.05);
+    assert!(dtw_distance(&a, &other_axis) > 0.3);
+}
+
+#[test]
+fn test_matcher_finds_template_in_stream() {
+    let template = Template::from_frames(1, 0.1, &bump(16, 2, 1.0));
+    let mut matcher = TemplateMatcher::new(1, alloc::vec![template]);
+
+    // Quiet, a slower version of the template, quiet, a sideways bump, quiet
+    let mut stream = vec![FusionVector::zero(); 30];
+    stream.extend(bump(22, 2, 1.0));
+    stream.extend(vec![FusionVector::zero(); 30]);
+    stream.extend(bump(16, 1, 1.0));
+    stream.extend(vec![FusionVector::zero(); 30]);
+
+    let matches: Vec<_> = stream.into_iter().filter_map(|s| matcher.add_measurement(s)).collect();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].id, 1);
+    assert!((28..=32).contains(&matches[0].start));
+    assert!((49..=53).contains(&matches[0].end));
+}
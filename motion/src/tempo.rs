@@ -0,0 +1,201 @@
+use alloc::{vec, vec::Vec};
+use core::f32::consts::PI;
+
+use imu_fusion::FusionVector;
+use micromath::F32Ext;
+
+use crate::template::Decimator;
+
+/// Rate at which linear acceleration is kept for the tempo analysis, in Hz.
+const FRAME_RATE: u32 = 50;
+
+/// Among the autocorrelation peaks, the one at the shortest lag is taken as
+/// long as it is this close to the highest one, so that a multiple of the
+/// period is not mistaken for it.
+const PEAK_TOLERANCE: f32 = 0.9;
+
+#[derive(Debug, Clone)]
+pub struct TempoConfig {
+    /// Seconds of movement the estimate is based on.
+    pub window: f32,
+    pub min_bpm: f32,
+    pub max_bpm: f32,
+    /// RMS linear acceleration, in g, under which no tempo is reported.
+    pub min_activity: f32,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        Self {
+            window: 4.0,
+            min_bpm: 40.0,
+            max_bpm: 200.0,
+            min_activity: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tempo {
+    /// Repetitions of the movement per minute.
+    pub bpm: f32,
+    /// Fraction of the current cycle elapsed at the latest frame, in `[0, 1)`.
+    /// Zero is the peak of acceleration along the earth axis that moves the
+    /// most, in its positive sense.
+    pub phase: f32,
+    /// Correlation between the movement and itself one period later, in `[0, 1]`.
+    pub confidence: f32,
+}
+
+/// Finds the dominant repetition frequency of the movement by autocorrelation
+/// of the linear acceleration over the last few seconds.
+pub struct TempoEstimator {
+    config: TempoConfig,
+    frame_rate: f32,
+    decimator: Decimator,
+    frames: Vec<FusionVector>,
+    next: usize,
+    filled: bool,
+}
+
+impl TempoEstimator {
+    pub fn new(config: TempoConfig, sampling_freq: u32) -> Self {
+        let decimation = (sampling_freq / FRAME_RATE).clamp(1, u8::MAX as u32);
+        let frame_rate = sampling_freq as f32 / decimation as f32;
+        let len = F32Ext::round(config.window * frame_rate).max(2.0) as usize;
+        Self {
+            config,
+            frame_rate,
+            decimator: Decimator::new(decimation as u8),
+            frames: vec![FusionVector::zero(); len],
+            next: 0,
+            filled: false,
+        }
+    }
+
+    pub fn add_measurement(&mut self, linear_accel: FusionVector) {
+        if let Some(frame) = self.decimator.add_measurement(linear_accel) {
+            self.frames[self.next] = frame;
+            self.next = (self.next + 1) % self.frames.len();
+            self.filled |= self.next == 0;
+        }
+    }
+
+    /// Tempo over the current window, if the window is full and the wrist
+    /// moves regularly enough.
+    pub fn estimate(&self) -> Option<Tempo> {
+        if !self.filled {
+            return None;
+        }
+        let n = self.frames.len();
+        let mean = self.frames.iter().fold(FusionVector::zero(), |sum, f| sum + *f) * (1.0 / n as f32);
+        // Oldest frame first, laid out once rather than indexed around the
+        // ring at every lag
+        let (newest, oldest) = self.frames.split_at(self.next);
+        let x: Vec<FusionVector> = oldest.iter().chain(newest).map(|f| *f - mean).collect();
+        let dot = |a: FusionVector, b: FusionVector| a.x * b.x + a.y * b.y + a.z * b.z;
+        // Energy of the first `i` frames, so that of any overlap is a difference
+        let mut cumulative = Vec::with_capacity(n + 1);
+        cumulative.push(0.0);
+        for v in &x {
+            cumulative.push(cumulative[cumulative.len() - 1] + dot(*v, *v));
+        }
+
+        let energy = cumulative[n];
+        if F32Ext::sqrt(energy / n as f32) < self.config.min_activity {
+            return None;
+        }
+
+        let min_lag = ((self.frame_rate * 60.0 / self.config.max_bpm) as usize).max(2);
+        let max_lag = (F32Ext::ceil(self.frame_rate * 60.0 / self.config.min_bpm) as usize).min(n / 2);
+        if min_lag >= max_lag {
+            return None;
+        }
+        // Normalized so that a lag at which the movement repeats exactly scores
+        // one, whatever the amplitude over the overlapping parts
+        let correlation = |k: usize| {
+            let cross: f32 = x[..n - k].iter().zip(&x[k..]).map(|(a, b)| dot(*a, *b)).sum();
+            let (head, tail) = (cumulative[n - k], energy - cumulative[k]);
+            cross / F32Ext::sqrt(head * tail).max(f32::EPSILON)
+        };
+        let correlations: Vec<f32> = (min_lag - 1..=max_lag + 1).map(correlation).collect();
+        let c = |k: usize| correlations[k + 1 - min_lag];
+
+        let peaks = (min_lag..=max_lag).filter(|&k| c(k) > 0.0 && c(k) >= c(k - 1) && c(k) >= c(k + 1));
+        let highest = peaks.clone().map(c).fold(0.0, f32::max);
+        let lag = peaks.clone().find(|&k| c(k) >= PEAK_TOLERANCE * highest)?;
+
+        // Refine the period between lags with a parabola through the peak
+        let curvature = c(lag - 1) - 2.0 * c(lag) + c(lag + 1);
+        let offset = if curvature < 0.0 { 0.5 * (c(lag - 1) - c(lag + 1)) / curvature } else { 0.0 };
+        let period = (lag as f32 + offset) / self.frame_rate;
+
+        Some(Tempo {
+            bpm: 60.0 / period,
+            phase: self.phase(period, &x),
+            confidence: c(lag).clamp(0.0, 1.0),
+        })
+    }
+
+    /// Phase of the fundamental at the latest frame, taken on the axis where it
+    /// is the strongest.
+    fn phase(&self, period: f32, x: &[FusionVector]) -> f32 {
+        let n = x.len();
+        let mut re = [0.0f32; 3];
+        let mut im = [0.0f32; 3];
+        for (i, v) in x.iter().enumerate() {
+            // Time relative to the latest frame, negative
+            let t = (i as f32 - (n - 1) as f32) / self.frame_rate;
+            let angle = 2.0 * PI * t / period;
+            let (sin, cos) = (F32Ext::sin(angle), F32Ext::cos(angle));
+            for (axis, value) in [v.x, v.y, v.z].into_iter().enumerate() {
+                re[axis] += value * cos;
+                im[axis] -= value * sin;
+            }
+        }
+        let strongest = (0..3)
+            .max_by(|&a, &b| (re[a] * re[a] + im[a] * im[a]).total_cmp(&(re[b] * re[b] + im[b] * im[b])))
+            .unwrap();
+        let fraction = F32Ext::atan2(im[strongest], re[strongest]) / (2.0 * PI);
+        fraction - F32Ext::floor(fraction)
+    }
+}
+
+#[cfg(test)]
+fn oscillate(estimator: &mut TempoEstimator, freq_hz: f32, seconds: f32, offset_cycles: f32) {
+    // Ends `offset_cycles` after a peak of acceleration along Z
+    let n = (seconds * 200.0) as usize;
+    for i in 0..n {
+        let t = (i as f32 - (n - 1) as f32) / 200.0;
+        let z = 1.0 * F32Ext::cos(2.0 * PI * (freq_hz * t + offset_cycles));
+        estimator.add_measurement(FusionVector::new(0.1 * z, 0.0, z));
+    }
+}
+
+#[test]
+fn test_tempo_of_steady_oscillation() {
+    for (freq_hz, offset) in [(2.0, 0.0), (1.25, 0.25), (3.0, 0.6)] {
+        let mut estimator = TempoEstimator::new(TempoConfig::default(), 200);
+        oscillate(&mut estimator, freq_hz, 5.0, offset);
+
+        let tempo = estimator.estimate().unwrap();
+        assert!(F32Ext::abs(tempo.bpm - 60.0 * freq_hz) < 0.02 * 60.0 * freq_hz, "{freq_hz} Hz: {tempo:?}");
+        assert!(tempo.confidence > 0.9, "{tempo:?}");
+        let phase_error = F32Ext::abs(tempo.phase - offset);
+        // Decimation leaves the latest frame up to a couple of samples behind
+        assert!(phase_error.min(1.0 - phase_error) < 0.05, "{freq_hz} Hz: {tempo:?}");
+    }
+}
+
+#[test]
+fn test_no_tempo_when_still_or_too_early() {
+    let mut estimator = TempoEstimator::new(TempoConfig::default(), 200);
+    oscillate(&mut estimator, 2.0, 2.0, 0.0);
+    assert_eq!(estimator.estimate(), None);
+
+    let mut estimator = TempoEstimator::new(TempoConfig::default(), 200);
+    for _ in 0..1000 {
+        estimator.add_measurement(FusionVector::new(0.01, -0.01, 0.0));
+    }
+    assert_eq!(estimator.estimate(), None);
+}
@@ -0,0 +1,27 @@
+/// A point in time, as far as the tracker is concerned.
+///
+/// The firmware uses `embassy_time::Instant` (behind the `embassy-time`
+/// feature), while host tools can simply count seconds in an `f32`/`f64`.
+pub trait Timestamp: Copy {
+    /// Seconds elapsed from `earlier` until `self`.
+    fn secs_since(&self, earlier: Self) -> f32;
+}
+
+impl Timestamp for f32 {
+    fn secs_since(&self, earlier: Self) -> f32 {
+        self - earlier
+    }
+}
+
+impl Timestamp for f64 {
+    fn secs_since(&self, earlier: Self) -> f32 {
+        (self - earlier) as f32
+    }
+}
+
+#[cfg(feature = "embassy-time")]
+impl Timestamp for embassy_time::Instant {
+    fn secs_since(&self, earlier: Self) -> f32 {
+        self.duration_since(earlier).as_micros() as f32 / 1e6
+    }
+}
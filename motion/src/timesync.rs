@@ -0,0 +1,198 @@
+//! Round trip time synchronization over a message broker, where no NTP
+//! server is at hand.
+//!
+//! An aggregator sends timestamped probes to each device, which echoes them
+//! with the local times it got and answered them at. Each probe also carries
+//! when the echo of the previous one arrived, so that both sides get the four
+//! timestamps of every exchange and discipline a [`WallClock`] mapping the
+//! device's clock to the aggregator's. Messages are text, with times in µs:
+//!
+//! - probe: `seq,t1` or `seq,t1,previous_seq,previous_t4`
+//! - echo: `seq,t1,t2,t3`
+
+use core::fmt;
+
+use crate::clock::{ClockSample, WallClock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    pub seq: u32,
+    /// When it was sent, on the aggregator's clock.
+    pub sent: u64,
+    /// Sequence number and arrival time of the last echo the aggregator got.
+    pub previous: Option<(u32, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Echo {
+    pub seq: u32,
+    /// When the probe was sent, on the aggregator's clock.
+    pub probe_sent: u64,
+    /// When the probe arrived and when the echo left, on the device's clock.
+    pub received: u64,
+    pub sent: u64,
+}
+
+fn fields<const N: usize>(text: &str) -> Option<[u64; N]> {
+    let mut fields = [0; N];
+    let mut parts = text.trim().split(',');
+    for field in fields.iter_mut() {
+        *field = parts.next()?.trim().parse().ok()?;
+    }
+    parts.next().is_none().then_some(fields)
+}
+
+impl Probe {
+    pub fn parse(text: &str) -> Option<Probe> {
+        if let Some([seq, sent, previous_seq, previous_arrival]) = fields(text) {
+            Some(Probe {
+                seq: seq.try_into().ok()?,
+                sent,
+                previous: Some((previous_seq.try_into().ok()?, previous_arrival)),
+            })
+        } else {
+            let [seq, sent] = fields(text)?;
+            Some(Probe { seq: seq.try_into().ok()?, sent, previous: None })
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.seq, self.sent)?;
+        if let Some((seq, arrival)) = self.previous {
+            write!(f, ",{seq},{arrival}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Echo {
+    pub fn parse(text: &str) -> Option<Echo> {
+        let [seq, probe_sent, received, sent] = fields(text)?;
+        Some(Echo { seq: seq.try_into().ok()?, probe_sent, received, sent })
+    }
+}
+
+impl fmt::Display for Echo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.seq, self.probe_sent, self.received, self.sent)
+    }
+}
+
+/// The device side, whose clock gets mapped to the aggregator's.
+#[derive(Debug, Default)]
+pub struct Responder {
+    last: Option<Echo>,
+}
+
+impl Responder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers a probe that arrived at local time `received`, the echo to
+    /// leave at `sent`. Returns the echo and, when the probe tells when the
+    /// previous echo arrived, the clock sample of that exchange.
+    pub fn answer(&mut self, probe: &Probe, received: u64, sent: u64) -> (Echo, Option<ClockSample>) {
+        let sample = match (self.last, probe.previous) {
+            (Some(last), Some((seq, arrival))) if last.seq == seq => {
+                ClockSample::from_echo(last.probe_sent, last.received, last.sent, arrival)
+            }
+            _ => None,
+        };
+        let echo = Echo { seq: probe.seq, probe_sent: probe.sent, received, sent };
+        self.last = Some(echo);
+        (echo, sample)
+    }
+}
+
+/// The aggregator side, for one device.
+#[derive(Debug, Default)]
+pub struct Prober {
+    seq: u32,
+    /// The last probe sent, until echoed.
+    outstanding: Option<(u32, u64)>,
+    /// The last echo received.
+    completed: Option<(u32, u64)>,
+    clock: WallClock,
+}
+
+impl Prober {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next probe, sent at `now` on the aggregator's clock. A probe not
+    /// echoed by then is given up.
+    pub fn probe(&mut self, now: u64) -> Probe {
+        self.seq = self.seq.wrapping_add(1);
+        self.outstanding = Some((self.seq, now));
+        Probe { seq: self.seq, sent: now, previous: self.completed }
+    }
+
+    /// Takes an echo arriving at `now`, returning the clock sample of the
+    /// exchange.
+    pub fn receive(&mut self, echo: &Echo, now: u64) -> Option<ClockSample> {
+        if self.outstanding != Some((echo.seq, echo.probe_sent)) {
+            return None;
+        }
+        self.outstanding = None;
+        self.completed = Some((echo.seq, now));
+        let sample = ClockSample::from_echo(echo.probe_sent, echo.received, echo.sent, now)?;
+        self.clock.add_sample(sample);
+        Some(sample)
+    }
+
+    /// The device's clock as mapped to the aggregator's: its `wall_time` of a
+    /// device timestamp is the aggregator's time.
+    pub fn clock(&self) -> &WallClock {
+        &self.clock
+    }
+}
+
+#[test]
+fn test_messages() {
+    let probe = Probe { seq: 7, sent: 1_700_000_000_000_000, previous: Some((6, 1_699_999_998_000_120)) };
+    assert_eq!(Probe::parse(&alloc::format!("{probe}")), Some(probe));
+    let first = Probe { previous: None, ..probe };
+    assert_eq!(Probe::parse(&alloc::format!("{first}")), Some(first));
+    let echo = Echo { seq: 7, probe_sent: 1_700_000_000_000_000, received: 5_000_000, sent: 5_000_200 };
+    assert_eq!(Echo::parse(&alloc::format!("{echo}")), Some(echo));
+
+    assert_eq!(Probe::parse("7"), None);
+    assert_eq!(Probe::parse("7,1,2"), None);
+    assert_eq!(Echo::parse("7,1,2,3,4"), None);
+    assert_eq!(Echo::parse("x,1,2,3"), None);
+}
+
+#[test]
+fn test_both_sides_agree() {
+    // The device boots 1.7e15 µs after the aggregator's epoch and runs 30 ppm
+    // fast; messages take 5 ms one way and 9 ms the other, now and then 80 more
+    let to_device = |aggregator: u64| ((aggregator - 1_700_000_000_000_000) as f64 * (1.0 + 30e-6)) as u64;
+    let mut prober = Prober::new();
+    let mut responder = Responder::new();
+    let mut device_clock = WallClock::new();
+
+    for i in 0..30u64 {
+        let t1 = 1_700_000_010_000_000 + i * 2_000_000;
+        let probe = Probe::parse(&alloc::format!("{}", prober.probe(t1))).unwrap();
+        let late = if i % 4 == 1 { 80_000 } else { 0 };
+        let t2 = to_device(t1 + 5_000 + late);
+        let (echo, sample) = responder.answer(&probe, t2, t2 + 300);
+        if let Some(sample) = sample {
+            device_clock.add_sample(sample);
+        }
+        let t4 = t1 + 5_000 + late + 300 + 9_000;
+        prober.receive(&Echo::parse(&alloc::format!("{echo}")).unwrap(), t4).unwrap();
+    }
+
+    // Off by half the asymmetry of the path, 2 ms
+    let device_time = to_device(1_700_000_075_000_000);
+    for clock in [prober.clock(), &device_clock] {
+        let error = clock.wall_time(device_time).unwrap() as i64 - 1_700_000_075_000_000;
+        assert!((error - 2_000).abs() < 100, "{error} µs off");
+        assert!((clock.drift_ppm() + 30.0).abs() < 5.0, "{} ppm", clock.drift_ppm());
+    }
+}
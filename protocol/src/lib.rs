@@ -0,0 +1,365 @@
+//! Wire format of the events the wristband publishes over MQTT.
+//!
+//! Every frame starts with a fixed header, all fields little-endian:
+//!
+//! | offset | size | field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 1    | format version, [`VERSION`]             |
+//! | 1      | 1    | payload type, see [`PayloadType`]       |
+//! | 2      | 4    | sequence number, per device, wrapping   |
+//! | 6      | 8    | timestamp, in µs                        |
+//! | 14     | ...  | payload                                 |
+//!
+//! Timestamps count µs since the Unix epoch once the device has synchronized
+//! its clock, and since it booted until then; [`is_wall_time`] tells which.
+//!
+//! A payload may be longer than the fields of its type: decoders ignore the
+//! extra bytes, so that fields can be appended to a payload without a new
+//! version. Anything else that older decoders would misread, such as a new
+//! layout for an existing type, takes a new version.
+//!
+//! The crate is shared by the firmware, which encodes frames, and by the host
+//! tools, which decode them.
+#![no_std]
+
+/// Version of the format written by [`Encoder`], the only one decoded.
+pub const VERSION: u8 = 1;
+
+pub const HEADER_SIZE: usize = 14;
+
+/// Size of the largest frame of this version.
+pub const MAX_FRAME_SIZE: usize = HEADER_SIZE + DETECTION_SIZE;
+
+const DETECTION_SIZE: usize = 21;
+
+const ORIENTATION_SIZE: usize = 16;
+
+/// Scale of the quantized quaternion components: 1 is `i16::MAX`.
+pub const QUATERNION_SCALE: f32 = i16::MAX as f32;
+
+/// 2020-01-01, in µs since the Unix epoch. No device stays up that long.
+const WALL_TIME_MIN: u64 = 1_577_836_800_000_000;
+
+/// Whether a timestamp is wall time rather than time since boot.
+pub fn is_wall_time(timestamp: u64) -> bool {
+    timestamp >= WALL_TIME_MIN
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer cannot hold the encoded frame.
+    BufferTooSmall,
+    /// The frame ends before its header or payload does.
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownPayload(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PayloadType {
+    Class = 1,
+    Direction = 2,
+    Detection = 3,
+    Orientation = 4,
+}
+
+impl PayloadType {
+    pub fn from_u8(value: u8) -> Option<PayloadType> {
+        match value {
+            1 => Some(PayloadType::Class),
+            2 => Some(PayloadType::Direction),
+            3 => Some(PayloadType::Detection),
+            4 => Some(PayloadType::Orientation),
+            _ => None,
+        }
+    }
+}
+
+/// A detected movement with its intensity and timing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    /// Digit of the direction, as in `MovementDirection::as_digit`.
+    pub direction: u8,
+    /// Magnitude of the acceleration, in g.
+    pub magnitude: f32,
+    /// Degrees from the nearest boundary between classes.
+    pub confidence: f32,
+    /// When the movement started, in µs like the header's timestamp.
+    pub onset: u64,
+    /// Seconds the state before it lasted.
+    pub previous_duration: f32,
+}
+
+/// Orientation of the device, quantized to 16 bits a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    /// `w`, `x`, `y` and `z` of the unit quaternion, times
+    /// [`QUATERNION_SCALE`].
+    pub quaternion: [i16; 4],
+    /// Roll, pitch and yaw, in hundredths of a degree.
+    pub euler: [i16; 3],
+    /// Clockwise from magnetic north, in hundredths of a degree.
+    pub heading: u16,
+}
+
+impl Orientation {
+    /// Out of a unit quaternion, Euler angles in degrees within ±180 and a
+    /// heading in degrees within [0, 360).
+    pub fn quantize(quaternion: [f32; 4], euler: [f32; 3], heading: f32) -> Orientation {
+        Orientation {
+            quaternion: quaternion.map(|c| round(c.clamp(-1.0, 1.0) * QUATERNION_SCALE) as i16),
+            euler: euler.map(|a| round(a * 100.0) as i16),
+            heading: round(heading * 100.0) as u16 % 36_000,
+        }
+    }
+
+    pub fn quaternion(&self) -> [f32; 4] {
+        self.quaternion.map(|c| c as f32 / QUATERNION_SCALE)
+    }
+
+    /// In degrees.
+    pub fn euler(&self) -> [f32; 3] {
+        self.euler.map(|a| a as f32 / 100.0)
+    }
+
+    /// In degrees.
+    pub fn heading(&self) -> f32 {
+        self.heading as f32 / 100.0
+    }
+}
+
+/// To the nearest integer, which `core` lacks; casts then saturate.
+fn round(value: f32) -> f32 {
+    if value < 0.0 { value - 0.5 } else { value + 0.5 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Payload {
+    /// Digit of a horizontal, vertical or diagonal class, as in
+    /// `MovementClass::as_digit`.
+    Class(u8),
+    /// Digit of a signed direction, as in `MovementDirection::as_digit`.
+    Direction(u8),
+    Detection(Detection),
+    Orientation(Orientation),
+}
+
+impl Payload {
+    pub fn payload_type(&self) -> PayloadType {
+        match self {
+            Payload::Class(_) => PayloadType::Class,
+            Payload::Direction(_) => PayloadType::Direction,
+            Payload::Detection(_) => PayloadType::Detection,
+            Payload::Orientation(_) => PayloadType::Orientation,
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        match *self {
+            Payload::Class(digit) | Payload::Direction(digit) => {
+                *buf.first_mut().ok_or(Error::BufferTooSmall)? = digit;
+                Ok(1)
+            }
+            Payload::Detection(d) => {
+                let buf = buf.get_mut(..DETECTION_SIZE).ok_or(Error::BufferTooSmall)?;
+                buf[0] = d.direction;
+                buf[1..5].copy_from_slice(&d.magnitude.to_le_bytes());
+                buf[5..9].copy_from_slice(&d.confidence.to_le_bytes());
+                buf[9..17].copy_from_slice(&d.onset.to_le_bytes());
+                buf[17..21].copy_from_slice(&d.previous_duration.to_le_bytes());
+                Ok(DETECTION_SIZE)
+            }
+            Payload::Orientation(o) => {
+                let buf = buf.get_mut(..ORIENTATION_SIZE).ok_or(Error::BufferTooSmall)?;
+                let fields = o.quaternion.into_iter().chain(o.euler).map(i16::to_le_bytes)
+                    .chain([o.heading.to_le_bytes()]);
+                for (chunk, bytes) in buf.chunks_exact_mut(2).zip(fields) {
+                    chunk.copy_from_slice(&bytes);
+                }
+                Ok(ORIENTATION_SIZE)
+            }
+        }
+    }
+
+    fn decode(payload_type: PayloadType, bytes: &[u8]) -> Result<Payload, Error> {
+        match payload_type {
+            PayloadType::Class => Ok(Payload::Class(*bytes.first().ok_or(Error::Truncated)?)),
+            PayloadType::Direction => Ok(Payload::Direction(*bytes.first().ok_or(Error::Truncated)?)),
+            PayloadType::Detection => {
+                let bytes = bytes.get(..DETECTION_SIZE).ok_or(Error::Truncated)?;
+                Ok(Payload::Detection(Detection {
+                    direction: bytes[0],
+                    magnitude: f32::from_le_bytes(bytes[1..5].try_into().unwrap()),
+                    confidence: f32::from_le_bytes(bytes[5..9].try_into().unwrap()),
+                    onset: u64::from_le_bytes(bytes[9..17].try_into().unwrap()),
+                    previous_duration: f32::from_le_bytes(bytes[17..21].try_into().unwrap()),
+                }))
+            }
+            PayloadType::Orientation => {
+                let bytes = bytes.get(..ORIENTATION_SIZE).ok_or(Error::Truncated)?;
+                let field = |i: usize| [bytes[2 * i], bytes[2 * i + 1]];
+                Ok(Payload::Orientation(Orientation {
+                    quaternion: core::array::from_fn(|i| i16::from_le_bytes(field(i))),
+                    euler: core::array::from_fn(|i| i16::from_le_bytes(field(4 + i))),
+                    heading: u16::from_le_bytes(field(7)),
+                }))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    /// Raw payload type, which may be one this version does not know.
+    pub payload_type: u8,
+    pub sequence: u32,
+    pub timestamp: u64,
+}
+
+impl Header {
+    /// Decodes the header of a frame of any version, returning it with the
+    /// payload bytes. Consumers can track sequence numbers with it even
+    /// through frames they cannot decode.
+    pub fn decode(bytes: &[u8]) -> Result<(Header, &[u8]), Error> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+        let header = Header {
+            version: bytes[0],
+            payload_type: bytes[1],
+            sequence: u32::from_le_bytes(bytes[2..6].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(bytes[6..14].try_into().unwrap()),
+        };
+        Ok((header, &bytes[HEADER_SIZE..]))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub sequence: u32,
+    /// In µs, see [`is_wall_time`].
+    pub timestamp: u64,
+    pub payload: Payload,
+}
+
+impl Frame {
+    pub fn decode(bytes: &[u8]) -> Result<Frame, Error> {
+        let (header, payload) = Header::decode(bytes)?;
+        if header.version != VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        let payload_type = PayloadType::from_u8(header.payload_type)
+            .ok_or(Error::UnknownPayload(header.payload_type))?;
+        Ok(Frame {
+            sequence: header.sequence,
+            timestamp: header.timestamp,
+            payload: Payload::decode(payload_type, payload)?,
+        })
+    }
+
+    /// Writes the frame into `buf`, returning its length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() < HEADER_SIZE {
+            return Err(Error::BufferTooSmall);
+        }
+        buf[0] = VERSION;
+        buf[1] = self.payload.payload_type() as u8;
+        buf[2..6].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[6..14].copy_from_slice(&self.timestamp.to_le_bytes());
+        Ok(HEADER_SIZE + self.payload.encode(&mut buf[HEADER_SIZE..])?)
+    }
+}
+
+/// Numbers the frames of a device.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    sequence: u32,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Self { sequence: 0 }
+    }
+
+    /// Writes the next frame into `buf`, returning its length. Frames that do
+    /// not fit do not take a sequence number.
+    pub fn encode(&mut self, timestamp: u64, payload: Payload, buf: &mut [u8]) -> Result<usize, Error> {
+        let frame = Frame { sequence: self.sequence, timestamp, payload };
+        let len = frame.encode(buf)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(len)
+    }
+}
+
+/// Frames lost between two received from the same device, given that MQTT
+/// keeps them in order.
+pub fn frames_missed(previous: u32, current: u32) -> u32 {
+    current.wrapping_sub(previous).wrapping_sub(1)
+}
+
+#[test]
+fn test_round_trip() {
+    let detection = Detection {
+        direction: 9,
+        magnitude: 0.42,
+        confidence: 12.5,
+        onset: 73_012_345,
+        previous_duration: 1.25,
+    };
+    let mut encoder = Encoder::new();
+    let mut buf = [0; MAX_FRAME_SIZE];
+    for (i, payload) in [Payload::Class(2), Payload::Direction(4), Payload::Detection(detection)].into_iter().enumerate() {
+        let len = encoder.encode(1_000 * i as u64, payload, &mut buf).unwrap();
+        let frame = Frame::decode(&buf[..len]).unwrap();
+        assert_eq!(frame, Frame { sequence: i as u32, timestamp: 1_000 * i as u64, payload });
+    }
+    assert_eq!(encoder.encode(0, Payload::Detection(detection), &mut buf[..20]), Err(Error::BufferTooSmall));
+    assert_eq!(encoder.sequence, 3);
+}
+
+#[test]
+fn test_orientation() {
+    let orientation = Orientation::quantize([0.6, 0.0, -0.8, 0.0], [12.34, -89.99, 180.0], 359.999);
+    assert_eq!(orientation.quaternion, [19660, 0, -26214, 0]);
+    assert_eq!(orientation.euler, [1234, -8999, 18000]);
+    assert_eq!(orientation.heading, 0);
+    assert!((orientation.quaternion()[2] + 0.8).abs() < 1e-4);
+    assert_eq!(orientation.euler()[1], -89.99);
+
+    // Past the unit sphere by rounding errors
+    assert_eq!(Orientation::quantize([1.0001, 0.0, 0.0, -1.0001], [0.0; 3], 0.0).quaternion, [i16::MAX, 0, 0, -i16::MAX]);
+
+    let mut buf = [0; MAX_FRAME_SIZE];
+    let len = Encoder::new().encode(7, Payload::Orientation(orientation), &mut buf).unwrap();
+    assert_eq!(len, HEADER_SIZE + ORIENTATION_SIZE);
+    assert_eq!(Frame::decode(&buf[..len]).unwrap().payload, Payload::Orientation(orientation));
+    assert_eq!(Frame::decode(&buf[..len - 1]), Err(Error::Truncated));
+}
+
+#[test]
+fn test_decoding_errors() {
+    let frame = Frame { sequence: u32::MAX, timestamp: 5, payload: Payload::Direction(8) };
+    let mut buf = [0; MAX_FRAME_SIZE + 4];
+    let len = frame.encode(&mut buf).unwrap();
+    assert_eq!(len, HEADER_SIZE + 1);
+
+    // Bytes appended by a later revision of the payload are skipped
+    assert_eq!(Frame::decode(&buf[..len + 4]), Ok(frame));
+    assert_eq!(Frame::decode(&buf[..len - 1]), Err(Error::Truncated));
+    assert_eq!(Frame::decode(&buf[..HEADER_SIZE - 1]), Err(Error::Truncated));
+
+    buf[1] = 0x7f;
+    assert_eq!(Frame::decode(&buf[..len]), Err(Error::UnknownPayload(0x7f)));
+    assert_eq!(Header::decode(&buf[..len]).unwrap().0.sequence, u32::MAX);
+    buf[0] = VERSION + 1;
+    assert_eq!(Frame::decode(&buf[..len]), Err(Error::UnsupportedVersion(VERSION + 1)));
+}
+
+#[test]
+fn test_frames_missed() {
+    assert_eq!(frames_missed(4, 5), 0);
+    assert_eq!(frames_missed(4, 8), 3);
+    assert_eq!(frames_missed(u32::MAX, 1), 1);
+}
@@ -15,4 +15,39 @@ pub struct FirmwareConfig {
     mqtt_pass: &'static str,
     #[default("")]
     mqtt_id: &'static str,
+    // Publish only the horizontal/vertical/diagonal class of movements, as
+    // before directions were signed
+    #[default(false)]
+    legacy_directions: bool,
+    // Publish every field of the detection event instead of the direction
+    // digit alone
+    #[default(false)]
+    detection_events: bool,
+    // Publish events as versioned binary frames (see the motion-protocol
+    // crate) instead of text
+    #[default(false)]
+    binary_events: bool,
+    // Times per second the movement tempo is published, 0 to disable
+    #[default(4)]
+    tempo_report_hz: u32,
+    // Times per second the orientation is published at boot, 0 to only start
+    // on the orientation command
+    #[default(0)]
+    orientation_report_hz: u32,
+    // Estimator of the orientation: fusion, mahony or kalman
+    #[default("fusion")]
+    orientation_estimator: &'static str,
+    // SNTP server the wall clock is synchronized to, empty to disable
+    #[default("pool.ntp.org")]
+    ntp_host: &'static str,
+    // Seconds between SNTP polls
+    #[default(64)]
+    ntp_poll_secs: u32,
+    // Synchronize the wall clock to the probes of the sync aggregator on
+    // <mqtt_id>/timesync instead of over SNTP
+    #[default(false)]
+    mqtt_time_sync: bool,
+    // Append the wall time to text payloads
+    #[default(true)]
+    stamp_messages: bool,
 }
@@ -1,11 +1,27 @@
 use heapless::Vec;
 use const_format::formatcp;
-use crate::config::FIRMWARE_CONFIG;
+use embassy_time::Instant;
+use motion::{calibration::CalibrationUpdate, DenoiserKind};
+use crate::{config::FIRMWARE_CONFIG, timesync};
 
 #[derive(Clone)]
 pub enum SysCommands {
     Restart,
     PowerOff,
+    SetDenoiser(DenoiserKind),
+    /// Measure the gyroscope offsets again, the device being still
+    CalibrateGyro,
+    /// Forget the stored calibration, measuring the gyroscope offsets again
+    ResetCalibration,
+    /// Collect magnetometer readings while the device is turned every which
+    /// way, then fit and apply its hard- and soft-iron calibration
+    CalibrateMag,
+    /// Walk through the six poses of the accelerometer calibration, then fit
+    /// and apply it
+    CalibrateAcc,
+    UpdateCalibration(CalibrationUpdate),
+    /// Publish the orientation this many times per second, 0 to stop
+    SetOrientationRate(u32),
 }
 
 #[repr(u8)]
@@ -18,8 +34,16 @@ pub enum SysStates {
     ConnectedBroker = 210,
 }
 
+/// LED hues asking for each pose of the accelerometer calibration, in the
+/// order of `motion::acc_calibration::POSES`: red, yellow, green, cyan, blue
+/// and magenta.
+pub const POSE_HUES: [u8; 6] = [0, 43, 85, 128, 170, 213];
+
 pub enum MessageTopics {
     Event,
+    Gesture,
+    Tempo,
+    Orientation,
     Report,
 }
 
@@ -27,14 +51,43 @@ impl MessageTopics {
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Event => formatcp!("{}/event", FIRMWARE_CONFIG.mqtt_id),
+            Self::Gesture => formatcp!("{}/gesture", FIRMWARE_CONFIG.mqtt_id),
+            Self::Tempo => formatcp!("{}/tempo", FIRMWARE_CONFIG.mqtt_id),
+            Self::Orientation => formatcp!("{}/orientation", FIRMWARE_CONFIG.mqtt_id),
             Self::Report => formatcp!("{}/report", FIRMWARE_CONFIG.mqtt_id),
         }
     }
 }
 
-pub const MAX_SIZE: usize = 10;
+pub const MAX_SIZE: usize = 80;
+
+const _: () = assert!(MAX_SIZE >= motion_protocol::MAX_FRAME_SIZE);
 
 pub struct MQTTMessage {
     pub topic: MessageTopics,
     pub payload: Vec<u8, MAX_SIZE>,
 }
+
+impl MQTTMessage {
+    /// A text message, followed by the wall time at `instant` in milliseconds
+    /// when `stamp_messages` is set and the clock is synchronized. Text that
+    /// does not fit is cut short, and the stamp left out, rather than
+    /// panicking.
+    pub fn text(topic: MessageTopics, text: core::fmt::Arguments, instant: Instant) -> Self {
+        let mut payload = heapless::String::<MAX_SIZE>::new();
+        if core::fmt::write(&mut payload, text).is_err() {
+            log::warn!("Message to {} cut short", topic.as_str());
+        }
+        if FIRMWARE_CONFIG.stamp_messages {
+            if let Some(wall_time) = timesync::wall_time(instant) {
+                // Any u64 fits, and the stamp is appended whole or not at all
+                let mut stamp = heapless::String::<24>::new();
+                let _ = core::fmt::write(&mut stamp, format_args!(";{}", wall_time / 1000));
+                if payload.push_str(&stamp).is_err() {
+                    log::warn!("No room to stamp a message to {}", topic.as_str());
+                }
+            }
+        }
+        Self { topic, payload: Vec::from_slice(payload.as_bytes()).unwrap() }
+    }
+}
@@ -60,20 +60,29 @@ use smart_leds::{
 };
 
 use icm20948_async::{AccRange, AccDlp, AccUnit, GyrDlp, GyrRange, GyrUnit, IcmError, Icm20948};
-use imu_fusion::{FusionMatrix, FusionVector};
+use imu_fusion::FusionVector;
 
 const NUM_BLOCKS: usize = 2;
-use core::f32::consts::PI;
+// Orientation stream rate of `orientation:on`, unless one is configured
+const DEFAULT_ORIENTATION_HZ: u32 = 20;
 
-mod analysis;
 mod config;
 mod control;
-mod imu_tracker;
+mod storage;
+mod timesync;
 
 use crate::config::FIRMWARE_CONFIG;
-use imu_tracker::ImuTracker;
-use analysis::Analysis;
+use motion::{
+    acc_calibration::SixPositionCalibrator,
+    alignment::AttitudeAligner,
+    calibration::{CalibrationUpdate, GyroOffsetEstimator},
+    imu_tracker::align_magnetometer,
+    mag_calibration::MagCalibrator,
+    AhrsConfig, Analysis, AnalysisConfig, DenoiserKind, EstimatorKind, GestureConfig, ImuCalibration, GestureRecognizer, ImuTracker, TempoConfig, TempoEstimator, TemplateMatcher,
+};
+use motion_protocol::{Detection, Encoder, Orientation, Payload};
 use control::{
+    POSE_HUES,
     SysCommands,
     SysStates,
     MessageTopics,
@@ -95,14 +104,62 @@ fn init_heap() {
 
 static mut APP_CORE_STACK: CPUStack<10000> = CPUStack::new();
 
+/// Gesture templates recorded with the `template` host tool; empty when no
+/// `gesture_templates.bin` was present at build time.
+static GESTURE_TEMPLATES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/gesture_templates.bin"));
+
 
 #[embassy_executor::task]
 async fn motion_analysis(
     mut i2c: I2C<'static, I2C0, Async>,
     event_sender: Sender<'static, CriticalSectionRawMutex, MQTTMessage, NUM_BLOCKS>,
     mut cmd_receiver: Subscriber<'static, CriticalSectionRawMutex, SysCommands, 1, 3, 2>,
-    mut flag_pin: Output<'static, GpioPin<2>>
+    mut flag_pin: Output<'static, GpioPin<2>>,
+    sender_led: Sender<'static, CriticalSectionRawMutex, u8, 1>,
+    stored_calibration: Result<ImuCalibration, storage::StorageError>,
 ) {
+    // Numbers the binary event frames, across restarts of the IMU
+    let mut encoder = Encoder::new();
+    // The orientation frames on their own, so that gaps in either tell lost
+    // frames
+    let mut orientation_encoder = Encoder::new();
+
+    // The gyroscope offsets are only measured when none were stored
+    let (mut calibration, mut calibrate_gyro) = match stored_calibration {
+        Ok(calibration) => {
+            log::info!("Loaded the IMU calibration: {calibration:?}");
+            (calibration, false)
+        }
+        Err(e) => {
+            log::warn!("No IMU calibration stored ({e:?}), using the defaults");
+            (ImuCalibration::default(), true)
+        }
+    };
+
+    const IMU_SAMPLE_FREQ: u32 = 200;
+    const IMU_SAMPLE_PERIOD: Duration = Duration::from_hz(IMU_SAMPLE_FREQ as u64);
+    // Readings taken, still, before tracking starts
+    const STILL_SAMPLES: u32 = 250;
+    // Time the device is turned around for the magnetometer calibration, and
+    // what a fit must achieve to be applied
+    const MAG_CALIBRATION_TIME: Duration = Duration::from_secs(20);
+    const MAX_MAG_RESIDUAL: f32 = 0.05;
+    const MIN_MAG_COVERAGE: u8 = 6;
+    // Time given to walk through the poses of the accelerometer calibration
+    const ACC_CALIBRATION_TIMEOUT: Duration = Duration::from_secs(120);
+    // Fastest orientation stream, which the network loop keeps up with
+    const MAX_ORIENTATION_HZ: u32 = 50;
+    // Denoiser chosen by remote command, kept across restarts of the IMU
+    let mut denoiser = AnalysisConfig::default().denoiser;
+    // Rate of the orientation stream, kept across restarts of the IMU
+    let mut orientation_hz = FIRMWARE_CONFIG.orientation_report_hz.min(MAX_ORIENTATION_HZ);
+    let ahrs_config = AhrsConfig {
+        estimator: EstimatorKind::from_name(FIRMWARE_CONFIG.orientation_estimator).unwrap_or_else(|| {
+            log::warn!("Unknown orientation estimator {}, using fusion", FIRMWARE_CONFIG.orientation_estimator);
+            EstimatorKind::Fusion
+        }),
+        ..AhrsConfig::default()
+    };
     'full: loop {
         // Create and await IMU object
         let imu_configured = Icm20948::new_i2c(&mut i2c, Delay)
@@ -130,30 +187,68 @@ async fn motion_analysis(
             }
         };
 
-        // Calibrate gyroscope offsets using 100 samples
-        log::info!("Calibrating gyroscopes, keep still...");
-        let _gyr_cal = imu.gyr_calibrate(250).await.is_ok();
-        log::info!("... done calibrating gyros.");
+        // Held still, the device gives the gyroscope offsets, when they are to
+        // be measured, and the attitude tracking starts from
+        log::info!("Keep still...");
+        let mut estimator = GyroOffsetEstimator::new();
+        let mut aligner = AttitudeAligner::new();
+        for _ in 0..STILL_SAMPLES {
+            Timer::after(IMU_SAMPLE_PERIOD).await;
+            if let Ok(meas) = imu.read_9dof().await {
+                estimator.add(FusionVector::new(meas.gyr.x, meas.gyr.y, meas.gyr.z));
+                aligner.add(FusionVector::new(meas.acc.x, meas.acc.y, meas.acc.z),
+                            align_magnetometer(FusionVector::new(meas.mag.x, meas.mag.y, meas.mag.z)));
+            }
+        }
+        if calibrate_gyro {
+            match estimator.offset() {
+                Some(offset) => {
+                    calibration.gyr_offset = offset;
+                    calibrate_gyro = false;
+                    log::info!("... done calibrating gyros: {offset:?}");
+                    storage::store_calibration(calibration);
+                }
+                None => log::warn!("... the device moved, keeping the gyro offsets it had"),
+            }
+        }
 
         // Setup motion analysis
-        const IMU_SAMPLE_PERIOD: Duration = Duration::from_hz(200);
-
-        let acc_misalignment = FusionMatrix::identity();
-        let acc_offset = FusionVector::zero();
-        let acc_sensitivity = FusionVector::ones();
-        let gyr_offset = FusionVector::zero();
-        let mut tracker = ImuTracker::new(IMU_SAMPLE_PERIOD, Instant::now(), 1000.0f32,
-                                        acc_misalignment, acc_sensitivity, acc_offset, gyr_offset);
+        let mut tracker = ImuTracker::new(IMU_SAMPLE_FREQ, Instant::now(), 1000.0f32, &ahrs_config, &calibration);
+        match aligner.mean() {
+            Some((acc, mag)) if tracker.align(acc, mag) => log::info!("... starting from the attitude the device lies in"),
+            _ => log::warn!("... the device moved, aligning its attitude while tracking"),
+        }
         //let mut analysis = Analysis::default();
-        const DIAGONAL_BAND_DEG: f32 = 25.0;
-        let mut analysis = Analysis::new(60, 30, 0.12,
-                                                DIAGONAL_BAND_DEG*PI/180.0,
-                                                (90.0 - DIAGONAL_BAND_DEG)*PI/180.0);
+        // Leaving a movement or the diagonal band takes a bit more than
+        // entering it, and changes must last 20 ms, so that borderline strokes
+        // do not chatter
+        let analysis_config = AnalysisConfig { denoiser, ..AnalysisConfig::default() };
+        let mut analysis = Analysis::new(&analysis_config, Instant::now());
+        let mut gestures = GestureRecognizer::new(GestureConfig::default(), Instant::now());
+        let mut templates = if GESTURE_TEMPLATES.is_empty() {
+            None
+        } else {
+            match TemplateMatcher::from_bytes(GESTURE_TEMPLATES) {
+                Ok(matcher) => Some(matcher),
+                Err(e) => {
+                    log::error!("Invalid gesture templates: {e:?}");
+                    None
+                }
+            }
+        };
+        let mut tempo = TempoEstimator::new(TempoConfig::default(), IMU_SAMPLE_FREQ);
         // Main loop: reading the sensor and sending movement detection data to the broker
 
         // modulus to send motion direction samples at a low rate
         const DETECTION_REPORT_FREQ: Duration = Duration::from_hz(8);
         const MOD_DETECTION: u32 = (DETECTION_REPORT_FREQ.as_ticks() / IMU_SAMPLE_PERIOD.as_ticks()) as u32;
+        // modulus to send the tempo estimate at the configured rate
+        let mod_tempo = IMU_SAMPLE_FREQ.checked_div(FIRMWARE_CONFIG.tempo_report_hz).map(|m| m.max(1));
+        // modulus to send the orientation at the rate last asked for
+        let mut mod_orientation = IMU_SAMPLE_FREQ.checked_div(orientation_hz).map(|m| m.max(1));
+
+        let mut mag_calibration: Option<(MagCalibrator, Instant)> = None;
+        let mut acc_calibration: Option<(SixPositionCalibrator, Instant)> = None;
 
         let mut id: u32 = 0;
         'sample: loop {
@@ -166,6 +261,8 @@ async fn motion_analysis(
                 Either::First(_) => {
                     id += 1;
                     let should_send_sample = id % MOD_DETECTION == 0;
+                    let should_send_tempo = mod_tempo.is_some_and(|m| id % m == 0);
+                    let should_send_orientation = mod_orientation.is_some_and(|m| id % m == 0);
 
                     let now = Instant::now();
                     flag_pin.set_high();
@@ -173,21 +270,166 @@ async fn motion_analysis(
                         Ok(meas) => {
                             let acc = FusionVector::new(meas.acc.x, meas.acc.y, meas.acc.z);
                             let gyr = FusionVector::new(meas.gyr.x, meas.gyr.y, meas.gyr.z);
-
-                            // Magnetometer axes are reflected along X axis, as per the datasheet
-                            let mag = FusionVector::new(meas.mag.x, -meas.mag.y, -meas.mag.z);
+                            let mag = align_magnetometer(FusionVector::new(meas.mag.x, meas.mag.y, meas.mag.z));
+                            // The device lies on a table meanwhile: nothing else is tracked
+                            if let Some((calibrator, end)) = acc_calibration.as_mut() {
+                                flag_pin.set_low();
+                                if let Some(pose) = calibrator.add(acc, gyr - calibration.gyr_offset.fusion()) {
+                                    log::info!("Captured the {} pose", pose.as_str());
+                                    let event = MQTTMessage::text(MessageTopics::Report,
+                                                                  format_args!("acc-cal,{}", pose.as_str()), now);
+                                    event_sender.send(event).await;
+                                    if let Some(next) = calibrator.pose() {
+                                        sender_led.send(POSE_HUES[next as usize]).await;
+                                    }
+                                }
+                                let report = match calibrator.pose() {
+                                    Some(_) if now < *end => continue 'sample,
+                                    Some(_) => MQTTMessage::text(MessageTopics::Report, format_args!("acc-cal,timeout"), now),
+                                    None => match calibrator.solve() {
+                                        Ok(fit) => {
+                                            log::info!("Accelerometer fit: {fit:?}");
+                                            if fit.is_good() {
+                                                fit.apply_to(&mut calibration);
+                                                storage::store_calibration(calibration);
+                                            }
+                                            MQTTMessage::text(MessageTopics::Report,
+                                                              format_args!("acc-cal,{},{:.1}",
+                                                                           if fit.is_good() { "applied" } else { "rejected" },
+                                                                           fit.residual * 1000.0),
+                                                              now)
+                                        }
+                                        Err(e) => {
+                                            log::warn!("Accelerometer fit failed: {e:?}");
+                                            MQTTMessage::text(MessageTopics::Report, format_args!("acc-cal,failed"), now)
+                                        }
+                                    },
+                                };
+                                event_sender.send(report).await;
+                                sender_led.send(SysStates::ConnectedBroker as u8).await;
+                                // Nothing was tracked through the flips: start
+                                // over from the still phase, as after a restart
+                                continue 'full;
+                            }
+                            if let Some((calibrator, end)) = mag_calibration.as_mut() {
+                                calibrator.add(mag);
+                                if now >= *end {
+                                    let report = match calibrator.fit() {
+                                        Ok(fit) => {
+                                            let good = fit.residual <= MAX_MAG_RESIDUAL && fit.coverage >= MIN_MAG_COVERAGE;
+                                            log::info!("Magnetometer fit: {fit:?}");
+                                            if good {
+                                                fit.apply_to(&mut calibration);
+                                                tracker.set_calibration(&calibration);
+                                                storage::store_calibration(calibration);
+                                            }
+                                            MQTTMessage::text(MessageTopics::Report,
+                                                              format_args!("mag-cal,{},{:.1},{:.1},{}",
+                                                                           if good { "applied" } else { "rejected" },
+                                                                           fit.residual * 100.0, fit.field_strength,
+                                                                           fit.coverage),
+                                                              now)
+                                        }
+                                        Err(e) => {
+                                            log::warn!("Magnetometer fit failed: {e:?}");
+                                            MQTTMessage::text(MessageTopics::Report, format_args!("mag-cal,failed"), now)
+                                        }
+                                    };
+                                    event_sender.send(report).await;
+                                    mag_calibration = None;
+                                }
+                            }
 
                             tracker.update(now, acc, gyr, mag);
-                            let new_direction = analysis.add_measurement(tracker.linear_accel);
+                            let new_event = analysis.add_measurement(now, tracker.linear_accel);
+                            let new_gesture = gestures.update(now, new_event.map(|e| e.direction));
+                            let new_template = templates.as_mut()
+                                .and_then(|t| t.add_measurement(tracker.linear_accel));
+                            tempo.add_measurement(tracker.linear_accel);
+                            let new_tempo = if should_send_tempo { tempo.estimate() } else { None };
                             flag_pin.set_low();
+                            if let Some(gesture) = new_gesture {
+                                let event = MQTTMessage::text(MessageTopics::Gesture,
+                                                              format_args!("{}", gesture.as_str()), now);
+                                event_sender.send(event).await;
+                            }
+                            if let Some(found) = new_template {
+                                let event = MQTTMessage::text(MessageTopics::Gesture,
+                                                              format_args!("t{}", found.id), now);
+                                event_sender.send(event).await;
+                            }
+                            if let Some(t) = new_tempo {
+                                let event = MQTTMessage::text(MessageTopics::Tempo,
+                                                              format_args!("{:.1},{:.2},{:.2}",
+                                                                           t.bpm, t.phase, t.confidence),
+                                                              now);
+                                event_sender.send(event).await;
+                            }
+                            if should_send_orientation {
+                                let orientation = tracker.orientation();
+                                let quantized = Orientation::quantize(orientation.quaternion, orientation.euler,
+                                                                      orientation.heading);
+                                let event = if FIRMWARE_CONFIG.binary_events {
+                                    let mut frame = [0; MAX_SIZE];
+                                    let len = orientation_encoder.encode(timesync::timestamp(now),
+                                                                         Payload::Orientation(quantized),
+                                                                         &mut frame).unwrap();
+                                    MQTTMessage {
+                                        topic: MessageTopics::Orientation,
+                                        payload: Vec::<u8, MAX_SIZE>::from_slice(&frame[..len]).unwrap(),
+                                    }
+                                } else {
+                                    let [w, x, y, z] = quantized.quaternion;
+                                    let [roll, pitch, yaw] = orientation.euler;
+                                    MQTTMessage::text(MessageTopics::Orientation,
+                                                      format_args!("{w},{x},{y},{z},{roll:.1},{pitch:.1},{yaw:.1},{:.1}",
+                                                                   orientation.heading),
+                                                      now)
+                                };
+                                // Rather dropped than stalling the sampling
+                                // when the broker lags: binary frames are
+                                // numbered, so the gaps show
+                                let _ = event_sender.try_send(event);
+                            }
                             if should_send_sample {
-                                if let Some(dir) = new_direction {
-                                    let value: u8 = 0x30 + dir.as_digit();
-                                    //let mark = (id % 100) as u8;
-                                    //let payload: SampleBuffer = [mark, value];
-                                    let event = MQTTMessage {
-                                        topic: MessageTopics::Event,
-                                        payload: Vec::<u8, MAX_SIZE>::from_slice(&[value]).unwrap(),
+                                if let Some(detection) = new_event {
+                                    // Detection events always carry the signed
+                                    // direction, whether text or binary
+                                    let digit = detection.direction.as_digit();
+                                    let event = if FIRMWARE_CONFIG.binary_events {
+                                        let payload = if FIRMWARE_CONFIG.detection_events {
+                                            Payload::Detection(Detection {
+                                                direction: digit,
+                                                magnitude: detection.magnitude,
+                                                confidence: detection.confidence,
+                                                onset: timesync::timestamp(detection.onset),
+                                                previous_duration: detection.previous_duration,
+                                            })
+                                        } else if FIRMWARE_CONFIG.legacy_directions {
+                                            Payload::Class(detection.direction.class().as_digit())
+                                        } else {
+                                            Payload::Direction(digit)
+                                        };
+                                        let mut frame = [0; MAX_SIZE];
+                                        let len = encoder.encode(timesync::timestamp(now), payload, &mut frame).unwrap();
+                                        MQTTMessage {
+                                            topic: MessageTopics::Event,
+                                            payload: Vec::<u8, MAX_SIZE>::from_slice(&frame[..len]).unwrap(),
+                                        }
+                                    } else if FIRMWARE_CONFIG.detection_events {
+                                        MQTTMessage::text(MessageTopics::Event,
+                                                          format_args!("{},{:.2},{:.1},{},{}",
+                                                                       digit, detection.magnitude, detection.confidence,
+                                                                       timesync::timestamp(detection.onset) / 1000,
+                                                                       (detection.previous_duration * 1000.0) as u32),
+                                                          now)
+                                    } else if FIRMWARE_CONFIG.legacy_directions {
+                                        // A letter, so that no class is taken
+                                        // for the signed direction of its digit
+                                        MQTTMessage::text(MessageTopics::Event,
+                                                          format_args!("{}", detection.direction.class().as_char()), now)
+                                    } else {
+                                        MQTTMessage::text(MessageTopics::Event, format_args!("{digit}"), now)
                                     };
                                     event_sender.send(event).await;
                                 }
@@ -200,9 +442,47 @@ async fn motion_analysis(
                     }
                 }
                 Either::Second(received_cmd) => {
-                    if let WaitResult::Message(SysCommands::Restart) = received_cmd {
-                        log::info!("Restarting!");
-                        continue 'full;
+                    match received_cmd {
+                        WaitResult::Message(SysCommands::Restart) => {
+                            log::info!("Restarting!");
+                            continue 'full;
+                        }
+                        WaitResult::Message(SysCommands::SetDenoiser(kind)) => {
+                            log::info!("Switching to the {} denoiser", kind.as_str());
+                            denoiser = kind;
+                            analysis.set_denoiser(kind.build(analysis_config.detection_window));
+                        }
+                        WaitResult::Message(SysCommands::CalibrateGyro) => {
+                            calibrate_gyro = true;
+                            continue 'full;
+                        }
+                        WaitResult::Message(SysCommands::ResetCalibration) => {
+                            log::info!("Resetting the IMU calibration");
+                            calibration = ImuCalibration::default();
+                            calibrate_gyro = true;
+                            continue 'full;
+                        }
+                        WaitResult::Message(SysCommands::CalibrateMag) => {
+                            log::info!("Calibrating the magnetometer, turn the device every which way...");
+                            mag_calibration = Some((MagCalibrator::new(), Instant::now() + MAG_CALIBRATION_TIME));
+                        }
+                        WaitResult::Message(SysCommands::CalibrateAcc) => {
+                            log::info!("Calibrating the accelerometer, lay the device still as the LED asks...");
+                            acc_calibration = Some((SixPositionCalibrator::new(), Instant::now() + ACC_CALIBRATION_TIMEOUT));
+                            sender_led.send(POSE_HUES[0]).await;
+                        }
+                        WaitResult::Message(SysCommands::SetOrientationRate(hz)) => {
+                            orientation_hz = hz.min(MAX_ORIENTATION_HZ);
+                            log::info!("Publishing the orientation {orientation_hz} times per second");
+                            mod_orientation = IMU_SAMPLE_FREQ.checked_div(orientation_hz).map(|m| m.max(1));
+                        }
+                        WaitResult::Message(SysCommands::UpdateCalibration(update)) => {
+                            log::info!("Updating the IMU calibration: {update:?}");
+                            update.apply(&mut calibration);
+                            tracker.set_calibration(&calibration);
+                            storage::store_calibration(calibration);
+                        }
+                        _ => {}
                     }
                 }
             }
@@ -291,6 +571,11 @@ async fn main(spawner: Spawner) -> ! {
         400.kHz(),
         &clocks,
     );
+    // The flash is only ever accessed from this core, see
+    // `storage::calibration_writer`
+    let stored_calibration = storage::load_calibration();
+    spawner.spawn(storage::calibration_writer()).ok();
+
     // Offload IMU reading and motion analysis to second core
     let msg_recv = channel_evts.subscriber().unwrap();
     let _guard = cpu_control
@@ -298,7 +583,8 @@ async fn main(spawner: Spawner) -> ! {
             static EXECUTOR: StaticCell<Executor> = StaticCell::new();
             let executor = EXECUTOR.init(Executor::new());
             executor.run(|spawner| {
-                spawner.spawn(motion_analysis(i2c0, sender_samples, msg_recv, flag)).unwrap();
+                spawner.spawn(motion_analysis(i2c0, sender_samples, msg_recv, flag, channel_led.sender(),
+                                              stored_calibration)).unwrap();
             });
         })
         .unwrap();
@@ -314,15 +600,18 @@ async fn main(spawner: Spawner) -> ! {
     let stack = &*make_static!(Stack::new(
         wifi_interface,
         Config::dhcpv4(dhcp_config),
-        make_static!(StackResources::<3>::new()),
+        make_static!(StackResources::<4>::new()),
         seed
     ));
 
     spawner.spawn(connection(controller, channel_led.sender())).ok();
     spawner.spawn(net_task(stack)).ok();
+    spawner.spawn(timesync::sntp_client(stack)).ok();
 
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
+    // Kept across reconnections, as the aggregator's probes are
+    let mut time_responder = motion::timesync::Responder::new();
 
     // Outer loop that maintains WiFi connectivity
     'conn: loop {
@@ -418,6 +707,13 @@ async fn main(spawner: Spawner) -> ! {
                 }
                 Timer::after(Duration::from_millis(500)).await;
             }
+            const TIMESYNC_TOPIC: &str = const_format::formatcp!("{}/timesync", FIRMWARE_CONFIG.mqtt_id);
+            const TIMESYNC_ECHO_TOPIC: &str = const_format::formatcp!("{}/timesync/echo", FIRMWARE_CONFIG.mqtt_id);
+            if FIRMWARE_CONFIG.mqtt_time_sync {
+                if let Err(result) = client.subscribe_to_topic(TIMESYNC_TOPIC).await {
+                    log::error!("Could not subscribe to time probes because {result}");
+                }
+            }
             log::info!("Subscribed!");
 
             // Main loop: sending motion samples via 'client'
@@ -450,6 +746,28 @@ async fn main(spawner: Spawner) -> ! {
                         }
                         println!("{:?}", String::from_utf8_lossy(&buf.payload));
                     }
+                    Either3::Second(Ok((topic, payload))) if topic == TIMESYNC_TOPIC => {
+                        let received = Instant::now().as_micros();
+                        let Some(probe) = str::from_utf8(payload).ok().and_then(motion::timesync::Probe::parse) else {
+                            log::warn!("Invalid time probe");
+                            continue;
+                        };
+                        let sent = Instant::now().as_micros();
+                        let (echo, sample) = time_responder.answer(&probe, received, sent);
+                        let mut text = heapless::String::<80>::new();
+                        core::fmt::write(&mut text, format_args!("{echo}")).unwrap();
+                        if let Err(result) = client
+                            .send_message(TIMESYNC_ECHO_TOPIC, text.as_bytes(), QualityOfService::QoS0, false)
+                            .await {
+                            if result != ReasonCode::Success {
+                                log::error!("Could not echo time probe because {result}");
+                            }
+                        }
+                        if let Some(sample) = sample {
+                            let drift = timesync::add_sample(sample);
+                            log::info!("Clock offset {} µs, delay {} µs, drift {drift:.1} ppm", sample.offset, sample.delay);
+                        }
+                    }
                     Either3::Second(msg) => {
                         if let Err(e) = process_mqtt_incoming(msg, &mut pusher_msgs).await {
                             log::error!("Problem receiving message: {:?}", e);
@@ -556,7 +874,22 @@ fn dispatch_incoming_mqtt_message(
             match payload {
                 "reset" => Some(SysCommands::Restart),
                 "off" => Some(SysCommands::PowerOff),
-                _ => None
+                "calibrate-gyro" => Some(SysCommands::CalibrateGyro),
+                "calibrate-mag" => Some(SysCommands::CalibrateMag),
+                "calibrate-acc" => Some(SysCommands::CalibrateAcc),
+                "calibration:reset" => Some(SysCommands::ResetCalibration),
+                "orientation:off" => Some(SysCommands::SetOrientationRate(0)),
+                "orientation:on" => Some(SysCommands::SetOrientationRate(
+                    match FIRMWARE_CONFIG.orientation_report_hz { 0 => DEFAULT_ORIENTATION_HZ, hz => hz })),
+                _ => if let Some(update) = payload.strip_prefix("calibration:") {
+                    CalibrationUpdate::parse(update).map(SysCommands::UpdateCalibration)
+                } else if let Some(hz) = payload.strip_prefix("orientation:") {
+                    hz.parse().ok().map(SysCommands::SetOrientationRate)
+                } else {
+                    payload.strip_prefix("denoiser:")
+                        .and_then(DenoiserKind::from_name)
+                        .map(SysCommands::SetDenoiser)
+                },
             }
         }
         _ => None
@@ -577,10 +910,7 @@ async fn power_handling(
         ).await;
         match futures {
             Either::First(_) => {
-                let event = MQTTMessage {
-                    topic: MessageTopics::Report,
-                    payload: Vec::<u8, MAX_SIZE>::from_slice("low-batt".as_bytes()).unwrap(),
-                };
+                let event = MQTTMessage::text(MessageTopics::Report, format_args!("low-batt"), Instant::now());
                 event_sender.send(event).await;
                 log::warn!("Low battery detected!");
             }
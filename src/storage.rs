@@ -0,0 +1,76 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorage;
+use motion::calibration::{CalibrationError, ImuCalibration, CALIBRATION_SIZE};
+
+/// Where the bootloader finds the partition table, whose entries are
+/// [`PARTITION_ENTRY_SIZE`] bytes long and start with [`PARTITION_MAGIC`].
+const PARTITION_TABLE: u32 = 0x8000;
+const PARTITION_ENTRY_SIZE: u32 = 32;
+const PARTITION_MAGIC: [u8; 2] = [0xaa, 0x50];
+const MAX_PARTITIONS: u32 = 95;
+
+/// Label of the partition the calibration is kept in, see `partitions.csv`.
+const CALIBRATION_PARTITION: &[u8] = b"calib";
+
+#[derive(Debug)]
+pub enum StorageError {
+    NoPartition,
+    Flash,
+    Calibration(CalibrationError),
+}
+
+/// Offset of the calibration partition in flash.
+fn partition(flash: &mut FlashStorage) -> Result<u32, StorageError> {
+    let mut entry = [0; PARTITION_ENTRY_SIZE as usize];
+    for i in 0..MAX_PARTITIONS {
+        flash.read(PARTITION_TABLE + i * PARTITION_ENTRY_SIZE, &mut entry).map_err(|_| StorageError::Flash)?;
+        if entry[..2] != PARTITION_MAGIC {
+            break;
+        }
+        let label = &entry[12..28];
+        let len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+        if &label[..len] == CALIBRATION_PARTITION {
+            return Ok(u32::from_le_bytes(entry[4..8].try_into().unwrap()));
+        }
+    }
+    Err(StorageError::NoPartition)
+}
+
+pub fn load_calibration() -> Result<ImuCalibration, StorageError> {
+    let mut flash = FlashStorage::new();
+    let offset = partition(&mut flash)?;
+    let mut bytes = [0; CALIBRATION_SIZE];
+    flash.read(offset, &mut bytes).map_err(|_| StorageError::Flash)?;
+    ImuCalibration::from_bytes(&bytes).map_err(StorageError::Calibration)
+}
+
+/// Writing erases the whole sector, so a reset halfway through leaves a
+/// record the checksum rejects rather than a wrong calibration.
+fn save_calibration(calibration: &ImuCalibration) -> Result<(), StorageError> {
+    let mut flash = FlashStorage::new();
+    let offset = partition(&mut flash)?;
+    flash.write(offset, &calibration.to_bytes()).map_err(|_| StorageError::Flash)
+}
+
+/// The calibration to store next, the latest one replacing any not yet
+/// written, so that the IMU loop never waits on the flash.
+static TO_STORE: Signal<CriticalSectionRawMutex, ImuCalibration> = Signal::new();
+
+/// Has `calibration_writer` store the calibration.
+pub fn store_calibration(calibration: ImuCalibration) {
+    TO_STORE.signal(calibration);
+}
+
+/// Stores the calibrations handed over by the app core. It runs on core 0,
+/// where the radio and the network stack run out of flash: esp-storage only
+/// keeps the core it is called from off the flash while writing.
+#[embassy_executor::task]
+pub async fn calibration_writer() {
+    loop {
+        let calibration = TO_STORE.wait().await;
+        if let Err(e) = save_calibration(&calibration) {
+            log::error!("Could not store the IMU calibration: {e:?}");
+        }
+    }
+}
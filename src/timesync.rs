@@ -0,0 +1,106 @@
+use core::cell::RefCell;
+
+use embassy_net::{
+    dns::DnsQueryType,
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
+use motion::{clock::{ClockSample, WallClock}, sntp};
+
+use crate::config::FIRMWARE_CONFIG;
+
+/// Wall clock shared by both cores, disciplined by the SNTP client or by the
+/// aggregator's probes over MQTT.
+pub static WALL_CLOCK: Mutex<CriticalSectionRawMutex, RefCell<WallClock>> = Mutex::new(RefCell::new(WallClock::new()));
+
+/// Polls sent in quick succession at startup, so that the drift is known
+/// before the first regular poll.
+const BURST_POLLS: u32 = 4;
+const BURST_PERIOD: Duration = Duration::from_secs(2);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Wall time at `instant`, in µs since the Unix epoch, once synchronized.
+pub fn wall_time(instant: Instant) -> Option<u64> {
+    WALL_CLOCK.lock(|clock| clock.borrow().wall_time(instant.as_micros()))
+}
+
+/// Disciplines the wall clock with `sample`, returning the drift in ppm.
+pub fn add_sample(sample: ClockSample) -> f32 {
+    WALL_CLOCK.lock(|clock| {
+        let mut clock = clock.borrow_mut();
+        clock.add_sample(sample);
+        clock.drift_ppm()
+    })
+}
+
+/// Timestamp of published data: µs since the Unix epoch once the clock is
+/// synchronized, since boot until then.
+pub fn timestamp(instant: Instant) -> u64 {
+    wall_time(instant).unwrap_or(instant.as_micros())
+}
+
+#[embassy_executor::task]
+pub async fn sntp_client(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
+    if FIRMWARE_CONFIG.ntp_host.is_empty() || FIRMWARE_CONFIG.mqtt_time_sync {
+        return;
+    }
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * sntp::PACKET_SIZE];
+    let mut tx_buffer = [0; 2 * sntp::PACKET_SIZE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    socket.bind(0).unwrap();
+
+    let mut polls: u32 = 0;
+    loop {
+        stack.wait_config_up().await;
+        match poll(stack, &socket).await {
+            Ok(sample) => {
+                let drift = add_sample(sample);
+                log::info!("SNTP offset {} µs, delay {} µs, drift {drift:.1} ppm", sample.offset, sample.delay);
+            }
+            Err(e) => log::warn!("SNTP poll failed: {e}"),
+        }
+        polls += 1;
+        let period = if polls < BURST_POLLS {
+            BURST_PERIOD
+        } else {
+            Duration::from_secs(FIRMWARE_CONFIG.ntp_poll_secs as u64)
+        };
+        Timer::after(period).await;
+    }
+}
+
+async fn poll(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    socket: &UdpSocket<'_>,
+) -> Result<ClockSample, &'static str> {
+    let host = *stack.dns_query(FIRMWARE_CONFIG.ntp_host, DnsQueryType::A).await
+        .map_err(|_| "DNS query failed")?
+        .first()
+        .ok_or("no address for the server")?;
+
+    let t1 = Instant::now().as_micros();
+    socket.send_to(&sntp::request(t1), (host, sntp::PORT)).await.map_err(|_| "could not send")?;
+    let mut packet = [0; sntp::PACKET_SIZE];
+    let response = with_timeout(REPLY_TIMEOUT, async {
+        // Late replies to earlier polls may still be queued
+        loop {
+            let Ok((len, _)) = socket.recv_from(&mut packet).await else {
+                break Err("could not receive");
+            };
+            match sntp::parse_response(&packet[..len], t1) {
+                Err(sntp::Error::Mismatch) => continue,
+                Err(sntp::Error::Unsynchronized) => break Err("server unsynchronized"),
+                Err(_) => break Err("invalid reply"),
+                Ok(response) => break Ok(response),
+            }
+        }
+    }).await.map_err(|_| "no reply")??;
+    let t4 = Instant::now().as_micros();
+
+    ClockSample::from_exchange(t1, response.receive, response.transmit, t4).ok_or("inconsistent timestamps")
+}
@@ -0,0 +1,53 @@
+//! Runs a recorded IMU trace through every orientation estimator the
+//! firmware can use, and prints how each drifts while the wrist rests and how
+//! far apart they are.
+//!
+//! Usage: estimators [OPTIONS] <trace.csv|trace.bin>
+//!
+//!   --rate <HZ>     IMU sampling rate (default 200)
+//!   --gain <GAIN>   steady state gain of the estimators (default 0.5)
+
+use std::process::ExitCode;
+
+use motion_tools::{estimators::compare, pipeline::PipelineSettings, trace};
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut settings = PipelineSettings::default();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--rate" => settings.rate = value()?.parse().map_err(|_| "invalid rate")?,
+            "--gain" => settings.ahrs.gain = value()?.parse().map_err(|_| "invalid gain")?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.ok_or("no trace file given")?;
+    let samples = trace::read_file(&path).map_err(|e| format!("{path}: {e}"))?;
+
+    let comparison = compare(&settings, &samples);
+    println!("{:.1} s at rest", comparison.rest_secs);
+    println!("{:<10} {:>10} {:>16}", "estimator", "tilt (°)", "drift (°/min)");
+    for report in &comparison.reports {
+        println!("{:<10} {:>10.2} {:>16.2}", report.kind.as_str(), report.tilt_error_deg, report.heading_drift_deg_per_min);
+    }
+    println!();
+    println!("{:<16} {:>10}", "agreement", "RMS (°)");
+    for agreement in &comparison.agreements {
+        let (a, b) = agreement.kinds;
+        println!("{:<16} {:>10.2}", format!("{}/{}", a.as_str(), b.as_str()), agreement.rms_deg);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("estimators: {e}");
+            eprintln!("usage: estimators [--rate HZ] [--gain GAIN] <trace>");
+            ExitCode::FAILURE
+        }
+    }
+}
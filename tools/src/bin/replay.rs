@@ -0,0 +1,133 @@
+//! Runs a recorded IMU trace through `ImuTracker` and `Analysis` exactly as
+//! the `motion_analysis` task in the firmware does, printing one CSV line per
+//! sample with the detected direction, the attitude quaternion and the linear
+//! acceleration.
+//!
+//! Usage: replay [OPTIONS] <trace.csv|trace.bin>
+//!
+//!   --rate <HZ>               IMU sampling rate (default 200)
+//!   --smoothing <N>           smoothing window, in samples (default 60)
+//!   --highpass <HZ>           smooth with a Butterworth high-pass instead
+//!   --detection <N>           detection window, in samples (default 30)
+//!   --denoiser <NAME>         average, quantile, median, ema or trimmed
+//!                             (default quantile)
+//!   --threshold <G>           magnitude over which movements start
+//!                             (default 0.35)
+//!   --exit-threshold <G>      magnitude under which movements end
+//!                             (default 0.32)
+//!   --diagonal <DEG>,<DEG>    elevations between which movements are
+//!                             diagonal (default 34,56)
+//!   --angle-hysteresis <DEG>  how much narrower the diagonal band is to enter
+//!                             than to leave (default 4)
+//!   --min-dwell <N>           samples a new direction must last (default 4)
+//!   --heading <DEG>           heading counted as forward, from north (default 0)
+//!   --estimator <NAME>        fusion, mahony or kalman (default fusion)
+//!   --legacy                  print the horizontal/vertical/diagonal class only
+
+use std::{io::{self, Write}, process::ExitCode};
+
+use motion::{DenoiserKind, EstimatorKind};
+use motion_tools::{pipeline::{Pipeline, PipelineSettings}, trace};
+
+struct Options {
+    settings: PipelineSettings,
+    legacy: bool,
+    path: String,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    fn value<T: std::str::FromStr>(flag: &str, arg: Option<String>) -> Result<T, String> {
+        arg.ok_or_else(|| format!("missing value for {flag}"))?
+            .parse()
+            .map_err(|_| format!("invalid value for {flag}"))
+    }
+
+    let mut options = Options { settings: PipelineSettings::default(), legacy: false, path: String::new() };
+    let settings = &mut options.settings;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rate" => settings.rate = value(&arg, args.next())?,
+            "--smoothing" => settings.analysis.smoothing_window = value(&arg, args.next())?,
+            "--highpass" => settings.highpass_hz = Some(value(&arg, args.next())?),
+            "--detection" => settings.analysis.detection_window = value(&arg, args.next())?,
+            "--denoiser" => {
+                let name: String = value(&arg, args.next())?;
+                settings.analysis.denoiser = DenoiserKind::from_name(&name).ok_or(format!("unknown denoiser {name}"))?;
+            }
+            "--threshold" => settings.analysis.start_threshold_g = value(&arg, args.next())?,
+            "--exit-threshold" => settings.analysis.stop_threshold_g = value(&arg, args.next())?,
+            "--diagonal" => {
+                let bounds: String = value(&arg, args.next())?;
+                let (low, high) = bounds.split_once(',').ok_or("--diagonal takes two elevations, as LOW,HIGH")?;
+                settings.analysis.diagonal_low_deg = value(&arg, Some(low.to_string()))?;
+                settings.analysis.diagonal_high_deg = value(&arg, Some(high.to_string()))?;
+            }
+            "--angle-hysteresis" => settings.analysis.angle_hysteresis_deg = value(&arg, args.next())?,
+            "--min-dwell" => settings.analysis.min_dwell = value(&arg, args.next())?,
+            "--heading" => settings.reference_heading_deg = value(&arg, args.next())?,
+            "--estimator" => {
+                let name: String = value(&arg, args.next())?;
+                settings.ahrs.estimator = EstimatorKind::from_name(&name).ok_or(format!("unknown estimator {name}"))?;
+            }
+            "--legacy" => options.legacy = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => options.path = arg,
+        }
+    }
+    if options.path.is_empty() {
+        return Err("no trace file given".into());
+    }
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("replay: {e}");
+            eprintln!("usage: replay [--rate HZ] [--smoothing N | --highpass HZ] [--detection N] [--denoiser NAME] [--threshold G] [--exit-threshold G] [--diagonal DEG,DEG] [--angle-hysteresis DEG] [--min-dwell N] [--heading DEG] [--estimator NAME] [--legacy] <trace>");
+            return ExitCode::FAILURE;
+        }
+    };
+    let samples = match trace::read_file(&options.path) {
+        Ok(samples) => samples,
+        Err(e) => {
+            eprintln!("replay: {}: {e}", options.path);
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(first) = samples.first() else {
+        return ExitCode::SUCCESS;
+    };
+
+    let mut pipeline = Pipeline::new(&options.settings, first.t);
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let result = (|| -> io::Result<()> {
+        writeln!(out, "t,direction,qw,qx,qy,qz,lin_x,lin_y,lin_z")?;
+        for s in &samples {
+            let direction = pipeline.step(s).map(|e| e.direction);
+
+            let q = pipeline.tracker.quaternion;
+            let l = pipeline.tracker.linear_accel;
+            let dir = match direction {
+                Some(d) if options.legacy => d.class().as_char(),
+                Some(d) => d.as_char(),
+                None => '-',
+            };
+            writeln!(out, "{:.6},{dir},{:.5},{:.5},{:.5},{:.5},{:.5},{:.5},{:.5}",
+                     s.t, q.w, q.x, q.y, q.z, l.x, l.y, l.z)?;
+        }
+        out.flush()
+    })();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // Downstream closed the pipe early (e.g. `| head`)
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("replay: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Scores how well a group of wristbands move together, from what they
+//! publish on the broker, and publishes the scores back.
+//!
+//! Usage: sync [OPTIONS]
+//!
+//!   --host <HOST>           MQTT broker (default localhost)
+//!   --port <PORT>           MQTT port (default 1883)
+//!   --prefix <TOPIC>        topic the scores are published under (default sync)
+//!   --period <SECONDS>      time between score updates (default 1)
+//!   --window <SECONDS>      history the scores are computed over (default 10)
+//!   --probe <SECONDS>       time between the clock probes sent to each device
+//!                           (default none)
+//!   --record <FILE>         also write every received message to FILE
+//!   --replay <FILE>         read messages from a recording instead of the
+//!                           broker, printing the scores to stdout
+
+use std::{fs, io, process::ExitCode};
+
+use motion_tools::{
+    bus::{self, Bus, MqttBus, RecordedBus, Recorder},
+    sync::{self, SyncSettings},
+};
+
+struct Options {
+    host: String,
+    port: u16,
+    prefix: String,
+    period: f64,
+    settings: SyncSettings,
+    record: Option<String>,
+    replay: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    fn value<T: std::str::FromStr>(flag: &str, arg: Option<String>) -> Result<T, String> {
+        arg.ok_or_else(|| format!("missing value for {flag}"))?
+            .parse()
+            .map_err(|_| format!("invalid value for {flag}"))
+    }
+
+    let mut options = Options {
+        host: "localhost".into(),
+        port: 1883,
+        prefix: "sync".into(),
+        period: 1.0,
+        settings: SyncSettings::default(),
+        record: None,
+        replay: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => options.host = value(&arg, args.next())?,
+            "--port" => options.port = value(&arg, args.next())?,
+            "--prefix" => options.prefix = value(&arg, args.next())?,
+            "--period" => options.period = value(&arg, args.next())?,
+            "--window" => options.settings.window = value(&arg, args.next())?,
+            "--probe" => options.settings.probe_period = Some(value(&arg, args.next())?),
+            "--record" => options.record = Some(value(&arg, args.next())?),
+            "--replay" => options.replay = Some(value(&arg, args.next())?),
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    if options.period <= 0.0 || options.settings.probe_period.is_some_and(|p| p <= 0.0) {
+        return Err("periods must be positive".into());
+    }
+    Ok(options)
+}
+
+fn run(options: Options) -> io::Result<()> {
+    if let Some(path) = &options.replay {
+        let mut bus = RecordedBus::new(bus::read_recorded_file(path)?);
+        sync::run(&mut bus, options.settings, &options.prefix, options.period)?;
+        for (t, message) in &bus.published {
+            println!("{t:.3},{},{}", message.topic, String::from_utf8_lossy(&message.payload));
+        }
+        return Ok(());
+    }
+
+    let client_id = format!("{}-{}", options.prefix, std::process::id());
+    let mqtt = MqttBus::connect(&options.host, options.port, &client_id, &["+/event", "+/tempo", "+/timesync/echo"]);
+    match &options.record {
+        Some(path) => {
+            let mut bus = Recorder::new(mqtt, io::BufWriter::new(fs::File::create(path)?));
+            serve(&mut bus, options)
+        }
+        None => serve(&mut { mqtt }, options),
+    }
+}
+
+fn serve(bus: &mut impl Bus, options: Options) -> io::Result<()> {
+    sync::run(bus, options.settings, &options.prefix, options.period)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("sync: {e}");
+            eprintln!("usage: sync [--host HOST] [--port PORT] [--prefix TOPIC] [--period SECONDS] [--window SECONDS] [--probe SECONDS] [--record FILE | --replay FILE]");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("sync: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Records gesture templates for the wristband out of example traces.
+//!
+//! Usage: template [--decimation N] -o <templates.bin> --id <ID> <example>... [--id <ID> <example>...]
+//!
+//! Each `--id` starts a new template, built from the traces that follow it
+//! (one performance of the gesture per trace). The output file is meant to be
+//! placed at the root of the firmware crate as `gesture_templates.bin`.
+
+use std::{fs, process::ExitCode};
+
+use motion::template::{encode_templates, MAX_TEMPLATES};
+use motion_tools::{pipeline::PipelineSettings, recording, trace};
+
+const DEFAULT_DECIMATION: u8 = 4;
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut decimation = DEFAULT_DECIMATION;
+    let mut output = None;
+    let mut groups: Vec<(u8, Vec<String>)> = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--decimation" => decimation = value()?.parse().map_err(|_| "invalid decimation")?,
+            "-o" => output = Some(value()?),
+            "--id" => groups.push((value()?.parse().map_err(|_| "invalid id")?, Vec::new())),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => groups.last_mut().ok_or("examples must follow an --id")?.1.push(arg),
+        }
+    }
+    let output = output.ok_or("no output file given")?;
+    if decimation == 0 {
+        return Err("decimation must be positive".into());
+    }
+
+    if groups.len() > MAX_TEMPLATES {
+        return Err(format!("{} templates given, at most {MAX_TEMPLATES} fit", groups.len()));
+    }
+
+    let settings = PipelineSettings::default();
+    let mut templates = Vec::new();
+    for (id, paths) in groups {
+        let mut examples = Vec::new();
+        for path in &paths {
+            let samples = trace::read_file(path).map_err(|e| format!("{path}: {e}"))?;
+            examples.push(recording::example_frames(&settings, decimation, &samples));
+        }
+        let template = recording::record_template(id, &examples).map_err(|e| format!("template {id}: {e}"))?;
+        eprintln!("template {id}: {} frames, threshold {:.3} g", template.frames.len(), template.threshold);
+        templates.push(template);
+    }
+
+    fs::write(&output, encode_templates(decimation, &templates)).map_err(|e| format!("{output}: {e}"))
+}
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("template: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, QoS, RecvTimeoutError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(topic: impl Into<String>, payload: impl Into<Vec<u8>>) -> Self {
+        Self { topic: topic.into(), payload: payload.into() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Received {
+    Message(Message),
+    /// Nothing arrived before the timeout.
+    Idle,
+    /// No more messages will arrive.
+    Closed,
+}
+
+/// Publish/subscribe transport of the host services: an MQTT broker, or a
+/// recorded stream standing in for it.
+pub trait Bus {
+    /// Seconds since the bus was opened.
+    fn now(&self) -> f64;
+    /// Waits up to `timeout` seconds for the next message.
+    fn receive(&mut self, timeout: f64) -> io::Result<Received>;
+    fn publish(&mut self, message: Message) -> io::Result<()>;
+}
+
+/// Connection to an MQTT broker, subscribed to a fixed set of topic filters.
+pub struct MqttBus {
+    client: Client,
+    connection: Connection,
+    filters: Vec<String>,
+    start: Instant,
+}
+
+impl MqttBus {
+    pub fn connect(host: &str, port: u16, client_id: &str, filters: &[&str]) -> Self {
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(5));
+        let (client, connection) = Client::new(options, 64);
+        Self {
+            client,
+            connection,
+            filters: filters.iter().map(|f| f.to_string()).collect(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Bus for MqttBus {
+    fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn receive(&mut self, timeout: f64) -> io::Result<Received> {
+        let deadline = Instant::now() + Duration::from_secs_f64(timeout);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.connection.recv_timeout(remaining) {
+                Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                    return Ok(Received::Message(Message::new(publish.topic, publish.payload.to_vec())));
+                }
+                // (Re)connected: the broker does not keep our subscriptions
+                Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => {
+                    for filter in &self.filters {
+                        self.client.try_subscribe(filter, QoS::AtMostOnce).map_err(io::Error::other)?;
+                    }
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => {
+                    // The next poll reconnects
+                    eprintln!("MQTT connection error: {e}");
+                    std::thread::sleep(remaining.min(Duration::from_secs(1)));
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(Received::Idle),
+                Err(RecvTimeoutError::Disconnected) => return Ok(Received::Closed),
+            }
+            if Instant::now() >= deadline {
+                return Ok(Received::Idle);
+            }
+        }
+    }
+
+    fn publish(&mut self, message: Message) -> io::Result<()> {
+        self.client.try_publish(message.topic, QoS::AtMostOnce, false, message.payload).map_err(io::Error::other)
+    }
+}
+
+/// Stands in for a broker by replaying recorded messages at their original
+/// times, on a virtual clock, and keeping whatever is published.
+pub struct RecordedBus {
+    incoming: VecDeque<(f64, Message)>,
+    now: f64,
+    pub published: Vec<(f64, Message)>,
+}
+
+impl RecordedBus {
+    /// Messages are sorted by time.
+    pub fn new(mut messages: Vec<(f64, Message)>) -> Self {
+        messages.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { incoming: messages.into(), now: 0.0, published: Vec::new() }
+    }
+}
+
+impl Bus for RecordedBus {
+    fn now(&self) -> f64 {
+        self.now
+    }
+
+    fn receive(&mut self, timeout: f64) -> io::Result<Received> {
+        match self.incoming.front() {
+            None => Ok(Received::Closed),
+            Some((t, _)) if *t > self.now + timeout => {
+                self.now += timeout;
+                Ok(Received::Idle)
+            }
+            Some(_) => {
+                let (t, message) = self.incoming.pop_front().unwrap();
+                self.now = self.now.max(t);
+                Ok(Received::Message(message))
+            }
+        }
+    }
+
+    fn publish(&mut self, message: Message) -> io::Result<()> {
+        self.published.push((self.now, message));
+        Ok(())
+    }
+}
+
+/// Passes everything through, writing the received messages to a recording.
+pub struct Recorder<B, W> {
+    bus: B,
+    out: W,
+}
+
+impl<B: Bus, W: Write> Recorder<B, W> {
+    pub fn new(bus: B, out: W) -> Self {
+        Self { bus, out }
+    }
+}
+
+impl<B: Bus, W: Write> Bus for Recorder<B, W> {
+    fn now(&self) -> f64 {
+        self.bus.now()
+    }
+
+    fn receive(&mut self, timeout: f64) -> io::Result<Received> {
+        let received = self.bus.receive(timeout)?;
+        if let Received::Message(message) = &received {
+            write_recorded(&mut self.out, self.bus.now(), message)?;
+        }
+        Ok(received)
+    }
+
+    fn publish(&mut self, message: Message) -> io::Result<()> {
+        self.bus.publish(message)
+    }
+}
+
+/// Prefix of payloads recorded in hexadecimal, as binary event frames are.
+const HEX_PREFIX: &str = "hex:";
+
+/// Writes one line of a recording: `t,topic,payload`, the payload as text
+/// when it is printable and in hexadecimal after `hex:` otherwise.
+pub fn write_recorded(out: &mut impl Write, t: f64, message: &Message) -> io::Result<()> {
+    match std::str::from_utf8(&message.payload) {
+        Ok(text) if !text.starts_with(HEX_PREFIX) && !text.chars().any(char::is_control) => {
+            writeln!(out, "{t:.3},{},{text}", message.topic)?;
+        }
+        _ => {
+            let hex: String = message.payload.iter().map(|b| format!("{b:02x}")).collect();
+            writeln!(out, "{t:.3},{},{HEX_PREFIX}{hex}", message.topic)?;
+        }
+    }
+    out.flush()
+}
+
+pub fn read_recorded(reader: impl BufRead) -> io::Result<Vec<(f64, Message)>> {
+    let invalid = |line: usize| io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: malformed record"));
+    let mut messages = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // The payload may hold commas of its own
+        let mut fields = line.splitn(3, ',');
+        let (Some(t), Some(topic), Some(payload)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(invalid(i + 1));
+        };
+        let t = t.parse().map_err(|_| invalid(i + 1))?;
+        let payload = match payload.strip_prefix(HEX_PREFIX) {
+            Some(hex) if hex.len().is_multiple_of(2) => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| invalid(i + 1))?,
+            Some(_) => return Err(invalid(i + 1)),
+            None => payload.as_bytes().to_vec(),
+        };
+        messages.push((t, Message::new(topic, payload)));
+    }
+    Ok(messages)
+}
+
+pub fn read_recorded_file(path: impl AsRef<Path>) -> io::Result<Vec<(f64, Message)>> {
+    read_recorded(BufReader::new(fs::File::open(path)?))
+}
@@ -0,0 +1,158 @@
+//! Runs every orientation estimator over the same trace, to tell which suits
+//! wrist motion best without knowing the true orientation.
+//!
+//! While the wrist rests, gravity is all the accelerometer reads, so how far
+//! the estimated vertical is from it is the tilt error, and any change of
+//! heading is drift. Over the whole trace, how far apart the estimators are
+//! from each other tells where at least one of them is wrong.
+
+use imu_fusion::{FusionQuaternion, FusionVector};
+use motion::{
+    estimator::angle_between,
+    imu_tracker::{align_magnetometer, rotate},
+    AhrsConfig, EstimatorKind, ImuTracker,
+};
+
+use crate::{pipeline::PipelineSettings, trace::Sample};
+
+/// Largest rotation rate, in degrees/s, of a resting wrist.
+const MAX_REST_RATE: f32 = 3.0;
+
+/// Largest difference between the norm of the acceleration of a resting
+/// wrist and 1 g.
+const MAX_REST_ACCEL: f32 = 0.03;
+
+/// Shortest rest counted, in seconds, so that the turning points of a
+/// movement are not taken for one.
+const MIN_REST: f64 = 0.5;
+
+/// How one estimator fared over a trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EstimatorReport {
+    pub kind: EstimatorKind,
+    /// Angle between the estimated vertical and the mean accelerometer
+    /// reading of each rest, in degrees, averaged over the time at rest.
+    pub tilt_error_deg: f32,
+    /// Mean change of heading while resting, in degrees per minute, whatever
+    /// its sense.
+    pub heading_drift_deg_per_min: f32,
+}
+
+/// Root mean square of the angle between the orientations two estimators
+/// gave, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Agreement {
+    pub kinds: (EstimatorKind, EstimatorKind),
+    pub rms_deg: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub reports: Vec<EstimatorReport>,
+    pub agreements: Vec<Agreement>,
+    /// Time the wrist rested for, over which the reports are taken, in
+    /// seconds.
+    pub rest_secs: f64,
+}
+
+/// What a tracker made of one sample.
+struct Estimate {
+    quaternion: FusionQuaternion,
+    /// The accelerometer reading in the earth axes.
+    gravity: FusionVector,
+    heading: f32,
+}
+
+/// Compares the estimators of [`EstimatorKind::ALL`] over `samples`, each set
+/// up with the `settings`, the alignment period of the AHRS being left out.
+pub fn compare(settings: &PipelineSettings, samples: &[Sample]) -> Comparison {
+    let runs: Vec<(EstimatorKind, Vec<Estimate>)> = EstimatorKind::ALL.into_iter()
+        .map(|kind| (kind, run(&AhrsConfig { estimator: kind, ..settings.ahrs }, settings, samples)))
+        .collect();
+    let settled = samples.first().map_or(0.0, |first| first.t + settings.ahrs.alignment_period as f64);
+    let first_settled = samples.partition_point(|s| s.t < settled);
+    let rests: Vec<_> = rests(settings, samples).into_iter()
+        .map(|(start, end)| (start.max(first_settled), end))
+        .filter(|&(start, end)| start < end && samples[end].t - samples[start].t >= MIN_REST)
+        .collect();
+    let rest_secs = rests.iter().map(|&(start, end)| samples[end].t - samples[start].t).sum::<f64>();
+
+    let reports = runs.iter()
+        .map(|(kind, estimates)| {
+            let mut tilt = 0.0;
+            let mut turned = 0.0;
+            for &(start, end) in &rests {
+                // The reading averaged, so that its noise does not count
+                let gravity = estimates[start..=end].iter().fold(FusionVector::zero(), |sum, e| sum + e.gravity);
+                let norm = gravity.dot_product(&gravity).sqrt();
+                // Up or down, as the convention has it
+                let cosine = if norm > 0.0 { (gravity.z.abs() / norm).min(1.0) } else { 1.0 };
+                tilt += cosine.acos().to_degrees() as f64 * (samples[end].t - samples[start].t);
+                let change = estimates[end].heading - estimates[start].heading;
+                turned += ((change + 540.0) % 360.0 - 180.0).abs();
+            }
+            EstimatorReport {
+                kind: *kind,
+                tilt_error_deg: if rest_secs > 0.0 { (tilt / rest_secs) as f32 } else { 0.0 },
+                heading_drift_deg_per_min: if rest_secs > 0.0 { (60.0 * turned as f64 / rest_secs) as f32 } else { 0.0 },
+            }
+        })
+        .collect();
+
+    let mut agreements = Vec::new();
+    for (i, (a, first)) in runs.iter().enumerate() {
+        for (b, second) in &runs[i + 1..] {
+            let squared: Vec<f32> = samples.iter().zip(first.iter().zip(second))
+                .filter(|(s, _)| s.t >= settled)
+                .map(|(_, (x, y))| angle_between(x.quaternion, y.quaternion).powi(2))
+                .collect();
+            let rms_deg = if squared.is_empty() { 0.0 } else { (squared.iter().sum::<f32>() / squared.len() as f32).sqrt() };
+            agreements.push(Agreement { kinds: (*a, *b), rms_deg });
+        }
+    }
+
+    Comparison { reports, agreements, rest_secs }
+}
+
+fn run(ahrs: &AhrsConfig, settings: &PipelineSettings, samples: &[Sample]) -> Vec<Estimate> {
+    let Some(first) = samples.first() else {
+        return Vec::new();
+    };
+    let mut tracker = ImuTracker::new(settings.rate, first.t, 1000.0f32, ahrs, &settings.calibration);
+    samples.iter()
+        .map(|s| {
+            tracker.update(s.t, s.acc(), s.gyr(), align_magnetometer(s.mag()));
+            Estimate {
+                quaternion: tracker.quaternion,
+                gravity: rotate(tracker.calibrated_accel(s.acc()), tracker.quaternion),
+                heading: tracker.orientation().heading,
+            }
+        })
+        .collect()
+}
+
+/// First and last indices of the samples of each rest.
+fn rests(settings: &PipelineSettings, samples: &[Sample]) -> Vec<(usize, usize)> {
+    let calibration = &settings.calibration;
+    let resting = |s: &Sample| {
+        let (acc, gyr) = (calibration.correct_acc(s.acc()), calibration.correct_gyr(s.gyr()));
+        gyr.dot_product(&gyr).sqrt() < MAX_REST_RATE && (acc.dot_product(&acc).sqrt() - 1.0).abs() < MAX_REST_ACCEL
+    };
+
+    let mut rests = Vec::new();
+    let mut start = None;
+    for (i, sample) in samples.iter().enumerate() {
+        match (resting(sample), start) {
+            (true, None) => start = Some(i),
+            (false, Some(first)) => {
+                rests.push((first, i - 1));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        rests.push((first, samples.len() - 1));
+    }
+    rests
+}
@@ -0,0 +1,11 @@
+//! Host-side tooling around the [motion] crate: trace files, replay and
+//! friends. Everything in here runs on the development machine, not on the
+//! wristband.
+
+pub mod bus;
+pub mod estimators;
+pub mod pipeline;
+pub mod recording;
+pub mod sync;
+pub mod synth;
+pub mod trace;
@@ -0,0 +1,73 @@
+use core::f32::consts::PI;
+
+use motion::{
+    filter::{FilterBank, FilterKind},
+    imu_tracker::align_magnetometer,
+    AhrsConfig, Analysis, AnalysisConfig, DetectionEvent, ImuCalibration, ImuTracker, MovementDirection,
+};
+
+use crate::trace::Sample;
+
+const HIGHPASS_ORDER: usize = 2;
+
+/// Tunables of the motion pipeline. The defaults mirror the settings of
+/// `motion_analysis` in the firmware.
+#[derive(Debug, Clone)]
+pub struct PipelineSettings {
+    pub rate: u32,
+    pub ahrs: AhrsConfig,
+    pub analysis: AnalysisConfig,
+    /// Cutoff of a Butterworth high-pass used instead of the moving average
+    /// smoothing, in Hz.
+    pub highpass_hz: Option<f32>,
+    /// Heading counted as forward, degrees counter-clockwise from north.
+    pub reference_heading_deg: f32,
+    /// Calibration of the IMU the trace was recorded with.
+    pub calibration: ImuCalibration,
+}
+
+impl Default for PipelineSettings {
+    fn default() -> Self {
+        Self {
+            rate: 200,
+            ahrs: AhrsConfig::default(),
+            analysis: AnalysisConfig::default(),
+            highpass_hz: None,
+            reference_heading_deg: 0.0,
+            calibration: ImuCalibration::default(),
+        }
+    }
+}
+
+/// `ImuTracker` followed by `Analysis`, wired as in the firmware.
+pub struct Pipeline {
+    pub tracker: ImuTracker<f64>,
+    pub analysis: Analysis<f64>,
+}
+
+impl Pipeline {
+    pub fn new(settings: &PipelineSettings, t0: f64) -> Self {
+        let tracker = ImuTracker::new(settings.rate, t0, 1000.0f32, &settings.ahrs, &settings.calibration);
+        let mut analysis = Analysis::new(&settings.analysis, t0);
+        if let Some(cutoff) = settings.highpass_hz {
+            let filter = FilterBank::butterworth(FilterKind::HighPass, HIGHPASS_ORDER, cutoff, settings.rate as f32);
+            analysis.set_smoothing(Box::new(filter));
+        }
+        analysis.set_reference_heading(settings.reference_heading_deg*PI/180.0);
+        Self { tracker, analysis }
+    }
+
+    pub fn step(&mut self, sample: &Sample) -> Option<DetectionEvent<f64>> {
+        self.tracker.update(sample.t, sample.acc(), sample.gyr(), align_magnetometer(sample.mag()));
+        self.analysis.add_measurement(sample.t, self.tracker.linear_accel)
+    }
+
+    /// Runs a whole trace, returning the direction emitted for each sample.
+    pub fn run(settings: &PipelineSettings, samples: &[Sample]) -> Vec<Option<MovementDirection>> {
+        let Some(first) = samples.first() else {
+            return Vec::new();
+        };
+        let mut pipeline = Self::new(settings, first.t);
+        samples.iter().map(|s| pipeline.step(s).map(|e| e.direction)).collect()
+    }
+}
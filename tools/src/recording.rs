@@ -0,0 +1,63 @@
+use imu_fusion::FusionVector;
+use motion::template::{dtw_distance, Decimator, Template, MAX_TEMPLATE_FRAMES};
+
+use crate::{
+    pipeline::{Pipeline, PipelineSettings},
+    trace::Sample,
+};
+
+/// Frames quieter than this (in g) are trimmed from both ends of an example.
+pub const ACTIVITY_THRESHOLD: f32 = 0.1;
+
+/// Threshold used when a single example does not tell how much variation to
+/// allow, as mean distance per frame in g.
+pub const DEFAULT_THRESHOLD: f32 = 0.15;
+
+/// Margin over the largest distance between the chosen example and the others.
+const THRESHOLD_MARGIN: f32 = 1.5;
+
+/// Linear acceleration frames of one recorded example, as the firmware's
+/// tracker sees them, trimmed to where the wrist actually moves.
+pub fn example_frames(settings: &PipelineSettings, decimation: u8, samples: &[Sample]) -> Vec<FusionVector> {
+    let Some(first) = samples.first() else {
+        return Vec::new();
+    };
+    let mut pipeline = Pipeline::new(settings, first.t);
+    let mut decimator = Decimator::new(decimation);
+    let frames: Vec<_> = samples.iter()
+        .filter_map(|s| {
+            pipeline.step(s);
+            decimator.add_measurement(pipeline.tracker.linear_accel)
+        })
+        .collect();
+
+    let active = |f: &FusionVector| f.magnitude() > ACTIVITY_THRESHOLD;
+    let start = frames.iter().position(active).unwrap_or(0);
+    let end = frames.iter().rposition(active).map_or(start, |i| i + 1);
+    frames[start..end].to_vec()
+}
+
+/// Builds a template out of a few examples of the same gesture: the example
+/// closest to all others, with a threshold covering how far apart they are.
+pub fn record_template(id: u8, examples: &[Vec<FusionVector>]) -> Result<Template, String> {
+    let examples: Vec<_> = examples.iter().filter(|e| !e.is_empty()).collect();
+    if examples.is_empty() {
+        return Err("no movement in any example".into());
+    }
+
+    let (medoid, spread) = examples.iter()
+        .map(|candidate| {
+            let distances = examples.iter().map(|other| dtw_distance(candidate, other));
+            let spread = distances.fold(0.0f32, f32::max);
+            (*candidate, spread)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+
+    if medoid.len() > MAX_TEMPLATE_FRAMES {
+        return Err(format!("example is {} frames long, at most {MAX_TEMPLATE_FRAMES} fit; \
+                            use a larger decimation", medoid.len()));
+    }
+    let threshold = if examples.len() > 1 { spread * THRESHOLD_MARGIN } else { DEFAULT_THRESHOLD };
+    Ok(Template::from_frames(id, threshold, medoid))
+}
@@ -0,0 +1,466 @@
+//! Synchronization scores between wristbands, out of what they publish.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    f64::consts::PI,
+    fmt::Write as _,
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use motion::{
+    timesync::{Echo, Prober},
+    MovementDirection, Tempo,
+};
+use motion_protocol::{Frame, Payload};
+
+use crate::bus::{Bus, Message, Received};
+
+/// What a wristband reported, as decoded from one of its topics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Observation {
+    Direction(MovementDirection),
+    Tempo(Tempo),
+}
+
+/// Decodes `<id>/event` (a direction digit, in text or in a binary frame) and
+/// `<id>/tempo` (`bpm,phase,confidence`) messages, returning the device id
+/// and, when the message carries one, the device's timestamp in µs. The
+/// classes of `legacy_directions`, a letter or a class frame, tell no sense
+/// and are ignored.
+pub fn parse_message(message: &Message) -> Option<(&str, Option<u64>, Observation)> {
+    let (device, subtopic) = message.topic.split_once('/')?;
+    if subtopic == "event" && message.payload.first() == Some(&motion_protocol::VERSION) {
+        let frame = Frame::decode(&message.payload).ok()?;
+        let digit = match frame.payload {
+            Payload::Direction(digit) => digit,
+            Payload::Detection(detection) => detection.direction,
+            Payload::Class(_) | Payload::Orientation(_) => return None,
+        };
+        return Some((device, Some(frame.timestamp), Observation::Direction(MovementDirection::from_digit(digit)?)));
+    }
+    // Then the wall time stamp of `stamp_messages`, in ms, if any
+    let text = std::str::from_utf8(&message.payload).ok()?;
+    let (payload, stamp) = match text.split_once(';') {
+        Some((payload, stamp)) => (payload, Some(stamp.trim().parse::<u64>().ok()? * 1000)),
+        None => (text, None),
+    };
+    let observation = match subtopic {
+        "event" => {
+            // Either the digit alone or the fields of a detection event
+            let [digit] = payload.split(',').next()?.as_bytes() else {
+                return None;
+            };
+            Observation::Direction(MovementDirection::from_digit(digit.wrapping_sub(b'0'))?)
+        }
+        "tempo" => {
+            let mut fields = payload.split(',').map(|f| f.trim().parse::<f32>());
+            let (Some(Ok(bpm)), Some(Ok(phase)), Some(Ok(confidence))) = (fields.next(), fields.next(), fields.next())
+            else {
+                return None;
+            };
+            Observation::Tempo(Tempo { bpm, phase, confidence })
+        }
+        _ => return None,
+    };
+    Some((device, stamp, observation))
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncSettings {
+    /// Seconds of history the scores are computed over.
+    pub window: f64,
+    /// Rate at which the streams are resampled onto a common time grid, in Hz.
+    pub grid_rate: f64,
+    /// Seconds a reported direction stands for; the firmware publishes 8 per
+    /// second while the wrist moves.
+    pub hold: f64,
+    /// Largest lag looked for between two devices, in seconds.
+    pub max_lag: f64,
+    /// Seconds after which a device's tempo is considered gone.
+    pub tempo_timeout: f64,
+    /// Seconds between the clock probes sent to each device heard from, if
+    /// they are to be sent at all.
+    pub probe_period: Option<f64>,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            window: 10.0,
+            grid_rate: 8.0,
+            hold: 0.25,
+            max_lag: 1.0,
+            tempo_timeout: 2.0,
+            probe_period: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairScore {
+    pub a: String,
+    pub b: String,
+    /// Fraction of the time either moves in which both move the same way.
+    pub agreement: Option<f64>,
+    /// Seconds `b` follows `a` by, where their directions agree the most.
+    pub lag: Option<f64>,
+    /// Phase locking value of their tempos, in `[0, 1]`.
+    pub phase_lock: Option<f64>,
+}
+
+/// Scores averaged over the pairs a device (or the whole group) takes part in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupScore {
+    pub devices: usize,
+    pub agreement: Option<f64>,
+    /// Mean absolute lag.
+    pub lag: Option<f64>,
+    pub phase_lock: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scores {
+    pub pairs: Vec<PairScore>,
+    pub devices: BTreeMap<String, GroupScore>,
+    pub group: GroupScore,
+}
+
+#[derive(Default)]
+struct DeviceHistory {
+    directions: VecDeque<(f64, MovementDirection)>,
+    tempos: VecDeque<(f64, Tempo)>,
+}
+
+impl DeviceHistory {
+    fn direction_at(&self, t: f64, hold: f64) -> Option<MovementDirection> {
+        let (reported, direction) = self.directions.iter().rev().find(|(reported, _)| *reported <= t)?;
+        (t - reported < hold).then_some(*direction)
+    }
+
+    /// Phase in cycles, extrapolated from the latest tempo report.
+    fn phase_at(&self, t: f64, timeout: f64) -> Option<f64> {
+        let (reported, tempo) = self.tempos.iter().rev().find(|(reported, _)| *reported <= t)?;
+        (t - reported < timeout).then(|| tempo.phase as f64 + (t - reported) * tempo.bpm as f64 / 60.0)
+    }
+}
+
+/// Keeps the recent history of every device heard from and scores how well
+/// they move together.
+pub struct SyncScorer {
+    settings: SyncSettings,
+    devices: BTreeMap<String, DeviceHistory>,
+}
+
+impl SyncScorer {
+    pub fn new(settings: SyncSettings) -> Self {
+        Self { settings, devices: BTreeMap::new() }
+    }
+
+    pub fn observe(&mut self, t: f64, device: &str, observation: Observation) {
+        let history = self.devices.entry(device.to_string()).or_default();
+        match observation {
+            Observation::Direction(direction) => history.directions.push_back((t, direction)),
+            Observation::Tempo(tempo) => history.tempos.push_back((t, tempo)),
+        }
+    }
+
+    pub fn scores(&mut self, now: f64) -> Scores {
+        let keep = now - self.settings.window - self.settings.max_lag - self.settings.tempo_timeout;
+        for history in self.devices.values_mut() {
+            while history.directions.front().is_some_and(|(t, _)| *t < keep) {
+                history.directions.pop_front();
+            }
+            while history.tempos.front().is_some_and(|(t, _)| *t < keep) {
+                history.tempos.pop_front();
+            }
+        }
+        self.devices.retain(|_, h| !h.directions.is_empty() || !h.tempos.is_empty());
+
+        let names: Vec<&String> = self.devices.keys().collect();
+        let mut pairs = Vec::new();
+        for (i, a) in names.iter().enumerate() {
+            for b in &names[i + 1..] {
+                pairs.push(self.pair_score(now, a, b));
+            }
+        }
+        let devices = names.iter()
+            .map(|name| {
+                let involved: Vec<_> = pairs.iter().filter(|p| &p.a == *name || &p.b == *name).collect();
+                ((*name).clone(), summarize(&involved, involved.len() + 1))
+            })
+            .collect();
+        let group = summarize(&pairs.iter().collect::<Vec<_>>(), names.len());
+        Scores { pairs, devices, group }
+    }
+
+    fn pair_score(&self, now: f64, a: &str, b: &str) -> PairScore {
+        let s = &self.settings;
+        let (history_a, history_b) = (&self.devices[a], &self.devices[b]);
+        let steps = (s.window * s.grid_rate) as i64;
+        let max_shift = (s.max_lag * s.grid_rate) as i64;
+        let grid = |k: i64| now - s.window + k as f64 / s.grid_rate;
+
+        // Shifted `b` is looked at later, so the window ends `max_lag` early
+        let agreement_at = |shift: i64| {
+            let (mut active, mut agree) = (0, 0);
+            for k in 0..steps - max_shift {
+                let da = history_a.direction_at(grid(k), s.hold);
+                let db = history_b.direction_at(grid(k + shift), s.hold);
+                if da.is_some() || db.is_some() {
+                    active += 1;
+                    agree += (da.is_some() && da == db) as u32;
+                }
+            }
+            (active > 0).then(|| agree as f64 / active as f64)
+        };
+        let agreement = agreement_at(0);
+        let lag = (-max_shift..=max_shift)
+            .filter_map(|shift| Some((shift, agreement_at(shift)?)))
+            .filter(|(_, score)| *score > 0.0)
+            // Closest to no lag among the best
+            .min_by(|x, y| y.1.total_cmp(&x.1).then(x.0.abs().cmp(&y.0.abs())))
+            .map(|(shift, _)| shift as f64 / s.grid_rate);
+
+        let (mut re, mut im, mut count) = (0.0, 0.0, 0);
+        for k in 0..=steps {
+            let t = grid(k);
+            if let (Some(pa), Some(pb)) = (history_a.phase_at(t, s.tempo_timeout), history_b.phase_at(t, s.tempo_timeout)) {
+                let angle = 2.0 * PI * (pa - pb);
+                re += angle.cos();
+                im += angle.sin();
+                count += 1;
+            }
+        }
+        let phase_lock = (count > 0).then(|| re.hypot(im) / count as f64);
+
+        PairScore { a: a.to_string(), b: b.to_string(), agreement, lag, phase_lock }
+    }
+}
+
+fn summarize(pairs: &[&PairScore], devices: usize) -> GroupScore {
+    let mean = |values: Vec<f64>| (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64);
+    GroupScore {
+        devices,
+        agreement: mean(pairs.iter().filter_map(|p| p.agreement).collect()),
+        lag: mean(pairs.iter().filter_map(|p| p.lag.map(f64::abs)).collect()),
+        phase_lock: mean(pairs.iter().filter_map(|p| p.phase_lock).collect()),
+    }
+}
+
+fn json(fields: &[(&str, Option<f64>)]) -> String {
+    let mut out = String::from("{");
+    for (i, (name, value)) in fields.iter().enumerate() {
+        let separator = if i > 0 { "," } else { "" };
+        match value {
+            Some(value) => write!(out, "{separator}\"{name}\":{value:.3}").unwrap(),
+            None => write!(out, "{separator}\"{name}\":null").unwrap(),
+        }
+    }
+    out.push('}');
+    out
+}
+
+impl GroupScore {
+    pub fn to_json(&self) -> String {
+        json(&[
+            ("devices", Some(self.devices as f64)),
+            ("agreement", self.agreement),
+            ("lag", self.lag),
+            ("phase_lock", self.phase_lock),
+        ])
+    }
+}
+
+impl PairScore {
+    pub fn to_json(&self) -> String {
+        json(&[("agreement", self.agreement), ("lag", self.lag), ("phase_lock", self.phase_lock)])
+    }
+}
+
+impl Scores {
+    /// Messages publishing the scores: `<prefix>/group`, `<prefix>/<device>`
+    /// and `<prefix>/<a>/<b>` for every pair, as JSON objects.
+    pub fn messages(&self, prefix: &str) -> Vec<Message> {
+        let mut messages = vec![Message::new(format!("{prefix}/group"), self.group.to_json())];
+        for (device, score) in &self.devices {
+            messages.push(Message::new(format!("{prefix}/{device}"), score.to_json()));
+        }
+        for pair in &self.pairs {
+            messages.push(Message::new(format!("{prefix}/{}/{}", pair.a, pair.b), pair.to_json()));
+        }
+        messages
+    }
+}
+
+/// Maps bus time to µs since the Unix epoch, the aggregator's clock the
+/// devices are synchronized to.
+fn unix_micros(epoch: f64, now: f64) -> u64 {
+    ((epoch + now) * 1e6) as u64
+}
+
+/// Bus time of a device timestamp: wall time as it is, and time since boot
+/// through the device's clock as its probes measured it, once they have.
+fn bus_time(epoch: f64, timestamp: u64, prober: Option<&Prober>) -> Option<f64> {
+    let wall = if motion_protocol::is_wall_time(timestamp) {
+        timestamp
+    } else {
+        prober?.clock().wall_time(timestamp)?
+    };
+    Some(wall as f64 / 1e6 - epoch)
+}
+
+/// Feeds everything heard on the bus to a [`SyncScorer`] and publishes the
+/// scores every `period` seconds, until the bus closes.
+pub fn run(bus: &mut impl Bus, settings: SyncSettings, prefix: &str, period: f64) -> io::Result<()> {
+    let epoch = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()) - bus.now();
+    let probe_period = settings.probe_period;
+    let mut scorer = SyncScorer::new(settings);
+    let mut probers: BTreeMap<String, Prober> = BTreeMap::new();
+    let mut next_report = bus.now() + period;
+    let mut next_probe = probe_period.map(|p| bus.now() + p);
+    loop {
+        let next = next_probe.map_or(next_report, |t| t.min(next_report));
+        match bus.receive((next - bus.now()).max(0.0))? {
+            Received::Message(message) => {
+                if let Some(device) = message.topic.strip_suffix("/timesync/echo") {
+                    let echo = std::str::from_utf8(&message.payload).ok().and_then(Echo::parse);
+                    if let (Some(prober), Some(echo)) = (probers.get_mut(device), echo) {
+                        if let Some(sample) = prober.receive(&echo, unix_micros(epoch, bus.now())) {
+                            eprintln!("{device}: clock offset {:.1} ms, round trip {:.1} ms, drift {:.1} ppm",
+                                      sample.offset as f64 / 1e3, sample.delay as f64 / 1e3,
+                                      prober.clock().drift_ppm());
+                        }
+                    }
+                } else if let Some((device, timestamp, observation)) = parse_message(&message) {
+                    if next_probe.is_some() && !probers.contains_key(device) {
+                        probers.insert(device.to_string(), Prober::new());
+                    }
+                    // When it happened rather than when it got here, if known
+                    let t = timestamp.and_then(|ts| bus_time(epoch, ts, probers.get(device))).unwrap_or(bus.now());
+                    scorer.observe(t, device, observation);
+                }
+            }
+            Received::Idle => {}
+            Received::Closed => return Ok(()),
+        }
+        if bus.now() >= next_report {
+            for message in scorer.scores(bus.now()).messages(prefix) {
+                bus.publish(message)?;
+            }
+            next_report += period;
+        }
+        if let (Some(t), Some(probe_period)) = (next_probe.as_mut(), probe_period) {
+            if bus.now() >= *t {
+                let now = unix_micros(epoch, bus.now());
+                for (device, prober) in &mut probers {
+                    bus.publish(Message::new(format!("{device}/timesync"), prober.probe(now).to_string()))?;
+                }
+                *t += probe_period;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse_messages() {
+    let event = Message::new("imu3/event", "9");
+    let tempo = Message::new("imu3/tempo", "120.5,0.25,0.90");
+
+    assert_eq!(parse_message(&event), Some(("imu3", None, Observation::Direction(MovementDirection::UpRight))));
+    assert_eq!(parse_message(&tempo),
+               Some(("imu3", None, Observation::Tempo(Tempo { bpm: 120.5, phase: 0.25, confidence: 0.9 }))));
+    assert_eq!(parse_message(&Message::new("imu3/event", "8,0.52,11.4,73012,1250")),
+               Some(("imu3", None, Observation::Direction(MovementDirection::Up))));
+    assert_eq!(parse_message(&Message::new("imu3/event", "7;1739999999123")),
+               Some(("imu3", Some(1_739_999_999_123_000), Observation::Direction(MovementDirection::UpLeft))));
+    assert_eq!(parse_message(&Message::new("imu3/event", "7;soon")), None);
+    assert_eq!(parse_message(&Message::new("imu3/event", "x")), None);
+
+    let mut frame = [0; motion_protocol::MAX_FRAME_SIZE];
+    let len = motion_protocol::Encoder::new().encode(1_000, Payload::Direction(4), &mut frame).unwrap();
+    assert_eq!(parse_message(&Message::new("imu3/event", frame[..len].to_vec())),
+               Some(("imu3", Some(1_000), Observation::Direction(MovementDirection::Left))));
+    assert_eq!(parse_message(&Message::new("imu3/report", "low-batt")), None);
+}
+
+#[test]
+fn test_legacy_classes_are_ignored() {
+    let mut class = [0; motion_protocol::MAX_FRAME_SIZE];
+    let len = motion_protocol::Encoder::new().encode(1_000, Payload::Class(1), &mut class).unwrap();
+    let mut bus = crate::bus::RecordedBus::new((0..16)
+        .flat_map(|i| {
+            let t = i as f64 / 8.0;
+            [
+                (t, Message::new("imu3/event", "8")),
+                (t, Message::new("imu4/event", ["V", "H", "D"][i % 3])),
+                (t, Message::new("imu5/event", class[..len].to_vec())),
+            ]
+        })
+        .collect());
+    run(&mut bus, SyncSettings::default(), "sync", 1.0).unwrap();
+
+    assert!(bus.published.iter().any(|(_, m)| m.topic == "sync/imu3"));
+    assert!(bus.published.iter().all(|(_, m)| m.topic != "sync/imu4" && m.topic != "sync/imu5"));
+}
+
+#[test]
+fn test_lagged_pair_scores() {
+    let mut scorer = SyncScorer::new(SyncSettings::default());
+    let directions = [MovementDirection::Up, MovementDirection::Down];
+    // `b` repeats what `a` does half a second later; `c` does something else
+    for i in 0..80 {
+        let t = i as f64 / 8.0;
+        let direction = directions[(i / 8) % 2];
+        scorer.observe(t, "a", Observation::Direction(direction));
+        scorer.observe(t + 0.5, "b", Observation::Direction(direction));
+        scorer.observe(t, "c", Observation::Direction(MovementDirection::Left));
+    }
+    let scores = scorer.scores(10.5);
+
+    let ab = &scores.pairs[0];
+    assert_eq!((ab.a.as_str(), ab.b.as_str()), ("a", "b"));
+    assert!(ab.agreement.unwrap() < 0.6, "{ab:?}");
+    assert_eq!(ab.lag, Some(0.5));
+    let ac = &scores.pairs[1];
+    assert_eq!(ac.agreement, Some(0.0));
+    assert_eq!(ac.lag, None);
+    assert_eq!(scores.group.devices, 3);
+    assert_eq!(scores.devices["c"].agreement, Some(0.0));
+}
+
+#[test]
+fn test_device_times() {
+    let epoch = 1_700_000_000.0;
+    assert_eq!(bus_time(epoch, 1_700_000_002_000_000, None), Some(2.0));
+    assert_eq!(bus_time(epoch, 6_000_000, None), None);
+
+    // Echoed 5 s after the device booted, 300 µs after the probe left
+    let mut prober = Prober::new();
+    let probe = prober.probe(1_700_000_000_000_000);
+    assert_eq!(bus_time(epoch, 6_000_000, Some(&prober)), None);
+    let echo = Echo { seq: probe.seq, probe_sent: probe.sent, received: 5_000_000, sent: 5_000_100 };
+    prober.receive(&echo, 1_700_000_000_000_300).unwrap();
+    let t = bus_time(epoch, 6_000_050, Some(&prober)).unwrap();
+    assert!((t - 1.000_15).abs() < 1e-6, "{t}");
+}
+
+#[test]
+fn test_clock_probes() {
+    let settings = SyncSettings { probe_period: Some(1.0), ..SyncSettings::default() };
+    let mut bus = crate::bus::RecordedBus::new(vec![
+        (0.5, Message::new("imu3/event", "8")),
+        (3.5, Message::new("imu4/event", "2")),
+    ]);
+    run(&mut bus, settings, "sync", 10.0).unwrap();
+
+    let probes: Vec<_> = bus.published.iter()
+        .filter(|(_, m)| m.topic == "imu3/timesync")
+        .map(|(t, m)| (*t, motion::timesync::Probe::parse(std::str::from_utf8(&m.payload).unwrap()).unwrap()))
+        .collect();
+    assert_eq!(probes.iter().map(|(t, p)| (*t, p.seq)).collect::<Vec<_>>(), [(1.0, 1), (2.0, 2), (3.0, 3)]);
+    // Never echoed, so none tells of a previous exchange
+    assert!(probes.iter().all(|(_, p)| p.previous.is_none()));
+    assert_eq!(probes[1].1.sent - probes[0].1.sent, 1_000_000);
+    assert!(bus.published.iter().all(|(_, m)| m.topic != "imu4/timesync"));
+}
@@ -0,0 +1,309 @@
+use std::f64::consts::PI;
+
+use crate::trace::Sample;
+
+/// Full scale of the accelerometer, as configured with `AccRange::Gs8`.
+pub const ACC_RANGE_G: f32 = 8.0;
+/// Full scale of the gyroscope, as configured with `GyrRange::Dps1000`.
+pub const GYR_RANGE_DPS: f32 = 1000.0;
+
+/// A scripted movement of the wrist.
+///
+/// Directions are given in the earth frame used by the tracker (NWU): a
+/// heading of 0° points north (X), 90° points west (Y). Oscillating motions
+/// follow `amplitude_g * sin(2π·freq_hz·τ)`, τ being the time since the start
+/// of the segment, so they should last a whole number of cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Rest,
+    HorizontalSweep { amplitude_g: f32, freq_hz: f32, heading_deg: f32 },
+    VerticalPump { amplitude_g: f32, freq_hz: f32 },
+    /// Stroke along a line inclined `elevation_deg` above the horizontal.
+    DiagonalStroke { amplitude_g: f32, freq_hz: f32, heading_deg: f32, elevation_deg: f32 },
+    /// Turn in place at a constant rate around an axis of the sensor frame.
+    Rotation { axis: [f32; 3], rate_dps: f32 },
+}
+
+/// Where a [`Motion`] sits within a generated trace, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub motion: Motion,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Segment {
+    pub fn contains(&self, t: f64) -> bool {
+        self.start <= t && t < self.end
+    }
+}
+
+/// A sequence of motions, each with its duration in seconds.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<(Motion, f64)>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, motion: Motion, duration: f64) -> Self {
+        self.steps.push((motion, duration));
+        self
+    }
+
+    pub fn rest(self, duration: f64) -> Self {
+        self.then(Motion::Rest, duration)
+    }
+
+    pub fn horizontal_sweep(self, duration: f64, amplitude_g: f32, freq_hz: f32) -> Self {
+        self.then(Motion::HorizontalSweep { amplitude_g, freq_hz, heading_deg: 0.0 }, duration)
+    }
+
+    pub fn vertical_pump(self, duration: f64, amplitude_g: f32, freq_hz: f32) -> Self {
+        self.then(Motion::VerticalPump { amplitude_g, freq_hz }, duration)
+    }
+
+    pub fn diagonal_stroke(self, duration: f64, amplitude_g: f32, freq_hz: f32) -> Self {
+        let stroke = Motion::DiagonalStroke { amplitude_g, freq_hz, heading_deg: 0.0, elevation_deg: 45.0 };
+        self.then(stroke, duration)
+    }
+
+    pub fn rotation(self, duration: f64, axis: [f32; 3], rate_dps: f32) -> Self {
+        self.then(Motion::Rotation { axis, rate_dps }, duration)
+    }
+}
+
+/// Imperfections and environment of the simulated IMU.
+#[derive(Debug, Clone)]
+pub struct SensorModel {
+    pub rate: u32,
+    /// Constant gyroscope bias, degrees/s.
+    pub gyr_bias: [f32; 3],
+    /// Standard deviation of the white noise on each axis.
+    pub acc_noise_g: f32,
+    pub gyr_noise_dps: f32,
+    pub mag_noise_ut: f32,
+    /// Geomagnetic field in the earth frame (NWU), µT.
+    pub earth_field_ut: [f32; 3],
+    pub acc_range_g: f32,
+    pub gyr_range_dps: f32,
+    pub seed: u64,
+}
+
+impl Default for SensorModel {
+    fn default() -> Self {
+        Self {
+            rate: 200,
+            gyr_bias: [0.4, -0.3, 0.2],
+            acc_noise_g: 0.01,
+            gyr_noise_dps: 0.1,
+            mag_noise_ut: 0.5,
+            earth_field_ut: [20.0, 0.0, -40.0],
+            acc_range_g: ACC_RANGE_G,
+            gyr_range_dps: GYR_RANGE_DPS,
+            seed: 0x5EED,
+        }
+    }
+}
+
+pub struct SyntheticTrace {
+    pub samples: Vec<Sample>,
+    pub segments: Vec<Segment>,
+}
+
+/// Simulates the IMU of a wristband following `script`.
+///
+/// The sensor starts aligned with the earth frame. Its attitude is integrated
+/// from the true angular rate, and every reading is derived from that attitude:
+/// the accelerometer sees the specific force (motion plus 1 g upwards), the
+/// magnetometer the rotated geomagnetic field, reported with the ICM-20948's
+/// Y/Z axes flipped as the driver delivers it.
+pub fn generate(script: &Script, model: &SensorModel) -> SyntheticTrace {
+    let dt = 1.0 / model.rate as f64;
+    let mut rng = Rng::new(model.seed);
+    let mut attitude = Quat::IDENTITY;
+    let mut samples = Vec::new();
+    let mut segments = Vec::new();
+    let mut start = 0.0;
+    let mut n = 0usize;
+
+    for &(motion, duration) in &script.steps {
+        let end = start + duration;
+        segments.push(Segment { motion, start, end });
+
+        loop {
+            let t = n as f64 * dt;
+            if t >= end {
+                break;
+            }
+            let tau = t - start;
+            let accel = motion_accel(motion, tau);
+            let rate = motion_rate(motion);
+
+            let specific_force = attitude.conj().rotate([accel[0], accel[1], accel[2] + 1.0]);
+            let field = attitude.conj().rotate(model.earth_field_ut.map(f64::from));
+
+            let acc: [f32; 3] = std::array::from_fn(|i| {
+                let v = specific_force[i] as f32 + model.acc_noise_g * rng.gaussian();
+                v.clamp(-model.acc_range_g, model.acc_range_g)
+            });
+            let gyr: [f32; 3] = std::array::from_fn(|i| {
+                let v = rate[i].to_degrees() as f32 + model.gyr_bias[i] + model.gyr_noise_dps * rng.gaussian();
+                v.clamp(-model.gyr_range_dps, model.gyr_range_dps)
+            });
+            let mag: [f32; 3] = std::array::from_fn(|i| field[i] as f32 + model.mag_noise_ut * rng.gaussian());
+
+            samples.push(Sample { t, acc, gyr, mag: [mag[0], -mag[1], -mag[2]] });
+
+            attitude = attitude.integrate(rate, dt);
+            n += 1;
+        }
+        start = end;
+    }
+
+    SyntheticTrace { samples, segments }
+}
+
+/// Acceleration of the wrist in the earth frame, in g.
+fn motion_accel(motion: Motion, tau: f64) -> [f64; 3] {
+    let wave = |amplitude_g: f32, freq_hz: f32| amplitude_g as f64 * (2.0 * PI * freq_hz as f64 * tau).sin();
+    let along = |heading_deg: f32, elevation_deg: f32, a: f64| {
+        let (heading, elevation) = ((heading_deg as f64).to_radians(), (elevation_deg as f64).to_radians());
+        [a * elevation.cos() * heading.cos(), a * elevation.cos() * heading.sin(), a * elevation.sin()]
+    };
+
+    match motion {
+        Motion::Rest | Motion::Rotation { .. } => [0.0; 3],
+        Motion::HorizontalSweep { amplitude_g, freq_hz, heading_deg } => {
+            along(heading_deg, 0.0, wave(amplitude_g, freq_hz))
+        }
+        Motion::VerticalPump { amplitude_g, freq_hz } => along(0.0, 90.0, wave(amplitude_g, freq_hz)),
+        Motion::DiagonalStroke { amplitude_g, freq_hz, heading_deg, elevation_deg } => {
+            along(heading_deg, elevation_deg, wave(amplitude_g, freq_hz))
+        }
+    }
+}
+
+/// Angular rate in the sensor frame, in rad/s.
+fn motion_rate(motion: Motion) -> [f64; 3] {
+    match motion {
+        Motion::Rotation { axis, rate_dps } => {
+            let norm = axis.iter().map(|a| (*a as f64).powi(2)).sum::<f64>().sqrt();
+            let rate = (rate_dps as f64).to_radians();
+            axis.map(|a| a as f64 / norm * rate)
+        }
+        _ => [0.0; 3],
+    }
+}
+
+/// Sensor-to-earth rotation, kept in double precision to keep the simulated
+/// attitude free of integration error.
+#[derive(Debug, Clone, Copy)]
+struct Quat {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quat {
+    const IDENTITY: Self = Self { w: 1.0, x: 0.0, y: 0.0, z: 0.0 };
+
+    fn conj(self) -> Self {
+        Self { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    fn mul(self, o: Self) -> Self {
+        Self {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+
+    fn rotate(self, v: [f64; 3]) -> [f64; 3] {
+        let r = self.mul(Self { w: 0.0, x: v[0], y: v[1], z: v[2] }).mul(self.conj());
+        [r.x, r.y, r.z]
+    }
+
+    /// Advances by a body-frame angular rate (rad/s) held during `dt`.
+    fn integrate(self, rate: [f64; 3], dt: f64) -> Self {
+        let norm = (rate[0] * rate[0] + rate[1] * rate[1] + rate[2] * rate[2]).sqrt();
+        if norm == 0.0 {
+            return self;
+        }
+        let half = 0.5 * norm * dt;
+        let s = half.sin() / norm;
+        self.mul(Self { w: half.cos(), x: rate[0] * s, y: rate[1] * s, z: rate[2] * s })
+    }
+}
+
+/// Small deterministic generator (xorshift64*), so traces are reproducible
+/// from the model's seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal deviate, via Box-Muller.
+    fn gaussian(&mut self) -> f32 {
+        let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+        let u2 = self.next_f64();
+        ((-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()) as f32
+    }
+}
+
+#[test]
+fn test_rest_reads_gravity_and_field() {
+    let model = SensorModel { acc_noise_g: 0.0, gyr_noise_dps: 0.0, mag_noise_ut: 0.0, ..Default::default() };
+    let trace = generate(&Script::new().rest(1.0), &model);
+
+    assert_eq!(trace.samples.len(), 200);
+    let s = trace.samples[100];
+    assert_eq!(s.acc, [0.0, 0.0, 1.0]);
+    assert_eq!(s.gyr, model.gyr_bias);
+    // The driver reports the magnetometer with Y and Z flipped
+    assert_eq!(s.mag, [20.0, 0.0, 40.0]);
+}
+
+#[test]
+fn test_readings_saturate_at_full_scale() {
+    let model = SensorModel { acc_noise_g: 0.0, gyr_noise_dps: 0.0, ..Default::default() };
+    let script = Script::new()
+        .vertical_pump(0.5, 12.0, 2.0)
+        .rotation(0.5, [0.0, 0.0, 1.0], 1500.0);
+    let trace = generate(&script, &model);
+
+    let max_acc = trace.samples.iter().map(|s| s.acc[2]).fold(f32::MIN, f32::max);
+    let max_gyr = trace.samples.iter().map(|s| s.gyr[2]).fold(f32::MIN, f32::max);
+    assert_eq!(max_acc, ACC_RANGE_G);
+    assert_eq!(max_gyr, GYR_RANGE_DPS);
+}
+
+#[test]
+fn test_quarter_turn_moves_field_to_other_axis() {
+    let model = SensorModel {
+        gyr_bias: [0.0; 3], acc_noise_g: 0.0, gyr_noise_dps: 0.0, mag_noise_ut: 0.0, ..Default::default()
+    };
+    // 90° about Z in one second
+    let trace = generate(&Script::new().rotation(1.0, [0.0, 0.0, 1.0], 90.0).rest(0.1), &model);
+
+    let last = trace.samples.last().unwrap();
+    // North now lies along -Y of the sensor, reported by the driver as +Y
+    assert!(last.mag[0].abs() < 0.2);
+    assert!((last.mag[1] - 20.0).abs() < 0.2);
+    assert!((last.acc[2] - 1.0).abs() < 1e-4);
+}
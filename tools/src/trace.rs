@@ -0,0 +1,208 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use imu_fusion::FusionVector;
+
+/// Leading bytes of a binary trace file.
+pub const BINARY_MAGIC: &[u8; 8] = b"IMUTRC1\0";
+
+/// Header line of a CSV trace file.
+pub const CSV_HEADER: &str = "t,ax,ay,az,gx,gy,gz,mx,my,mz";
+
+/// One timestamped 9-DoF reading, as delivered by the IMU driver.
+///
+/// Units follow the IMU configuration in the firmware: seconds, g, degrees/s
+/// and µT. The magnetometer is stored raw, i.e. before the axis flip done by
+/// [`motion::imu_tracker::align_magnetometer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub t: f64,
+    pub acc: [f32; 3],
+    pub gyr: [f32; 3],
+    pub mag: [f32; 3],
+}
+
+impl Sample {
+    pub fn acc(&self) -> FusionVector {
+        FusionVector::new(self.acc[0], self.acc[1], self.acc[2])
+    }
+
+    pub fn gyr(&self) -> FusionVector {
+        FusionVector::new(self.gyr[0], self.gyr[1], self.gyr[2])
+    }
+
+    pub fn mag(&self) -> FusionVector {
+        FusionVector::new(self.mag[0], self.mag[1], self.mag[2])
+    }
+
+    fn values(&self) -> impl Iterator<Item = f32> + '_ {
+        self.acc.iter().chain(self.gyr.iter()).chain(self.mag.iter()).copied()
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    /// A CSV line could not be parsed; `line` is 1-based.
+    Parse { line: usize, reason: String },
+    Truncated,
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::Truncated => write!(f, "binary trace ends in the middle of a record"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Reads a trace file, telling CSV and binary apart by the magic bytes.
+pub fn read_file(path: impl AsRef<Path>) -> Result<Vec<Sample>, TraceError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+        read_binary(reader)
+    } else {
+        read_csv(reader)
+    }
+}
+
+/// Parses a CSV trace. Blank lines, `#` comments and a header line starting
+/// with a non-numeric field are skipped.
+pub fn read_csv(reader: impl BufRead) -> Result<Vec<Sample>, TraceError> {
+    let mut samples = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if idx == 0 && fields[0].parse::<f64>().is_err() {
+            continue;
+        }
+        samples.push(parse_csv_fields(&fields).map_err(|reason| TraceError::Parse {
+            line: idx + 1,
+            reason,
+        })?);
+    }
+    Ok(samples)
+}
+
+fn parse_csv_fields(fields: &[&str]) -> Result<Sample, String> {
+    if fields.len() != 10 {
+        return Err(format!("expected 10 fields, found {}", fields.len()));
+    }
+    let t = fields[0]
+        .parse::<f64>()
+        .map_err(|e| format!("bad timestamp '{}': {e}", fields[0]))?;
+    let mut values = [0f32; 9];
+    for (value, field) in values.iter_mut().zip(&fields[1..]) {
+        *value = field
+            .parse::<f32>()
+            .map_err(|e| format!("bad value '{field}': {e}"))?;
+    }
+    Ok(Sample {
+        t,
+        acc: [values[0], values[1], values[2]],
+        gyr: [values[3], values[4], values[5]],
+        mag: [values[6], values[7], values[8]],
+    })
+}
+
+/// Parses a binary trace: [`BINARY_MAGIC`] followed by records of a
+/// little-endian `f64` timestamp and nine little-endian `f32` values.
+pub fn read_binary(mut reader: impl Read) -> Result<Vec<Sample>, TraceError> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != BINARY_MAGIC {
+        return Err(TraceError::Parse { line: 0, reason: "not a binary trace".into() });
+    }
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    const RECORD_SIZE: usize = 8 + 9 * 4;
+    if bytes.len() % RECORD_SIZE != 0 {
+        return Err(TraceError::Truncated);
+    }
+
+    Ok(bytes
+        .chunks_exact(RECORD_SIZE)
+        .map(|record| {
+            let t = f64::from_le_bytes(record[..8].try_into().unwrap());
+            let mut values = [0f32; 9];
+            for (value, chunk) in values.iter_mut().zip(record[8..].chunks_exact(4)) {
+                *value = f32::from_le_bytes(chunk.try_into().unwrap());
+            }
+            Sample {
+                t,
+                acc: [values[0], values[1], values[2]],
+                gyr: [values[3], values[4], values[5]],
+                mag: [values[6], values[7], values[8]],
+            }
+        })
+        .collect())
+}
+
+pub fn write_csv(writer: impl Write, samples: &[Sample]) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writeln!(writer, "{CSV_HEADER}")?;
+    for s in samples {
+        write!(writer, "{:.6}", s.t)?;
+        for v in s.values() {
+            write!(writer, ",{v}")?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+pub fn write_binary(writer: impl Write, samples: &[Sample]) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(BINARY_MAGIC)?;
+    for s in samples {
+        writer.write_all(&s.t.to_le_bytes())?;
+        for v in s.values() {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+#[test]
+fn test_csv_and_binary_round_trip() {
+    let samples = vec![
+        Sample { t: 0.0, acc: [0.0, 0.0, 1.0], gyr: [0.5, -0.5, 0.0], mag: [20.0, -3.0, 40.0] },
+        Sample { t: 0.005, acc: [0.1, 0.0, 0.98], gyr: [1.0, 0.0, -2.0], mag: [21.0, -3.5, 39.5] },
+    ];
+
+    let mut csv = Vec::new();
+    write_csv(&mut csv, &samples).unwrap();
+    assert_eq!(read_csv(csv.as_slice()).unwrap(), samples);
+
+    let mut binary = Vec::new();
+    write_binary(&mut binary, &samples).unwrap();
+    assert_eq!(read_binary(binary.as_slice()).unwrap(), samples);
+}
+
+#[test]
+fn test_csv_reports_bad_line() {
+    let csv = "t,ax,ay,az,gx,gy,gz,mx,my,mz\n0,0,0,1,0,0,0,0,0,0\n0.005,0,0,1,0,0\n";
+    match read_csv(csv.as_bytes()) {
+        Err(TraceError::Parse { line, .. }) => assert_eq!(line, 3),
+        other => panic!("unexpected {other:?}"),
+    }
+}
@@ -0,0 +1,59 @@
+//! Every orientation estimator must get the tilt and heading of the
+//! synthetic wrist back after its movements, and agree with the others.
+
+use motion::{AhrsConfig, EstimatorKind};
+use motion_tools::{
+    estimators::compare,
+    pipeline::PipelineSettings,
+    synth::{self, Script, SensorModel},
+};
+
+fn wrist_script() -> Script {
+    Script::new()
+        .rest(3.0)
+        .rotation(1.0, [0.0, 0.0, 1.0], 90.0)
+        .rest(3.0)
+        .horizontal_sweep(2.0, 1.0, 2.0)
+        .rest(3.0)
+        .rotation(1.0, [1.0, 0.0, 0.0], 40.0)
+        .rest(3.0)
+        .vertical_pump(2.0, 1.0, 2.0)
+        .rest(3.0)
+        .rotation(1.0, [1.0, 0.0, 0.0], -40.0)
+        .rest(3.0)
+}
+
+#[test]
+fn test_estimators_agree() {
+    let trace = synth::generate(&wrist_script(), &SensorModel::default());
+    let comparison = compare(&PipelineSettings::default(), &trace.samples);
+
+    assert!(comparison.rest_secs > 15.0, "{}", comparison.rest_secs);
+    assert_eq!(comparison.reports.len(), EstimatorKind::ALL.len());
+    for report in &comparison.reports {
+        assert!(report.tilt_error_deg < 1.5, "{report:?}");
+        assert!(report.heading_drift_deg_per_min < 10.0, "{report:?}");
+    }
+    assert_eq!(comparison.agreements.len(), 3);
+    for agreement in &comparison.agreements {
+        assert!(agreement.rms_deg < 3.0, "{agreement:?}");
+    }
+}
+
+#[test]
+fn test_gyroscope_drift() {
+    // Without the accelerometer and magnetometer, the gyroscope bias turns
+    // the heading by 12°/min, but for Fusion's own correction of the bias
+    let model = SensorModel { gyr_bias: [0.0, 0.0, 0.2], gyr_noise_dps: 0.0, ..SensorModel::default() };
+    let trace = synth::generate(&Script::new().rest(30.0), &model);
+    let settings = PipelineSettings { ahrs: AhrsConfig { gain: 0.0, ..AhrsConfig::default() }, ..PipelineSettings::default() };
+    let comparison = compare(&settings, &trace.samples);
+
+    for report in &comparison.reports {
+        assert!(report.tilt_error_deg < 0.1, "{report:?}");
+        match report.kind {
+            EstimatorKind::Fusion => assert!(report.heading_drift_deg_per_min < 6.0, "{report:?}"),
+            _ => assert!((report.heading_drift_deg_per_min - 12.0).abs() < 0.1, "{report:?}"),
+        }
+    }
+}
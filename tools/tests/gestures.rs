@@ -0,0 +1,75 @@
+//! Gestures performed by the synthetic IMU must be recognized from the
+//! directions the firmware's motion pipeline reports.
+
+use motion::{Gesture, GestureConfig, GestureRecognizer};
+use motion_tools::{
+    pipeline::{Pipeline, PipelineSettings},
+    synth::{self, Motion, Script, SensorModel},
+};
+
+fn recognize(script: &Script) -> Vec<Gesture> {
+    let trace = synth::generate(script, &SensorModel::default());
+    let directions = Pipeline::run(&PipelineSettings::default(), &trace.samples);
+    let mut recognizer = GestureRecognizer::new(GestureConfig::default(), 0.0);
+
+    trace.samples.iter()
+        .zip(directions)
+        .filter_map(|(s, d)| recognizer.update(s.t as f32, d))
+        .filter(|g| *g != Gesture::HoldStill)
+        .collect()
+}
+
+/// Strokes going back and forth along a line, one cycle of acceleration each.
+fn back_and_forth(mut script: Script, strokes: usize, stroke: impl Fn(f32) -> Motion) -> Script {
+    for i in 0..strokes {
+        let sense = if i % 2 == 0 { 1.0 } else { -1.0 };
+        script = script.then(stroke(sense), 0.4);
+    }
+    script
+}
+
+#[test]
+fn test_wave() {
+    // Whichever way the wearer faces
+    for heading_deg in [0.0, 90.0, 180.0, 270.0] {
+        let sweep = |sense| Motion::HorizontalSweep { amplitude_g: sense * 1.5, freq_hz: 2.5, heading_deg };
+        let script = back_and_forth(Script::new().rest(1.0), 4, sweep).rest(1.0);
+
+        assert_eq!(recognize(&script), [Gesture::Wave], "heading {heading_deg}");
+    }
+}
+
+#[test]
+fn test_pump() {
+    let pump = |sense| Motion::VerticalPump { amplitude_g: sense * 1.5, freq_hz: 2.5 };
+    let script = back_and_forth(Script::new().rest(1.0), 4, pump).rest(1.0);
+
+    assert_eq!(recognize(&script), [Gesture::Pump]);
+}
+
+#[test]
+fn test_cross() {
+    let stroke = |heading_deg| Motion::DiagonalStroke {
+        amplitude_g: -1.0, freq_hz: 2.0, heading_deg, elevation_deg: 45.0,
+    };
+    let script = Script::new()
+        .rest(1.0)
+        .then(stroke(90.0), 0.5)
+        .rest(0.3)
+        .then(stroke(270.0), 0.5)
+        .rest(1.0);
+
+    assert_eq!(recognize(&script), [Gesture::Cross]);
+}
+
+#[test]
+fn test_single_strokes_are_not_gestures() {
+    let script = Script::new()
+        .rest(1.0)
+        .then(Motion::VerticalPump { amplitude_g: 1.0, freq_hz: 2.0 }, 0.5)
+        .rest(1.5)
+        .then(Motion::HorizontalSweep { amplitude_g: 1.0, freq_hz: 2.0, heading_deg: 90.0 }, 0.5)
+        .rest(1.0);
+
+    assert!(recognize(&script).is_empty());
+}
@@ -0,0 +1,223 @@
+//! Golden tests: scripted motions from the synthetic IMU must come out of the
+//! firmware's motion pipeline as the expected directions, in the expected
+//! time windows.
+
+use motion::{
+    AnalysisConfig, DenoiserKind,
+    MovementClass::{self, Diagonal, Horizontal, Vertical},
+    MovementDirection,
+};
+use motion_tools::{
+    pipeline::{Pipeline, PipelineSettings},
+    synth::{self, Motion, Script, SensorModel, SyntheticTrace},
+};
+
+/// Time allowed for the smoothing and detection windows to fill up or drain
+/// after a change of motion.
+const SETTLE_S: f64 = 0.5;
+
+fn run(script: &Script) -> (SyntheticTrace, Vec<Option<MovementDirection>>) {
+    let trace = synth::generate(script, &SensorModel::default());
+    let directions = Pipeline::run(&PipelineSettings::default(), &trace.samples);
+    (trace, directions)
+}
+
+/// Directions emitted during segment `index`, once settled.
+fn settled(trace: &SyntheticTrace, directions: &[Option<MovementDirection>], index: usize)
+    -> Vec<Option<MovementDirection>> {
+    let segment = trace.segments[index];
+    trace.samples.iter()
+        .zip(directions)
+        .filter(|(s, _)| segment.start + SETTLE_S <= s.t && s.t < segment.end)
+        .map(|(_, d)| *d)
+        .collect()
+}
+
+fn assert_detects(trace: &SyntheticTrace, directions: &[Option<MovementDirection>],
+                  index: usize, expected: MovementClass) {
+    let window = settled(trace, directions, index);
+    let detected: Vec<_> = window.iter().flatten().collect();
+    let matching = detected.iter().filter(|d| d.class() == expected).count();
+
+    assert!(detected.len() * 2 >= window.len(),
+            "segment {index}: only {} of {} samples detected movement", detected.len(), window.len());
+    assert!(matching * 10 >= detected.len() * 9,
+            "segment {index}: {matching} of {} detections are {expected:?}", detected.len());
+}
+
+fn assert_quiet(trace: &SyntheticTrace, directions: &[Option<MovementDirection>], index: usize) {
+    let window = settled(trace, directions, index);
+    let detected = window.iter().flatten().count();
+    assert_eq!(detected, 0, "segment {index}: {detected} detections while at rest");
+}
+
+#[test]
+fn test_session_of_strokes() {
+    let script = Script::new()
+        .rest(2.0)
+        .horizontal_sweep(3.0, 1.0, 2.0)
+        .rest(2.0)
+        .vertical_pump(3.0, 1.0, 2.0)
+        .rest(2.0)
+        .diagonal_stroke(3.0, 1.0, 2.0)
+        .rest(2.0);
+    let (trace, directions) = run(&script);
+
+    assert_quiet(&trace, &directions, 0);
+    assert_detects(&trace, &directions, 1, Horizontal);
+    assert_quiet(&trace, &directions, 2);
+    assert_detects(&trace, &directions, 3, Vertical);
+    assert_quiet(&trace, &directions, 4);
+    assert_detects(&trace, &directions, 5, Diagonal);
+    assert_quiet(&trace, &directions, 6);
+}
+
+#[test]
+fn test_sweep_direction_does_not_depend_on_heading() {
+    let sweep = Motion::HorizontalSweep { amplitude_g: 1.0, freq_hz: 2.0, heading_deg: 60.0 };
+    let (trace, directions) = run(&Script::new().rest(1.0).then(sweep, 3.0));
+
+    assert_detects(&trace, &directions, 1, Horizontal);
+}
+
+#[test]
+fn test_slow_strokes_stay_below_threshold() {
+    let (trace, directions) = run(&Script::new().rest(1.0).horizontal_sweep(3.0, 0.1, 1.0));
+
+    assert_quiet(&trace, &directions, 1);
+}
+
+#[test]
+fn test_turning_in_place_is_not_a_stroke() {
+    let script = Script::new()
+        .rest(1.0)
+        .rotation(2.0, [0.0, 0.0, 1.0], 90.0)
+        .rotation(2.0, [1.0, 0.0, 0.0], 45.0)
+        .rest(1.0);
+    let (trace, directions) = run(&script);
+
+    assert_quiet(&trace, &directions, 1);
+    assert_quiet(&trace, &directions, 2);
+    assert_quiet(&trace, &directions, 3);
+}
+
+/// Runs a single stroke (one cycle of acceleration, so the wrist ends up at
+/// rest away from where it started) and checks that everything detected
+/// around it points in `expected`.
+fn assert_stroke(stroke: Motion, expected: MovementDirection) {
+    let (_, directions) = run(&Script::new().rest(1.0).then(stroke, 0.5).rest(1.0));
+    let detected: Vec<_> = directions.iter().flatten().collect();
+
+    assert!(detected.len() > 20, "{stroke:?}: only {} detections", detected.len());
+    assert!(detected.iter().all(|d| **d == expected), "{stroke:?}: expected {expected:?}, got {detected:?}");
+}
+
+#[test]
+fn test_vertical_strokes_are_signed() {
+    assert_stroke(Motion::VerticalPump { amplitude_g: 1.0, freq_hz: 2.0 }, MovementDirection::Up);
+    assert_stroke(Motion::VerticalPump { amplitude_g: -1.0, freq_hz: 2.0 }, MovementDirection::Down);
+}
+
+#[test]
+fn test_horizontal_strokes_follow_heading() {
+    let sweep = |heading_deg| Motion::HorizontalSweep { amplitude_g: 1.0, freq_hz: 2.0, heading_deg };
+
+    assert_stroke(sweep(0.0), MovementDirection::Forward);
+    assert_stroke(sweep(90.0), MovementDirection::Left);
+    assert_stroke(sweep(180.0), MovementDirection::Back);
+    assert_stroke(sweep(270.0), MovementDirection::Right);
+}
+
+#[test]
+fn test_diagonal_strokes_are_quadrants() {
+    let stroke = |amplitude_g, heading_deg| {
+        Motion::DiagonalStroke { amplitude_g, freq_hz: 2.0, heading_deg, elevation_deg: 45.0 }
+    };
+
+    assert_stroke(stroke(1.0, 90.0), MovementDirection::UpLeft);
+    assert_stroke(stroke(1.0, 270.0), MovementDirection::UpRight);
+    assert_stroke(stroke(-1.0, 270.0), MovementDirection::DownLeft);
+    assert_stroke(stroke(-1.0, 90.0), MovementDirection::DownRight);
+    // Forward counts as left, and back as right
+    assert_stroke(stroke(1.0, 0.0), MovementDirection::UpLeft);
+    assert_stroke(stroke(1.0, 180.0), MovementDirection::UpRight);
+    assert_stroke(stroke(-1.0, 180.0), MovementDirection::DownLeft);
+    assert_stroke(stroke(-1.0, 0.0), MovementDirection::DownRight);
+}
+
+#[test]
+fn test_reference_heading_turns_the_frame() {
+    let settings = PipelineSettings { reference_heading_deg: 90.0, ..Default::default() };
+    let sweep = Motion::HorizontalSweep { amplitude_g: 1.0, freq_hz: 2.0, heading_deg: 90.0 };
+    let trace = synth::generate(&Script::new().rest(1.0).then(sweep, 0.5).rest(1.0), &SensorModel::default());
+    let directions = Pipeline::run(&settings, &trace.samples);
+
+    assert!(directions.iter().flatten().all(|d| *d == MovementDirection::Forward));
+}
+
+#[test]
+fn test_every_denoiser_tells_classes_apart() {
+    let script = Script::new()
+        .rest(2.0)
+        .horizontal_sweep(3.0, 1.0, 2.0)
+        .rest(2.0)
+        .vertical_pump(3.0, 1.0, 2.0)
+        .rest(2.0);
+    let trace = synth::generate(&script, &SensorModel::default());
+
+    for denoiser in DenoiserKind::ALL {
+        let settings = PipelineSettings { analysis: AnalysisConfig { denoiser, ..Default::default() }, ..Default::default() };
+        let directions = Pipeline::run(&settings, &trace.samples);
+
+        assert_detects(&trace, &directions, 1, Horizontal);
+        assert_detects(&trace, &directions, 3, Vertical);
+        assert_quiet(&trace, &directions, 4);
+    }
+}
+
+#[test]
+fn test_highpass_smoothing() {
+    let script = Script::new()
+        .rest(2.0)
+        .horizontal_sweep(3.0, 1.0, 2.0)
+        .rest(2.0)
+        .vertical_pump(3.0, 1.0, 2.0)
+        .rest(2.0)
+        .diagonal_stroke(3.0, 1.0, 2.0)
+        .rest(2.0);
+    let trace = synth::generate(&script, &SensorModel::default());
+    let settings = PipelineSettings { highpass_hz: Some(1.0), ..Default::default() };
+    let directions = Pipeline::run(&settings, &trace.samples);
+
+    assert_quiet(&trace, &directions, 0);
+    assert_detects(&trace, &directions, 1, Horizontal);
+    assert_detects(&trace, &directions, 3, Vertical);
+    assert_detects(&trace, &directions, 5, Diagonal);
+    assert_quiet(&trace, &directions, 6);
+}
+
+/// Class changes among consecutive detections.
+fn class_changes(directions: &[Option<MovementDirection>]) -> usize {
+    let classes: Vec<_> = directions.iter().flatten().map(|d| d.class()).collect();
+    classes.windows(2).filter(|w| w[0] != w[1]).count()
+}
+
+#[test]
+fn test_borderline_stroke_does_not_chatter() {
+    // On the lower edge of the diagonal band
+    let stroke = Motion::DiagonalStroke { amplitude_g: 1.0, freq_hz: 2.0, heading_deg: 0.0, elevation_deg: 34.0 };
+    let trace = synth::generate(&Script::new().rest(1.0).then(stroke, 3.0).rest(1.0), &SensorModel::default());
+
+    let defaults = AnalysisConfig::default();
+    let analysis = AnalysisConfig {
+        stop_threshold_g: defaults.start_threshold_g,
+        angle_hysteresis_deg: 0.0,
+        min_dwell: 1,
+        ..defaults
+    };
+    let plain = PipelineSettings { analysis, ..Default::default() };
+    let plain_changes = class_changes(&Pipeline::run(&plain, &trace.samples));
+    let changes = class_changes(&Pipeline::run(&PipelineSettings::default(), &trace.samples));
+
+    assert!(changes * 2 <= plain_changes, "{changes} class changes, {plain_changes} without hysteresis");
+}
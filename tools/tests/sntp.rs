@@ -0,0 +1,71 @@
+//! The SNTP client logic of the firmware, against a local NTP stand-in over
+//! UDP whose clock is ahead of ours and runs fast.
+
+use std::{
+    net::UdpSocket,
+    thread,
+    time::{Duration, Instant},
+};
+
+use motion::{
+    clock::{ClockSample, WallClock},
+    sntp::{self, PACKET_SIZE},
+};
+
+const SERVER_EPOCH: u64 = 1_700_000_000_000_000;
+const SERVER_DRIFT: f64 = 400e-6;
+const POLLS: usize = 12;
+
+/// Answers `polls` requests, the first one with a kiss-o'-death.
+fn serve(socket: UdpSocket, start: Instant, polls: usize) {
+    let now = || SERVER_EPOCH + (start.elapsed().as_micros() as f64 * (1.0 + SERVER_DRIFT)) as u64;
+    let mut request = [0; PACKET_SIZE];
+    for i in 0..polls {
+        let (_, client) = socket.recv_from(&mut request).unwrap();
+        let receive = now();
+        let mut reply = [0; PACKET_SIZE];
+        // Version 4, server mode; stratum 0 is a kiss-o'-death
+        reply[0] = 0x24;
+        reply[1] = if i == 0 { 0 } else { 2 };
+        reply[24..32].copy_from_slice(&request[40..48]);
+        reply[32..40].copy_from_slice(&sntp::to_ntp_timestamp(receive).to_be_bytes());
+        reply[40..48].copy_from_slice(&sntp::to_ntp_timestamp(now()).to_be_bytes());
+        socket.send_to(&reply, client).unwrap();
+    }
+}
+
+#[test]
+fn test_discipline_against_ntp_stand_in() {
+    let start = Instant::now();
+    let local = || start.elapsed().as_micros() as u64;
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = server.local_addr().unwrap();
+    let stand_in = thread::spawn(move || serve(server, start, POLLS));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut clock = WallClock::new();
+    let mut refused = 0;
+    for _ in 0..POLLS {
+        let t1 = local();
+        socket.send_to(&sntp::request(t1), address).unwrap();
+        let mut reply = [0; PACKET_SIZE];
+        let len = socket.recv(&mut reply).unwrap();
+        let t4 = local();
+        match sntp::parse_response(&reply[..len], t1) {
+            Ok(response) => clock.add_sample(ClockSample::from_exchange(t1, response.receive, response.transmit, t4).unwrap()),
+            Err(sntp::Error::Unsynchronized) => refused += 1,
+            Err(e) => panic!("{e:?}"),
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+    stand_in.join().unwrap();
+    assert_eq!(refused, 1);
+
+    let now = local();
+    let truth = SERVER_EPOCH + (now as f64 * (1.0 + SERVER_DRIFT)) as u64;
+    let error = clock.wall_time(now).unwrap() as i64 - truth as i64;
+    assert!(error.abs() < 1_000, "{error} µs off");
+    assert!((clock.drift_ppm() - 400.0).abs() < 100.0, "{} ppm", clock.drift_ppm());
+}
@@ -0,0 +1,85 @@
+//! Wristbands simulated down to what they publish must be scored as moving
+//! together, with the right lag, when they do the same thing.
+
+use motion::{TempoConfig, TempoEstimator};
+use motion_protocol::{Detection, Encoder, Payload, MAX_FRAME_SIZE};
+use motion_tools::{
+    bus::{self, Message, RecordedBus},
+    pipeline::{Pipeline, PipelineSettings},
+    sync::{self, SyncSettings},
+    synth::{self, Script, SensorModel},
+};
+
+/// Messages a wristband running `script` publishes, as the firmware does:
+/// directions 8 times per second while moving, as digits or as binary
+/// detection frames, and the tempo 4 times per second.
+fn publish(device: &str, script: &Script, seed: u64, binary: bool) -> Vec<(f64, Message)> {
+    let trace = synth::generate(script, &SensorModel { seed, ..Default::default() });
+    let settings = PipelineSettings::default();
+    let mut pipeline = Pipeline::new(&settings, 0.0);
+    let mut tempo = TempoEstimator::new(TempoConfig::default(), settings.rate);
+
+    let mut encoder = Encoder::new();
+    let mut messages = Vec::new();
+    for (i, s) in trace.samples.iter().enumerate() {
+        let id = i + 1;
+        let event = pipeline.step(s);
+        tempo.add_measurement(pipeline.tracker.linear_accel);
+        if let (0, Some(event)) = (id % 25, event) {
+            let payload = if binary {
+                let detection = Detection {
+                    direction: event.direction.as_digit(),
+                    magnitude: event.magnitude,
+                    confidence: event.confidence,
+                    onset: (event.onset * 1e6) as u64,
+                    previous_duration: event.previous_duration,
+                };
+                let mut frame = [0; MAX_FRAME_SIZE];
+                let len = encoder.encode((s.t * 1e6) as u64, Payload::Detection(detection), &mut frame).unwrap();
+                frame[..len].to_vec()
+            } else {
+                vec![b'0' + event.direction.as_digit()]
+            };
+            messages.push((s.t, Message::new(format!("{device}/event"), payload)));
+        }
+        if let Some(t) = tempo.estimate().filter(|_| id % 50 == 0) {
+            let payload = format!("{:.1},{:.2},{:.2}", t.bpm, t.phase, t.confidence);
+            messages.push((s.t, Message::new(format!("{device}/tempo"), payload)));
+        }
+    }
+    messages
+}
+
+/// Value of a numeric field of the last JSON object published on `topic`.
+fn last_field(published: &[(f64, Message)], topic: &str, field: &str) -> Option<f64> {
+    let (_, message) = published.iter().rev().find(|(_, m)| m.topic == topic)?;
+    let json = String::from_utf8_lossy(&message.payload);
+    let start = json.find(&format!("\"{field}\":"))? + field.len() + 3;
+    json[start..].split([',', '}']).next()?.parse().ok()
+}
+
+#[test]
+fn test_group_moving_together() {
+    let pump = |lead_in| Script::new().rest(lead_in).vertical_pump(12.0, 1.0, 1.5).rest(0.5);
+    let mut messages = publish("a", &pump(1.0), 1, false);
+    messages.extend(publish("b", &pump(1.25), 2, true));
+    messages.extend(publish("c", &Script::new().rest(1.0).horizontal_sweep(12.0, 1.0, 2.5).rest(0.5), 3, false));
+
+    // Through the recording format, as the service would replay it
+    messages.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut recording = Vec::new();
+    for (t, message) in &messages {
+        bus::write_recorded(&mut recording, *t, message).unwrap();
+    }
+    let mut bus = RecordedBus::new(bus::read_recorded(recording.as_slice()).unwrap());
+    sync::run(&mut bus, SyncSettings::default(), "sync", 1.0).unwrap();
+
+    let ab = |field| last_field(&bus.published, "sync/a/b", field).unwrap();
+    let ac = |field| last_field(&bus.published, "sync/a/c", field).unwrap();
+    assert!((ab("lag") - 0.25).abs() <= 0.125, "lag {}", ab("lag"));
+    assert!(ab("phase_lock") > 0.8, "phase lock {}", ab("phase_lock"));
+    assert!(ac("phase_lock") < 0.5, "phase lock {}", ac("phase_lock"));
+    assert!(ac("agreement") < 0.1, "agreement {}", ac("agreement"));
+    assert_eq!(last_field(&bus.published, "sync/group", "devices"), Some(3.0));
+    assert!(last_field(&bus.published, "sync/c", "agreement").unwrap() < ab("agreement"));
+}
@@ -0,0 +1,68 @@
+//! Templates recorded from a few synthetic performances of a gesture must be
+//! found again in a longer trace, and not be confused with other movements.
+
+use motion::template::{decode_templates, encode_templates, TemplateMatcher};
+use motion_tools::{
+    pipeline::{Pipeline, PipelineSettings},
+    recording,
+    synth::{self, Motion, Script, SensorModel},
+};
+
+const DECIMATION: u8 = 4;
+
+/// Up then down, the second stroke a bit stronger.
+fn up_down(script: Script, scale: f32) -> Script {
+    script
+        .then(Motion::VerticalPump { amplitude_g: 1.2 * scale, freq_hz: 2.0 / scale }, 0.5 * scale as f64)
+        .then(Motion::VerticalPump { amplitude_g: -1.6 * scale, freq_hz: 2.0 / scale }, 0.5 * scale as f64)
+}
+
+fn record(id: u8, scripts: &[Script]) -> motion::template::Template {
+    let examples: Vec<_> = scripts.iter()
+        .enumerate()
+        .map(|(i, script)| {
+            let model = SensorModel { seed: 100 + i as u64, ..Default::default() };
+            let trace = synth::generate(script, &model);
+            recording::example_frames(&PipelineSettings::default(), DECIMATION, &trace.samples)
+        })
+        .collect();
+    recording::record_template(id, &examples).unwrap()
+}
+
+#[test]
+fn test_recorded_template_is_found() {
+    let template = record(5, &[
+        up_down(Script::new().rest(1.0), 1.0).rest(1.0),
+        up_down(Script::new().rest(1.0), 0.9).rest(1.0),
+        up_down(Script::new().rest(1.0), 1.1).rest(1.0),
+    ]);
+    let bytes = encode_templates(DECIMATION, &[template]);
+    let (_, decoded) = decode_templates(&bytes).unwrap();
+    assert_eq!(decoded[0].id, 5);
+
+    let mut matcher = TemplateMatcher::from_bytes(&bytes).unwrap();
+    let script = up_down(
+        Script::new()
+            .rest(1.0)
+            .horizontal_sweep(1.0, 1.0, 2.0)
+            .rest(1.0)
+            .diagonal_stroke(0.5, 1.0, 2.0)
+            .rest(1.0),
+        1.05,
+    ).rest(1.0);
+    let trace = synth::generate(&script, &SensorModel { seed: 7, ..Default::default() });
+
+    let mut pipeline = Pipeline::new(&PipelineSettings::default(), 0.0);
+    let matches: Vec<_> = trace.samples.iter()
+        .filter_map(|s| {
+            pipeline.step(s);
+            matcher.add_measurement(pipeline.tracker.linear_accel).map(|m| (s.t, m))
+        })
+        .collect();
+
+    assert_eq!(matches.len(), 1, "{matches:?}");
+    let (t, found) = matches[0];
+    assert_eq!(found.id, 5);
+    // Reported once the gesture (starting at 4.5 s, lasting 1.05 s) is over
+    assert!((5.5..6.5).contains(&t), "matched at {t}");
+}
@@ -0,0 +1,37 @@
+//! The tempo of repeated synthetic movements must be recovered from the
+//! linear acceleration of the firmware's tracker.
+
+use motion::{Tempo, TempoConfig, TempoEstimator};
+use motion_tools::{
+    pipeline::{Pipeline, PipelineSettings},
+    synth::{self, Script, SensorModel},
+};
+
+fn tempo_at_end(script: &Script) -> Option<Tempo> {
+    let trace = synth::generate(script, &SensorModel::default());
+    let settings = PipelineSettings::default();
+    let mut pipeline = Pipeline::new(&settings, 0.0);
+    let mut estimator = TempoEstimator::new(TempoConfig::default(), settings.rate);
+    for s in &trace.samples {
+        pipeline.step(s);
+        estimator.add_measurement(pipeline.tracker.linear_accel);
+    }
+    estimator.estimate()
+}
+
+#[test]
+fn test_pump_and_sweep_tempo() {
+    for (script, bpm) in [
+        (Script::new().rest(1.0).vertical_pump(6.0, 1.0, 1.5), 90.0),
+        (Script::new().rest(1.0).horizontal_sweep(6.0, 1.0, 2.5), 150.0),
+    ] {
+        let tempo = tempo_at_end(&script).unwrap();
+        assert!((tempo.bpm - bpm).abs() < 3.0, "expected {bpm} BPM, got {tempo:?}");
+        assert!(tempo.confidence > 0.8, "{tempo:?}");
+    }
+}
+
+#[test]
+fn test_no_tempo_at_rest() {
+    assert_eq!(tempo_at_end(&Script::new().rest(6.0)), None);
+}